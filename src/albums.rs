@@ -0,0 +1,393 @@
+// albums.rs
+// Google Photos album import for MetaSort_v1.0.0 – Google Photos Takeout Organizer
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::LazyLock;
+use chrono::{TimeZone, Utc};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+use crate::journal::{Journal, Stage};
+use crate::media_cleaning::{json_sidecar, separated_path};
+use crate::merge::same_contents;
+use crate::metadata_extraction::MediaMetadata;
+use crate::transfer::{leave_out, transfer_file, TransferMode};
+use crate::utils::log_to_file;
+
+/// Name of the album description file Takeout puts in every album folder.
+pub const ALBUM_METADATA_NAME: &str = "metadata.json";
+
+/// Year folders carry a `metadata.json` too, but are not albums.
+static YEAR_FOLDER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^Photos from \d{4}$").unwrap());
+
+/// How albums are recreated in the output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AlbumMode {
+    /// Only record album membership (metadata, reports)
+    #[default]
+    None,
+    /// A folder per album with hardlinks to the sorted files
+    Folders,
+    /// A folder per album with symbolic links to the sorted files
+    Symlinks,
+    /// An .m3u playlist per album listing the sorted files
+    Manifest,
+}
+
+/// A Google Photos album as described by its `metadata.json`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Album {
+    pub title: String,
+    pub description: Option<String>,
+    /// Album date in EXIF format (`YYYY:MM:DD HH:MM:SS`, UTC).
+    pub date: Option<String>,
+    /// The album folder in the Takeout.
+    pub folder: PathBuf,
+    /// Media in the album. A photo that also lives in a year folder points at
+    /// that copy, since the album copy is dropped.
+    pub members: Vec<PathBuf>,
+}
+
+/// Albums found in a Takeout, and the album copies of files that also live
+/// in a year folder as `(album copy, kept file)`. A copy has the same name and
+/// the same contents as the kept file.
+#[derive(Debug, Clone, Default)]
+pub struct AlbumScan {
+    pub albums: Vec<Album>,
+    pub copies: Vec<(PathBuf, PathBuf)>,
+}
+
+/// Finds albums among `files`, given as `(path, real path)` pairs: the path
+/// decides which folder a file belongs to, the real path is read from. For a
+/// plain folder both are the same; for a merged Takeout the path is the
+/// merged one (see [`crate::merge::VirtualTree`]). `same` tells whether two
+/// real paths have the same contents.
+pub fn scan_albums<'a>(files: impl IntoIterator<Item = (&'a Path, &'a Path)>, same: impl Fn(&Path, &Path) -> bool) -> AlbumScan {
+    let files: Vec<(&Path, &Path)> = files.into_iter().collect();
+    let mut albums: HashMap<&Path, Album> = HashMap::new();
+    for (path, real) in &files {
+        let Some(folder) = path.parent() else { continue };
+        let is_year_folder = folder.file_name().is_some_and(|n| YEAR_FOLDER.is_match(&n.to_string_lossy()));
+        if path.file_name().is_some_and(|n| n == ALBUM_METADATA_NAME) && !is_year_folder {
+            if let Some(album) = read_album(real, folder) {
+                albums.insert(folder, album);
+            }
+        }
+    }
+
+    // Files outside albums, by name, to find the original of an album copy
+    let is_media = |path: &Path| path.extension().is_none_or(|ext| !ext.eq_ignore_ascii_case("json"));
+    let mut by_name: HashMap<&std::ffi::OsStr, Vec<(&Path, &Path)>> = HashMap::new();
+    for (path, real) in &files {
+        if is_media(path) && !path.parent().is_some_and(|folder| albums.contains_key(folder)) {
+            if let Some(name) = path.file_name() {
+                by_name.entry(name).or_default().push((path, real));
+            }
+        }
+    }
+
+    let mut copies = Vec::new();
+    for (path, real) in &files {
+        let Some(album) = path.parent().and_then(|folder| albums.get_mut(folder)) else { continue };
+        if !is_media(path) {
+            continue;
+        }
+        let original = path.file_name()
+            .and_then(|name| by_name.get(name))
+            .and_then(|candidates| candidates.iter().find(|(_, other)| same(other, real)));
+        match original {
+            Some((original, _)) => {
+                album.members.push(original.to_path_buf());
+                copies.push((path.to_path_buf(), original.to_path_buf()));
+            }
+            None => album.members.push(path.to_path_buf()),
+        }
+    }
+    let mut albums: Vec<Album> = albums.into_values().collect();
+    albums.sort_by(|a, b| a.folder.cmp(&b.folder));
+    AlbumScan { albums, copies }
+}
+
+/// Reads an album `metadata.json`, in the current layout or the older one
+/// nested under `albumData`.
+fn read_album(json_path: &Path, folder: &Path) -> Option<Album> {
+    let text = fs::read_to_string(json_path).ok()?;
+    let json: serde_json::Value = serde_json::from_str(&text).ok()?;
+    let data = json.get("albumData").unwrap_or(&json);
+    let text_field = |key: &str| data[key].as_str().map(str::trim).filter(|s| !s.is_empty()).map(str::to_string);
+    // Only album files have a title; a media sidecar named metadata.json does not
+    data.get("title")?;
+    let folder_name = folder.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let date = data["date"]["timestamp"].as_str()
+        .and_then(|ts| ts.parse::<i64>().ok())
+        .and_then(|ts| Utc.timestamp_opt(ts, 0).single())
+        .map(|dt| dt.format("%Y:%m:%d %H:%M:%S").to_string());
+    Some(Album {
+        title: text_field("title").unwrap_or(folder_name),
+        description: text_field("description"),
+        date,
+        folder: folder.to_path_buf(),
+        members: Vec::new(),
+    })
+}
+
+/// Finds the albums in the working folder and leaves out album copies of files
+/// that also live in a year folder, with their JSON, so every photo is
+/// processed and sorted once; under `--transfer move` they are moved to
+/// `keep_in/Album Copies` (see [`leave_out`]). Albums are kept in the journal,
+/// since a resumed run no longer sees the removed copies.
+pub fn collect_albums(base_path: &Path, keep_in: Option<&Path>, logs_dir: &Path, journal: &mut Journal) -> Vec<Album> {
+    let recorded = |journal: &Journal| -> Vec<Album> {
+        journal.entries(Stage::Albums).into_iter()
+            .filter_map(|(_, data)| serde_json::from_value(data.clone()).ok())
+            .collect()
+    };
+    if journal.is_stage_done(Stage::Albums) {
+        return recorded(journal);
+    }
+    let files: Vec<PathBuf> = WalkDir::new(base_path).sort_by_file_name().into_iter()
+        .filter_map(Result::ok)
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.into_path())
+        .collect();
+    let scan = scan_albums(files.iter().map(|p| (p.as_path(), p.as_path())), same_contents);
+    // An interrupted run may already have deleted some copies; what it recorded wins
+    for album in &scan.albums {
+        if journal.data(Stage::Albums, &album.folder).is_none() {
+            if let Ok(data) = serde_json::to_value(album) {
+                journal.record_with(Stage::Albums, &album.folder, data);
+            }
+            log_to_file(logs_dir, "albums.log", &format!("Album {:?} ({:?}): {} files", album.title, album.folder, album.members.len()));
+        }
+    }
+    let keep_in = keep_in.map(|dir| dir.join("Album Copies"));
+    for (copy, original) in &scan.copies {
+        match leave_out(copy, base_path, keep_in.as_deref()) {
+            Ok(Some(dest)) => log_to_file(logs_dir, "albums.log", &format!("✅ Removed album copy {:?}: same file as {:?}, moved to {:?}", copy, original, dest)),
+            Ok(None) => log_to_file(logs_dir, "albums.log", &format!("✅ Removed album copy {:?}: same file as {:?}", copy, original)),
+            Err(e) => {
+                log_to_file(logs_dir, "albums.log", &format!("❌ Failed to remove album copy {:?}: {}", copy, e));
+                continue;
+            }
+        }
+        let json = json_sidecar(copy);
+        if json.exists() {
+            if let Err(e) = leave_out(&json, base_path, keep_in.as_deref()) {
+                log_to_file(logs_dir, "albums.log", &format!("❌ Failed to remove {:?}: {}", json, e));
+            }
+        }
+    }
+    journal.finish_stage(Stage::Albums);
+    recorded(journal)
+}
+
+/// Stores the titles of the albums each file belongs to in its metadata.
+pub fn assign_albums(albums: &[Album], metadata: &mut [MediaMetadata], journal: &Journal) {
+    let mut titles: HashMap<PathBuf, Vec<String>> = HashMap::new();
+    for album in albums {
+        for member in &album.members {
+            titles.entry(separated_path(journal, member)).or_default().push(album.title.clone());
+        }
+    }
+    for meta in metadata {
+        meta.albums = titles.get(&meta.media_path).cloned().unwrap_or_default();
+    }
+}
+
+/// Recreates every album under `output_dir/Albums` with `mode`, pointing at
+/// where each member was sorted to (`destinations`, by working path). Returns
+/// how many albums were written.
+pub fn write_albums(
+    albums: &[Album],
+    destinations: &HashMap<PathBuf, PathBuf>,
+    output_dir: &Path,
+    mode: AlbumMode,
+    logs_dir: &Path,
+    journal: &Journal,
+) -> usize {
+    if mode == AlbumMode::None || albums.is_empty() {
+        return 0;
+    }
+    let albums_dir = output_dir.join("Albums");
+    let mut used_names = HashSet::new();
+    let mut written = 0;
+    for album in albums {
+        let name = unique_name(&sanitize(&album.title), "", &mut used_names);
+        // Duplicates in one album all lead to the copy that was kept
+        let mut seen = HashSet::new();
+        let members: Vec<&PathBuf> = album.members.iter()
+            .filter_map(|member| destinations.get(&separated_path(journal, member)))
+            .filter(|destination| seen.insert(*destination))
+            .collect();
+        let result = match mode {
+            AlbumMode::Manifest => write_manifest(album, &members, &albums_dir.join(format!("{}.m3u", name)), output_dir),
+            _ => link_members(&members, &albums_dir.join(&name), mode, output_dir, logs_dir),
+        };
+        match result {
+            Ok(()) => {
+                written += 1;
+                log_to_file(logs_dir, "albums.log", &format!("✅ Wrote album {:?} with {} of {} files", name, members.len(), album.members.len()));
+            }
+            Err(e) => log_to_file(logs_dir, "albums.log", &format!("❌ Failed to write album {:?}: {}", name, e)),
+        }
+    }
+    written
+}
+
+fn link_members(members: &[&PathBuf], album_dir: &Path, mode: AlbumMode, output_dir: &Path, logs_dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(album_dir)?;
+    let mut used_names = HashSet::new();
+    for target in members {
+        let stem = target.file_stem().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        let ext = target.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+        let link = album_dir.join(unique_name(&stem, &ext, &mut used_names));
+        let result = if mode == AlbumMode::Symlinks {
+            // Relative, so the output folder can be moved as a whole
+            let relative = Path::new("../..").join(target.strip_prefix(output_dir).unwrap_or(target));
+            let _ = fs::remove_file(&link);
+            symlink_file(&relative, &link)
+        } else {
+            transfer_file(target, &link, TransferMode::Hardlink)
+        };
+        if let Err(e) = result {
+            log_to_file(logs_dir, "albums.log", &format!("❌ Failed to link {:?} into {:?}: {}", target, album_dir, e));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn symlink_file(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn symlink_file(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(target, link)
+}
+
+/// An extended M3U playlist with paths relative to the playlist.
+fn write_manifest(album: &Album, members: &[&PathBuf], path: &Path, output_dir: &Path) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut text = format!("#EXTM3U\n#PLAYLIST:{}\n", album.title);
+    if let Some(description) = &album.description {
+        text.push_str(&format!("# {}\n", description.replace('\n', " ")));
+    }
+    if let Some(date) = &album.date {
+        text.push_str(&format!("# Date: {}\n", date));
+    }
+    for target in members {
+        let relative = target.strip_prefix(output_dir).unwrap_or(target);
+        let parts: Vec<String> = relative.components()
+            .filter_map(|c| match c {
+                Component::Normal(part) => Some(part.to_string_lossy().to_string()),
+                _ => None,
+            })
+            .collect();
+        text.push_str(&format!("../{}\n", parts.join("/")));
+    }
+    fs::write(path, text)
+}
+
+/// An album title usable as a file name on every platform.
+fn sanitize(title: &str) -> String {
+    let name: String = title.chars()
+        .map(|c| if matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|') || c.is_control() { '_' } else { c })
+        .collect();
+    let name = name.trim().trim_end_matches('.').to_string();
+    if name.is_empty() { "Untitled".to_string() } else { name }
+}
+
+/// `stem` + `ext`, or `stem (2)` + `ext`, ... if that name was used before.
+fn unique_name(stem: &str, ext: &str, used: &mut HashSet<String>) -> String {
+    let mut candidate = format!("{}{}", stem, ext);
+    let mut n = 2;
+    while !used.insert(candidate.to_lowercase()) {
+        candidate = format!("{} ({}){}", stem, n, ext);
+        n += 1;
+    }
+    candidate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A Takeout with `IMG.jpg` in a year folder and in the album `Trip`,
+    /// the album file holding `album_contents`.
+    fn takeout(album_contents: &str) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let (year, album) = (dir.path().join("Photos from 2020"), dir.path().join("Trip"));
+        fs::create_dir_all(&year).unwrap();
+        fs::create_dir_all(&album).unwrap();
+        fs::write(year.join("IMG.jpg"), "photo one").unwrap();
+        fs::write(year.join("IMG.jpg.json"), "{}").unwrap();
+        fs::write(year.join(ALBUM_METADATA_NAME), r#"{"title": "Photos from 2020"}"#).unwrap();
+        fs::write(album.join(ALBUM_METADATA_NAME), r#"{"title": "Trip to Rome", "date": {"timestamp": "1589115600"}}"#).unwrap();
+        fs::write(album.join("IMG.jpg"), album_contents).unwrap();
+        fs::write(album.join("IMG.jpg.json"), "{}").unwrap();
+        dir
+    }
+
+    fn scan_folder(dir: &Path) -> AlbumScan {
+        let files: Vec<PathBuf> = WalkDir::new(dir).sort_by_file_name().into_iter().filter_map(Result::ok)
+            .filter(|e| e.file_type().is_file())
+            .map(|e| e.into_path())
+            .collect();
+        scan_albums(files.iter().map(|p| (p.as_path(), p.as_path())), same_contents)
+    }
+
+    #[test]
+    fn album_copies_must_have_the_same_contents() {
+        let dir = takeout("photo one");
+        let scan = scan_folder(dir.path());
+        let (copy, original) = (dir.path().join("Trip/IMG.jpg"), dir.path().join("Photos from 2020/IMG.jpg"));
+        assert_eq!(scan.copies, vec![(copy, original.clone())]);
+        assert_eq!(scan.albums.len(), 1);
+        assert_eq!(scan.albums[0].title, "Trip to Rome");
+        assert_eq!(scan.albums[0].date.as_deref(), Some("2020:05:10 13:00:00"));
+        assert_eq!(scan.albums[0].members, vec![original]);
+
+        // Same name and size, other photo
+        let dir = takeout("photo two");
+        let scan = scan_folder(dir.path());
+        assert!(scan.copies.is_empty());
+        assert_eq!(scan.albums[0].members, vec![dir.path().join("Trip/IMG.jpg")]);
+    }
+
+    #[test]
+    fn different_photos_with_the_same_name_and_size_are_kept() {
+        let dir = takeout("photo two");
+        let logs = tempfile::tempdir().unwrap();
+        let albums = collect_albums(dir.path(), None, logs.path(), &mut Journal::disabled());
+        assert_eq!(albums[0].members, vec![dir.path().join("Trip/IMG.jpg")]);
+        assert_eq!(fs::read_to_string(dir.path().join("Trip/IMG.jpg")).unwrap(), "photo two");
+        assert!(dir.path().join("Trip/IMG.jpg.json").exists());
+    }
+
+    #[test]
+    fn album_copies_are_deleted_or_left_out() {
+        let dir = takeout("photo one");
+        let logs = tempfile::tempdir().unwrap();
+        collect_albums(dir.path(), None, logs.path(), &mut Journal::disabled());
+        assert!(!dir.path().join("Trip/IMG.jpg").exists());
+        assert!(!dir.path().join("Trip/IMG.jpg.json").exists());
+        assert!(dir.path().join("Photos from 2020/IMG.jpg").exists());
+
+        // Under --transfer move the copy is kept aside
+        let dir = takeout("photo one");
+        let left_out = tempfile::tempdir().unwrap();
+        let albums = collect_albums(dir.path(), Some(left_out.path()), logs.path(), &mut Journal::disabled());
+        assert_eq!(albums[0].members, vec![dir.path().join("Photos from 2020/IMG.jpg")]);
+        assert!(!dir.path().join("Trip/IMG.jpg").exists());
+        let kept = left_out.path().join("Album Copies/Trip");
+        assert_eq!(fs::read_to_string(kept.join("IMG.jpg")).unwrap(), "photo one");
+        assert!(kept.join("IMG.jpg.json").exists());
+    }
+}
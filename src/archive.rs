@@ -0,0 +1,239 @@
+// archive.rs
+// Google Takeout zip/tgz input for MetaSort_v1.0.0 – Google Photos Takeout Organizer
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+use flate2::read::GzDecoder;
+use crate::dedup::hash_file;
+use crate::error::{Error, Result};
+
+/// Archive formats Google Takeout is delivered in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    TarGz,
+    Tar,
+}
+
+/// A file inside a Takeout archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveEntry {
+    /// Path inside the archive.
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+/// Detects a Takeout archive by its file name (`.zip`, `.tgz`, `.tar.gz`, `.tar`).
+pub fn archive_kind(path: &Path) -> Option<ArchiveKind> {
+    let name = path.file_name()?.to_string_lossy().to_lowercase();
+    if name.ends_with(".zip") {
+        Some(ArchiveKind::Zip)
+    } else if name.ends_with(".tgz") || name.ends_with(".tar.gz") {
+        Some(ArchiveKind::TarGz)
+    } else if name.ends_with(".tar") {
+        Some(ArchiveKind::Tar)
+    } else {
+        None
+    }
+}
+
+/// Archive files directly inside `dir`, sorted by name so parts are read in order.
+pub fn archives_in(dir: &Path) -> Vec<PathBuf> {
+    let mut archives: Vec<PathBuf> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|e| e.path())
+                .filter(|p| p.is_file() && archive_kind(p).is_some())
+                .collect()
+        })
+        .unwrap_or_default();
+    archives.sort();
+    archives
+}
+
+/// Lists the files of an archive without extracting them: a zip archive from
+/// its central directory, a tar archive by reading past every file once.
+/// Entries whose path would leave the folder they are extracted into are
+/// left out.
+pub fn list_entries(archive: &Path) -> Result<Vec<ArchiveEntry>> {
+    let mut entries = Vec::new();
+    read_entries(archive, |_| false, |path, size, _| {
+        entries.push(ArchiveEntry { path: path.to_path_buf(), size });
+        Ok(())
+    })?;
+    Ok(entries)
+}
+
+/// Reads the files of `archive` that are needed, in one pass: every entry in
+/// `destinations`, given by its path inside the archive, is written to the
+/// file it maps to, and every entry in `hashed` is hashed with BLAKE3.
+/// Other entries are skipped. `extracted` is called after each file written.
+/// Returns the hashes by entry path.
+pub fn read_archive(
+    archive: &Path,
+    destinations: &HashMap<PathBuf, PathBuf>,
+    hashed: &HashSet<PathBuf>,
+    mut extracted: impl FnMut(&Path),
+) -> Result<HashMap<PathBuf, String>> {
+    let mut hashes = HashMap::new();
+    let wanted = |path: &Path| destinations.contains_key(path) || hashed.contains(path);
+    read_entries(archive, wanted, |path, _, reader| {
+        let Some(reader) = reader else {
+            return Ok(());
+        };
+        match destinations.get(path) {
+            Some(dest) => {
+                if let Some(parent) = dest.parent() {
+                    fs::create_dir_all(parent).map_err(|e| Error::io(parent, e))?;
+                }
+                let mut out = File::create(dest).map_err(|e| Error::io(dest, e))?;
+                io::copy(reader, &mut out).map_err(|e| Error::io(dest, e))?;
+                if hashed.contains(path) {
+                    hashes.insert(path.to_path_buf(), hash_file(dest).map_err(|e| Error::io(dest, e))?);
+                }
+                extracted(path);
+            }
+            None => {
+                let mut hasher = blake3::Hasher::new();
+                io::copy(reader, &mut hasher).map_err(|e| archive_error(archive, e))?;
+                hashes.insert(path.to_path_buf(), hasher.finalize().to_hex().to_string());
+            }
+        }
+        Ok(())
+    })?;
+    Ok(hashes)
+}
+
+/// Calls `visit` for every file of `archive` with its path and size, and
+/// with a reader of its contents if `wanted` asks for them. Zip entries that
+/// are not wanted are never decompressed.
+fn read_entries(
+    archive: &Path,
+    wanted: impl Fn(&Path) -> bool,
+    mut visit: impl FnMut(&Path, u64, Option<&mut dyn Read>) -> Result<()>,
+) -> Result<()> {
+    let kind = archive_kind(archive).ok_or_else(|| Error::UnsupportedInput(archive.to_path_buf()))?;
+    let file = File::open(archive).map_err(|e| Error::io(archive, e))?;
+    // Never read a path outside the folder it is extracted into
+    let is_safe = |path: &Path| path.components().all(|c| matches!(c, Component::Normal(_)));
+    match kind {
+        ArchiveKind::Zip => {
+            let mut zip = zip::ZipArchive::new(BufReader::new(file)).map_err(|e| archive_error(archive, e))?;
+            for i in 0..zip.len() {
+                let entry = zip.by_index_raw(i).map_err(|e| archive_error(archive, e))?;
+                let (Some(path), true) = (entry.enclosed_name().filter(|path| is_safe(path)), entry.is_file()) else {
+                    continue;
+                };
+                let size = entry.size();
+                drop(entry);
+                if wanted(&path) {
+                    let mut entry = zip.by_index(i).map_err(|e| archive_error(archive, e))?;
+                    visit(&path, size, Some(&mut entry))?;
+                } else {
+                    visit(&path, size, None)?;
+                }
+            }
+        }
+        ArchiveKind::TarGz | ArchiveKind::Tar => {
+            let reader: Box<dyn Read> = if kind == ArchiveKind::TarGz {
+                Box::new(GzDecoder::new(BufReader::new(file)))
+            } else {
+                Box::new(BufReader::new(file))
+            };
+            let mut tar = tar::Archive::new(reader);
+            for entry in tar.entries().map_err(|e| archive_error(archive, e))? {
+                let mut entry = entry.map_err(|e| archive_error(archive, e))?;
+                if !entry.header().entry_type().is_file() {
+                    continue;
+                }
+                let path = entry.path().map_err(|e| archive_error(archive, e))?.into_owned();
+                if !is_safe(&path) {
+                    continue;
+                }
+                let size = entry.size();
+                if wanted(&path) {
+                    visit(&path, size, Some(&mut entry))?;
+                } else {
+                    visit(&path, size, None)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn archive_error(archive: &Path, e: impl ToString) -> Error {
+    Error::Archive { path: archive.to_path_buf(), message: e.to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const FILES: [(&str, &str); 3] = [
+        ("Takeout/Google Photos/Photos from 2021/IMG_1.jpg", "photo one"),
+        ("Takeout/Google Photos/Photos from 2021/IMG_1.jpg.json", "{}"),
+        ("Takeout/Google Photos/Photos from 2021/IMG_2.jpg", "photo two"),
+    ];
+
+    fn write_zip(path: &Path, files: &[(&str, &str)]) {
+        let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+        for (name, contents) in files {
+            zip.start_file(*name, zip::write::SimpleFileOptions::default()).unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    fn write_tgz(path: &Path, files: &[(&str, &str)]) {
+        let gz = flate2::write::GzEncoder::new(File::create(path).unwrap(), flate2::Compression::default());
+        let mut tar = tar::Builder::new(gz);
+        for (name, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            tar.append_data(&mut header, name, contents.as_bytes()).unwrap();
+        }
+        tar.into_inner().unwrap().finish().unwrap();
+    }
+
+    #[test]
+    fn zip_and_tgz_entries_are_listed_and_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let (zip, tgz) = (dir.path().join("takeout-001.zip"), dir.path().join("takeout-002.tgz"));
+        write_zip(&zip, &FILES);
+        write_tgz(&tgz, &FILES);
+        for (i, archive) in [&zip, &tgz].into_iter().enumerate() {
+            let entries = list_entries(archive).unwrap();
+            let expected: Vec<ArchiveEntry> = FILES.iter()
+                .map(|(name, contents)| ArchiveEntry { path: PathBuf::from(name), size: contents.len() as u64 })
+                .collect();
+            assert_eq!(entries, expected);
+
+            // Only the JSON is written, only the second photo hashed
+            let out = dir.path().join(format!("out-{}", i));
+            let destinations = HashMap::from([(PathBuf::from(FILES[1].0), out.join("IMG_1.jpg.json"))]);
+            let hashed = HashSet::from([PathBuf::from(FILES[2].0)]);
+            let mut extracted = Vec::new();
+            let hashes = read_archive(archive, &destinations, &hashed, |path| extracted.push(path.to_path_buf())).unwrap();
+            assert_eq!(extracted, [PathBuf::from(FILES[1].0)]);
+            assert_eq!(fs::read_to_string(out.join("IMG_1.jpg.json")).unwrap(), "{}");
+            assert_eq!(fs::read_dir(&out).unwrap().count(), 1);
+            assert_eq!(hashes.len(), 1);
+            assert_eq!(hashes[&PathBuf::from(FILES[2].0)], blake3::hash(b"photo two").to_hex().to_string());
+        }
+    }
+
+    #[test]
+    fn entries_leaving_the_archive_folder_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let zip = dir.path().join("takeout.zip");
+        write_zip(&zip, &[("../evil.jpg", "x"), ("Takeout/ok.jpg", "y")]);
+        let entries = list_entries(&zip).unwrap();
+        assert_eq!(entries, [ArchiveEntry { path: PathBuf::from("Takeout/ok.jpg"), size: 1 }]);
+    }
+}
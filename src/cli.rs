@@ -0,0 +1,174 @@
+// cli.rs
+// Command-line arguments for MetaSort_v1.0.0 – Google Photos Takeout Organizer
+
+use std::path::PathBuf;
+use clap::Parser;
+use metasort::{AlbumMode, CollisionStrategy, Config, DateSource, EditedPolicy, Locale, MetadataWriter, MotionPhotoMode, StatusPolicy, TransferMode, UnpairedPolicy};
+
+/// Every interactive prompt has a flag equivalent. Anything left unset is asked
+/// on stdin, unless `--yes` is given, in which case the default is used instead.
+#[derive(Parser, Debug)]
+#[command(name = "metasort", version, about = "MetaSort – Cross-platform Google Photos Takeout Organizer")]
+pub struct Cli {
+    /// TOML config file with saved run settings (flags override it)
+    #[arg(short, long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Google Photos Takeout folder, takeout-*.zip/.tgz archives, or a folder of archives
+    #[arg(short, long, value_name = "PATH", num_args = 1..)]
+    pub input: Vec<PathBuf>,
+
+    /// Folder where MetaSort works and writes MetaSort_Output (originals are untouched)
+    #[arg(short, long, value_name = "DIR")]
+    pub output: Option<PathBuf>,
+
+    /// Separate WhatsApp and Screenshot images into their own folders
+    #[arg(long, value_name = "BOOL", num_args = 0..=1, default_missing_value = "true")]
+    pub separate_whatsapp_screenshots: Option<bool>,
+
+    /// What to do with media that has no matching .json
+    #[arg(long, value_enum, value_name = "POLICY")]
+    pub unpaired: Option<UnpairedPolicy>,
+
+    /// Where the embedded date of WhatsApp & Screenshot images comes from
+    #[arg(long, value_enum, value_name = "SOURCE")]
+    pub date_source: Option<DateSource>,
+
+    /// How input files are brought into the working folder
+    #[arg(long, value_enum, value_name = "MODE")]
+    pub transfer: Option<TransferMode>,
+
+    /// What to do when a file would land on a name that is already taken
+    #[arg(long, value_enum, value_name = "STRATEGY")]
+    pub on_collision: Option<CollisionStrategy>,
+
+    /// Folder layout below 'Media Files', e.g. "{category}/{year}/{month:02}-{month_name}"
+    #[arg(long, value_name = "TEMPLATE")]
+    pub layout: Option<String>,
+
+    /// Rename dated files, e.g. "{date:%Y-%m-%d_%H%M%S}_{camera_model}_{seq}.{ext}"
+    #[arg(long, value_name = "TEMPLATE")]
+    pub rename: Option<String>,
+
+    /// Language of the month and category folder names
+    #[arg(long, value_enum, value_name = "LANG")]
+    pub locale: Option<Locale>,
+
+    /// Time zone of photos without one in the file or GPS, e.g. "Europe/Berlin" or "+02:00"
+    #[arg(long, value_name = "ZONE")]
+    pub timezone: Option<String>,
+
+    /// Recreate Google Photos albums in the output as folders, symlinks or .m3u manifests
+    #[arg(long, value_enum, value_name = "MODE")]
+    pub albums: Option<AlbumMode>,
+
+    /// What to do with the video embedded in Motion Photos
+    #[arg(long, value_enum, value_name = "MODE")]
+    pub motion_photos: Option<MotionPhotoMode>,
+
+    /// Report recompressed, resized and burst copies of photos in near_duplicates.csv
+    #[arg(long, value_name = "BOOL", num_args = 0..=1, default_missing_value = "true")]
+    pub near_duplicates: Option<bool>,
+
+    /// Which versions of photos edited in Google Photos are kept
+    #[arg(long, value_enum, value_name = "POLICY")]
+    pub edited: Option<EditedPolicy>,
+
+    /// What to do with files archived in Google Photos
+    #[arg(long, value_enum, value_name = "POLICY")]
+    pub archived: Option<StatusPolicy>,
+
+    /// What to do with files in the Google Photos trash
+    #[arg(long, value_enum, value_name = "POLICY")]
+    pub trashed: Option<StatusPolicy>,
+
+    /// What to do with files from the Google Photos Locked Folder
+    #[arg(long, value_enum, value_name = "POLICY")]
+    pub locked: Option<StatusPolicy>,
+
+    /// Number of files processed in parallel (default: one per CPU core)
+    #[arg(short, long, value_name = "N")]
+    pub jobs: Option<usize>,
+
+    /// How metadata is written into files
+    #[arg(long, value_enum, value_name = "WRITER")]
+    pub writer: Option<MetadataWriter>,
+
+    /// Path to the exiftool executable
+    #[arg(long, value_name = "PATH")]
+    pub exiftool: Option<PathBuf>,
+
+    /// Only write a plan of what would happen (CSV/JSON manifest) without copying, renaming or embedding
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Answer yes to confirmations and use defaults for anything not given on the command line
+    #[arg(short, long)]
+    pub yes: bool,
+}
+
+impl Cli {
+    /// Command-line flags take precedence over values from the config file.
+    pub fn apply_to(&self, config: &mut Config) {
+        if self.output.is_some() {
+            config.output = self.output.clone();
+        }
+        if self.separate_whatsapp_screenshots.is_some() {
+            config.separate_whatsapp_screenshots = self.separate_whatsapp_screenshots;
+        }
+        if self.unpaired.is_some() {
+            config.unpaired = self.unpaired;
+        }
+        if self.date_source.is_some() {
+            config.date_source = self.date_source;
+        }
+        if self.transfer.is_some() {
+            config.transfer = self.transfer;
+        }
+        if self.on_collision.is_some() {
+            config.on_collision = self.on_collision;
+        }
+        if self.layout.is_some() {
+            config.layout = self.layout.clone();
+        }
+        if self.rename.is_some() {
+            config.rename = self.rename.clone();
+        }
+        if self.locale.is_some() {
+            config.locale = self.locale;
+        }
+        if self.timezone.is_some() {
+            config.timezone = self.timezone.clone();
+        }
+        if self.albums.is_some() {
+            config.albums = self.albums;
+        }
+        if self.motion_photos.is_some() {
+            config.motion_photos = self.motion_photos;
+        }
+        if self.near_duplicates.is_some() {
+            config.near_duplicates = self.near_duplicates;
+        }
+        if self.edited.is_some() {
+            config.edited = self.edited;
+        }
+        if self.archived.is_some() {
+            config.archived = self.archived;
+        }
+        if self.trashed.is_some() {
+            config.trashed = self.trashed;
+        }
+        if self.locked.is_some() {
+            config.locked = self.locked;
+        }
+        if self.jobs.is_some() {
+            config.jobs = self.jobs;
+        }
+        if self.writer.is_some() {
+            config.writer = self.writer;
+        }
+        if self.exiftool.is_some() {
+            config.exiftool_path = self.exiftool.clone();
+        }
+    }
+}
@@ -0,0 +1,231 @@
+// collision.rs
+// File name collision handling for MetaSort_v1.0.0 – Google Photos Takeout Organizer
+
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::dedup::hash_file;
+use crate::error::{Error, Result};
+use crate::merge::same_contents;
+use crate::utils::log_to_file;
+
+/// Name of the CSV report listing every collision, in the CSV Report folder.
+pub const COLLISIONS_CSV: &str = "collisions.csv";
+
+/// What to do when a file would land on a name that is already taken.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CollisionStrategy {
+    /// Add ` (2)`, ` (3)`, ... before the extension
+    #[default]
+    Number,
+    /// Add the first 8 characters of the file's content hash before the extension
+    Hash,
+    /// Drop the file if it is identical to the one already there, else number it
+    SkipIdentical,
+    /// Stop the run; it can be resumed with another strategy
+    Fail,
+}
+
+/// How a collision was resolved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolution {
+    /// The file lands under another name.
+    Renamed(PathBuf),
+    /// The file is identical to this one, already there, and is dropped.
+    Identical(PathBuf),
+}
+
+/// Resolves the collision of `src` with the file already at `dest`.
+/// `occupant` gives the file holding a name, or `None` when it is free.
+pub fn resolve(src: &Path, dest: &Path, strategy: CollisionStrategy, occupant: impl Fn(&Path) -> Option<PathBuf>) -> Result<Resolution> {
+    let taken = |path: &Path| occupant(path).is_some();
+    match strategy {
+        CollisionStrategy::Number => Ok(Resolution::Renamed(numbered(dest, taken))),
+        CollisionStrategy::Hash => {
+            let hash = hash_file(src).map_err(|e| Error::io(src, e))?;
+            let hashed = with_suffix(dest, &format!("_{}", &hash[..8]));
+            Ok(Resolution::Renamed(if taken(&hashed) { numbered(&hashed, taken) } else { hashed }))
+        }
+        CollisionStrategy::SkipIdentical => {
+            // An identical copy may sit under a number from an earlier run
+            for candidate in std::iter::once(dest.to_path_buf()).chain(numbers(dest)) {
+                match occupant(&candidate) {
+                    None => return Ok(Resolution::Renamed(candidate)),
+                    Some(existing) if same_contents(src, &existing) => return Ok(Resolution::Identical(candidate)),
+                    Some(_) => {}
+                }
+            }
+            unreachable!("some number is free")
+        }
+        CollisionStrategy::Fail => Err(Error::Collision { path: src.to_path_buf(), existing: dest.to_path_buf() }),
+    }
+}
+
+/// Where `src` lands if moved to `dest` on disk: `None` when `dest` is free,
+/// else as resolved with `strategy`.
+pub fn land(src: &Path, dest: &Path, strategy: CollisionStrategy) -> Result<Option<Resolution>> {
+    if !dest.exists() {
+        return Ok(None);
+    }
+    resolve(src, dest, strategy, |path| path.exists().then(|| path.to_path_buf())).map(Some)
+}
+
+/// `name (2).ext`, `name (3).ext`, ...
+fn numbers(dest: &Path) -> impl Iterator<Item = PathBuf> + '_ {
+    (2..).map(|n| with_suffix(dest, &format!(" ({})", n)))
+}
+
+/// The first free of [`numbers`].
+fn numbered(dest: &Path, taken: impl Fn(&Path) -> bool) -> PathBuf {
+    numbers(dest).find(|path| !taken(path)).expect("some number is free")
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let name = match path.extension() {
+        Some(ext) => format!("{}{}.{}", stem, suffix, ext.to_string_lossy()),
+        None => format!("{}{}", stem, suffix),
+    };
+    path.with_file_name(name)
+}
+
+/// Records a resolved collision in `log_name` and in the collisions CSV.
+pub fn record(src: &Path, dest: &Path, resolution: &Resolution, logs_dir: &Path, log_name: &str, report: &Path) {
+    let (outcome, landed) = match resolution {
+        Resolution::Renamed(path) => ("renamed", path.as_path()),
+        Resolution::Identical(path) => ("skipped (identical)", path.as_path()),
+    };
+    log_to_file(logs_dir, log_name, &format!("Name collision: {:?} already exists; {:?} {} to {:?}", dest, src, outcome, landed));
+    if let Err(e) = append_report(report, src, dest, outcome, landed) {
+        log_to_file(logs_dir, log_name, &format!("❌ Failed to write {:?}: {}", report, e));
+    }
+}
+
+fn append_report(report: &Path, src: &Path, dest: &Path, outcome: &str, landed: &Path) -> io::Result<()> {
+    if let Some(parent) = report.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let is_new = !report.exists();
+    let file = OpenOptions::new().create(true).append(true).open(report)?;
+    let mut wtr = csv::Writer::from_writer(file);
+    if is_new {
+        wtr.write_record(["Source", "Wanted", "Resolution", "Destination"])?;
+    }
+    wtr.write_record([
+        src.to_string_lossy().as_ref(),
+        dest.to_string_lossy().as_ref(),
+        outcome,
+        landed.to_string_lossy().as_ref(),
+    ])?;
+    wtr.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(dir: &Path, name: &str, contents: &str) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn on_disk(path: &Path) -> Option<PathBuf> {
+        path.exists().then(|| path.to_path_buf())
+    }
+
+    #[test]
+    fn numbers_go_before_the_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = write(dir.path(), "src.jpg", "new");
+        let dest = write(dir.path(), "IMG.jpg", "old");
+        write(dir.path(), "IMG (2).jpg", "older");
+        let resolution = resolve(&src, &dest, CollisionStrategy::Number, on_disk).unwrap();
+        assert_eq!(resolution, Resolution::Renamed(dir.path().join("IMG (3).jpg")));
+        assert_eq!(with_suffix(Path::new("a/README"), " (2)"), Path::new("a/README (2)"));
+        assert_eq!(with_suffix(Path::new("a/IMG.tar.gz"), " (2)"), Path::new("a/IMG.tar (2).gz"));
+    }
+
+    #[test]
+    fn hashes_go_before_the_extension_and_are_numbered_when_taken() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = write(dir.path(), "src.jpg", "new");
+        let dest = write(dir.path(), "IMG.jpg", "old");
+        let hashed = dir.path().join(format!("IMG_{}.jpg", &hash_file(&src).unwrap()[..8]));
+        let resolution = resolve(&src, &dest, CollisionStrategy::Hash, on_disk).unwrap();
+        assert_eq!(resolution, Resolution::Renamed(hashed.clone()));
+        fs::write(&hashed, "new").unwrap();
+        let resolution = resolve(&src, &dest, CollisionStrategy::Hash, on_disk).unwrap();
+        assert_eq!(resolution, Resolution::Renamed(with_suffix(&hashed, " (2)")));
+    }
+
+    #[test]
+    fn identical_files_are_skipped_also_under_a_number() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = write(dir.path(), "src.jpg", "same");
+        let dest = write(dir.path(), "IMG.jpg", "same");
+        let resolution = resolve(&src, &dest, CollisionStrategy::SkipIdentical, on_disk).unwrap();
+        assert_eq!(resolution, Resolution::Identical(dest.clone()));
+
+        fs::write(&dest, "other").unwrap();
+        let numbered = write(dir.path(), "IMG (2).jpg", "same");
+        let resolution = resolve(&src, &dest, CollisionStrategy::SkipIdentical, on_disk).unwrap();
+        assert_eq!(resolution, Resolution::Identical(numbered.clone()));
+
+        fs::write(&numbered, "another").unwrap();
+        let resolution = resolve(&src, &dest, CollisionStrategy::SkipIdentical, on_disk).unwrap();
+        assert_eq!(resolution, Resolution::Renamed(dir.path().join("IMG (3).jpg")));
+    }
+
+    #[test]
+    fn fail_stops_with_both_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = write(dir.path(), "src.jpg", "new");
+        let dest = write(dir.path(), "IMG.jpg", "old");
+        match resolve(&src, &dest, CollisionStrategy::Fail, on_disk) {
+            Err(Error::Collision { path, existing }) => assert_eq!((path, existing), (src, dest)),
+            other => panic!("expected a collision error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn occupants_need_not_be_on_disk() {
+        // A plan claims names before any file is moved there
+        let planned = [PathBuf::from("out/IMG.jpg"), PathBuf::from("out/IMG (2).jpg")];
+        let occupant = |path: &Path| planned.iter().find(|planned| *planned == path).cloned();
+        let resolution = resolve(Path::new("in/IMG.jpg"), &planned[0], CollisionStrategy::Number, occupant).unwrap();
+        assert_eq!(resolution, Resolution::Renamed(PathBuf::from("out/IMG (3).jpg")));
+    }
+
+    #[test]
+    fn land_only_resolves_taken_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = write(dir.path(), "src.jpg", "new");
+        let free = dir.path().join("free.jpg");
+        assert_eq!(land(&src, &free, CollisionStrategy::Fail).unwrap(), None);
+        let dest = write(dir.path(), "IMG.jpg", "old");
+        assert_eq!(land(&src, &dest, CollisionStrategy::Number).unwrap(), Some(Resolution::Renamed(dir.path().join("IMG (2).jpg"))));
+    }
+
+    #[test]
+    fn collisions_are_logged_and_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let logs = dir.path().join("logs");
+        let report = dir.path().join("CSV Report").join(COLLISIONS_CSV);
+        let (src, dest) = (Path::new("in/IMG.jpg"), Path::new("out/IMG.jpg"));
+        record(src, dest, &Resolution::Renamed(PathBuf::from("out/IMG (2).jpg")), &logs, "sorting.log", &report);
+        record(src, dest, &Resolution::Identical(dest.to_path_buf()), &logs, "sorting.log", &report);
+
+        let csv = fs::read_to_string(&report).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines, [
+            "Source,Wanted,Resolution,Destination",
+            "in/IMG.jpg,out/IMG.jpg,renamed,out/IMG (2).jpg",
+            "in/IMG.jpg,out/IMG.jpg,skipped (identical),out/IMG.jpg",
+        ]);
+        let log = fs::read_to_string(logs.join("sorting.log")).unwrap();
+        assert_eq!(log.matches("Name collision").count(), 2);
+    }
+}
@@ -0,0 +1,130 @@
+// config.rs
+// Persistent run settings for MetaSort_v1.0.0 – Google Photos Takeout Organizer
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::albums::AlbumMode;
+use crate::collision::CollisionStrategy;
+use crate::edited::EditedPolicy;
+use crate::error::{Error, Result};
+use crate::item_status::{StatusPolicies, StatusPolicy};
+use crate::layout::{Layout, NameTemplate, DEFAULT_LAYOUT};
+use crate::live_photos::MotionPhotoMode;
+use crate::locale::Locale;
+use crate::metadata_embed::DateSource;
+use crate::metadata_extraction::UnpairedPolicy;
+use crate::native_metadata::MetadataWriter;
+use crate::timezone::Zone;
+use crate::transfer::TransferMode;
+
+/// Default config file name written into the output folder after an interactive run.
+pub const DEFAULT_CONFIG_NAME: &str = "metasort.toml";
+
+/// Settings that can be repeated across runs. Unset fields are taken from the
+/// command line or asked interactively.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "snake_case")]
+pub struct Config {
+    pub output: Option<PathBuf>,
+    pub separate_whatsapp_screenshots: Option<bool>,
+    pub unpaired: Option<UnpairedPolicy>,
+    pub date_source: Option<DateSource>,
+    pub exiftool_path: Option<PathBuf>,
+    pub writer: Option<MetadataWriter>,
+    pub transfer: Option<TransferMode>,
+    pub on_collision: Option<CollisionStrategy>,
+    /// Folder layout template, e.g. `{category}/{year}/{month:02}-{month_name}`.
+    pub layout: Option<String>,
+    /// File name template for dated files; unset keeps the original names.
+    pub rename: Option<String>,
+    /// Language of the month and category folder names.
+    pub locale: Option<Locale>,
+    /// Time zone for dates whose zone is not in the file or known from GPS,
+    /// e.g. `Europe/Berlin` or `+02:00`; unset leaves them in UTC.
+    pub timezone: Option<String>,
+    pub albums: Option<AlbumMode>,
+    pub motion_photos: Option<MotionPhotoMode>,
+    /// Report groups of similar-looking photos (never deletes anything).
+    pub near_duplicates: Option<bool>,
+    pub edited: Option<EditedPolicy>,
+    pub archived: Option<StatusPolicy>,
+    pub trashed: Option<StatusPolicy>,
+    pub locked: Option<StatusPolicy>,
+    /// Worker threads; unset uses every CPU core.
+    pub jobs: Option<usize>,
+}
+
+impl Config {
+    /// Loads a config file, or returns an empty config if it does not exist yet.
+    pub fn load(path: &Path) -> Result<Config> {
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+        let text = fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+        toml::from_str(&text).map_err(|e| Error::Config { path: path.to_path_buf(), message: e.to_string() })
+    }
+
+    /// Fills every unset setting with its default so that a run never prompts.
+    pub fn fill_defaults(&mut self) {
+        self.separate_whatsapp_screenshots.get_or_insert(false);
+        self.unpaired.get_or_insert(UnpairedPolicy::UnknownTime);
+        self.date_source.get_or_insert(DateSource::Metadata);
+        self.transfer.get_or_insert(TransferMode::Copy);
+        self.on_collision.get_or_insert(CollisionStrategy::Number);
+        self.layout.get_or_insert_with(|| DEFAULT_LAYOUT.to_string());
+        self.locale.get_or_insert(Locale::En);
+        self.albums.get_or_insert(AlbumMode::None);
+        self.motion_photos.get_or_insert(MotionPhotoMode::Keep);
+        self.near_duplicates.get_or_insert(false);
+        self.edited.get_or_insert(EditedPolicy::KeepBoth);
+        let policies = StatusPolicies::default();
+        self.archived.get_or_insert(policies.archived);
+        self.trashed.get_or_insert(policies.trashed);
+        self.locked.get_or_insert(policies.locked);
+        self.writer.get_or_insert(MetadataWriter::Native);
+    }
+
+    /// True when the run cannot do without exiftool: it was asked for by path
+    /// or chosen as the writer. Otherwise the built-in writer covers JPEG, PNG,
+    /// MP4 and MOV when exiftool is missing.
+    pub fn requires_exiftool(&self) -> bool {
+        self.exiftool_path.is_some() || self.writer == Some(MetadataWriter::Exiftool)
+    }
+
+    /// The folder layout and file name template in the chosen locale, checked
+    /// before anything is moved; the default layout when unset.
+    pub fn layout(&self) -> Result<Layout> {
+        let layout = Layout::parse(self.layout.as_deref().unwrap_or(DEFAULT_LAYOUT))?
+            .with_locale(self.locale.unwrap_or_default());
+        Ok(match &self.rename {
+            Some(template) => layout.with_names(NameTemplate::parse(template)?),
+            None => layout,
+        })
+    }
+
+    /// The fallback time zone, checked before anything is moved.
+    pub fn timezone(&self) -> Result<Option<Zone>> {
+        self.timezone.as_deref().map(Zone::parse).transpose()
+    }
+
+    /// Policies for archived, trashed and locked files, with defaults for unset ones.
+    pub fn status_policies(&self) -> StatusPolicies {
+        let defaults = StatusPolicies::default();
+        StatusPolicies {
+            archived: self.archived.unwrap_or(defaults.archived),
+            trashed: self.trashed.unwrap_or(defaults.trashed),
+            locked: self.locked.unwrap_or(defaults.locked),
+        }
+    }
+
+    /// Writes the config as TOML, creating parent folders as needed.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        let text = toml::to_string_pretty(self)
+            .map_err(|e| Error::Config { path: path.to_path_buf(), message: e.to_string() })?;
+        fs::write(path, text).map_err(|e| Error::io(path, e))
+    }
+}
@@ -2,21 +2,22 @@
 // CSV report generation logic for MetaSort_v1.0.0 – Google Photos Takeout Organizer 
 
 use std::path::Path;
-use csv;
+use crate::sort_to_folders::FileInfo;
 
 /// Write a CSV report for a given folder and set of files.
 pub fn write_csv_report(
     folder: &Path,
-    files: &[(String, String, String, String, String, u64)], // (FileName, Filetype, Original Time, Resolution, Human Size, Size)
+    files: &[FileInfo],
     csv_name: &str,
 ) {
     let csv_path = folder.join(csv_name);
     let mut wtr = csv::Writer::from_path(&csv_path).expect("Failed to create CSV file");
-    wtr.write_record(&["SL", "FileName", "Filetype", "Original Time", "File Resolution", "File Size", "Bytes"]).unwrap();
-    for (i, (filename, filetype, orig_time, resolution, human_size, size)) in files.iter().enumerate() {
+    wtr.write_record(["SL", "FileName", "Original FileName", "Filetype", "Original Time", "File Resolution", "File Size", "Bytes"]).unwrap();
+    for (i, (filename, original, filetype, orig_time, resolution, human_size, size)) in files.iter().enumerate() {
         wtr.write_record(&[
             (i + 1).to_string(),
             filename.to_string(),
+            original.to_string(),
             filetype.to_string(),
             orig_time.to_string(),
             resolution.to_string(),
@@ -0,0 +1,376 @@
+// dedup.rs
+// Content-hash deduplication for MetaSort_v1.0.0 – Google Photos Takeout Organizer
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::edited;
+use crate::journal::{Journal, Stage};
+use crate::metadata_extraction::MediaMetadata;
+use crate::transfer::leave_out;
+use crate::utils::log_to_file;
+
+/// A file left out because it has the same contents as another one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Duplicate {
+    pub path: PathBuf,
+    /// The copy that is kept and sorted.
+    pub kept: PathBuf,
+    pub size: u64,
+    /// BLAKE3 hash of the contents, in hex.
+    pub hash: String,
+}
+
+/// A removed duplicate as stored in the journal, so a resumed run can still
+/// merge its metadata after the file is gone.
+#[derive(Serialize, Deserialize)]
+struct DuplicateRecord {
+    duplicate: Duplicate,
+    metadata: MediaMetadata,
+}
+
+/// Finds files in `metadata` with identical contents. Only files whose size
+/// is shared with another file are hashed. Of every set of copies, the one
+/// with a JSON sidecar in a `Photos from YYYY` folder and the shortest name
+/// is kept; every other copy is returned as a [`Duplicate`].
+pub fn find_duplicates(metadata: &[MediaMetadata]) -> Vec<Duplicate> {
+    find_duplicates_by(metadata, |path| fs::metadata(path).ok().map(|file| file.len()), |path| hash_file(path).ok())
+}
+
+/// Like [`find_duplicates`], with the size and hash of a file given by `size`
+/// and `hash`, e.g. for files still inside an archive.
+pub fn find_duplicates_by(
+    metadata: &[MediaMetadata],
+    size: impl Fn(&Path) -> Option<u64>,
+    hash: impl Fn(&Path) -> Option<String> + Sync,
+) -> Vec<Duplicate> {
+    let mut by_size: HashMap<u64, Vec<&MediaMetadata>> = HashMap::new();
+    for meta in metadata {
+        if let Some(size) = size(&meta.media_path) {
+            by_size.entry(size).or_default().push(meta);
+        }
+    }
+    let candidates: Vec<(u64, &MediaMetadata)> = by_size.into_iter()
+        .filter(|(_, metas)| metas.len() > 1)
+        .flat_map(|(size, metas)| metas.into_iter().map(move |meta| (size, meta)))
+        .collect();
+    let hashed: Vec<(u64, String, &MediaMetadata)> = candidates.par_iter()
+        .filter_map(|&(size, meta)| hash(&meta.media_path).map(|hash| (size, hash, meta)))
+        .collect();
+
+    let mut groups: BTreeMap<(u64, String), Vec<&MediaMetadata>> = BTreeMap::new();
+    for (size, hash, meta) in hashed {
+        groups.entry((size, hash)).or_default().push(meta);
+    }
+    let mut duplicates = Vec::new();
+    for ((size, hash), mut copies) in groups {
+        if copies.len() < 2 {
+            continue;
+        }
+        copies.sort_by_key(|meta| keep_order(meta));
+        let kept = &copies[0].media_path;
+        duplicates.extend(copies[1..].iter().map(|meta| Duplicate { path: meta.media_path.clone(), kept: kept.clone(), size, hash: hash.clone() }));
+    }
+    duplicates.sort_by(|a, b| a.path.cmp(&b.path));
+    duplicates
+}
+
+/// Which copy of a file is kept: one with a JSON, in a year folder rather than
+/// an album, not an edited copy, with the shortest name (no ` (1)` numbering).
+fn keep_order(meta: &MediaMetadata) -> (bool, bool, bool, usize, PathBuf) {
+    let in_year_folder = meta.media_path.parent()
+        .and_then(|folder| folder.file_name())
+        .is_some_and(|name| name.to_string_lossy().starts_with("Photos from "));
+    let name_len = meta.media_path.file_name().map_or(0, |name| name.len());
+    (
+        meta.json_path.as_os_str().is_empty(),
+        !in_year_folder,
+        edited::is_edited(&meta.media_path),
+        name_len,
+        meta.media_path.clone(),
+    )
+}
+
+pub(crate) fn hash_file(path: &Path) -> io::Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// Merges the metadata of every duplicate into its kept copy and drops the
+/// duplicates from `metadata` and `failed_guess_paths`. Each copy's JSON may
+/// know something the others don't: missing fields are filled in, albums and
+/// people are combined, and a copy that is a favorite makes the kept one a
+/// favorite. The archived, trashed and locked flags only stay if every copy
+/// has them.
+pub fn merge_duplicates(metadata: &mut Vec<MediaMetadata>, failed_guess_paths: &mut Vec<PathBuf>, duplicates: &[Duplicate]) {
+    let copies: HashMap<&Path, &MediaMetadata> = metadata.iter().map(|meta| (meta.media_path.as_path(), meta)).collect();
+    let sources: Vec<(&Duplicate, MediaMetadata)> = duplicates.iter()
+        .filter_map(|duplicate| copies.get(duplicate.path.as_path()).map(|meta| (duplicate, (*meta).clone())))
+        .collect();
+    merge_into_kept(metadata, failed_guess_paths, &sources);
+}
+
+fn merge_into_kept(metadata: &mut Vec<MediaMetadata>, failed_guess_paths: &mut Vec<PathBuf>, sources: &[(&Duplicate, MediaMetadata)]) {
+    let mut by_kept: HashMap<&Path, Vec<&MediaMetadata>> = HashMap::new();
+    for (duplicate, meta) in sources {
+        by_kept.entry(duplicate.kept.as_path()).or_default().push(meta);
+    }
+    for kept in metadata.iter_mut() {
+        let Some(others) = by_kept.get(kept.media_path.as_path()) else { continue };
+        for other in others {
+            merge_metadata(kept, other);
+        }
+        if kept.exif_date.is_some() {
+            failed_guess_paths.retain(|path| *path != kept.media_path);
+        }
+    }
+    let removed: HashSet<&Path> = sources.iter().map(|(duplicate, _)| duplicate.path.as_path()).collect();
+    metadata.retain(|meta| !removed.contains(meta.media_path.as_path()));
+    failed_guess_paths.retain(|path| !removed.contains(path.as_path()));
+}
+
+fn merge_metadata(kept: &mut MediaMetadata, other: &MediaMetadata) {
+    if kept.json_path.as_os_str().is_empty() {
+        kept.json_path = other.json_path.clone();
+    }
+    // A date and its offset only make sense together
+    if kept.exif_date.is_none() {
+        kept.exif_date = other.exif_date.clone();
+        kept.exif_offset = other.exif_offset.clone();
+    }
+    // Coordinates only make sense together
+    if kept.gps_latitude.is_none() || kept.gps_longitude.is_none() {
+        kept.gps_latitude = other.gps_latitude;
+        kept.gps_longitude = other.gps_longitude;
+        kept.gps_altitude = other.gps_altitude;
+    }
+    kept.camera_make = kept.camera_make.take().or_else(|| other.camera_make.clone());
+    kept.camera_model = kept.camera_model.take().or_else(|| other.camera_model.clone());
+    kept.title = kept.title.take().or_else(|| other.title.clone());
+    kept.description = kept.description.take().or_else(|| other.description.clone());
+    for person in &other.people {
+        if !kept.people.contains(person) {
+            kept.people.push(person.clone());
+        }
+    }
+    for album in &other.albums {
+        if !kept.albums.contains(album) {
+            kept.albums.push(album.clone());
+        }
+    }
+    kept.favorited |= other.favorited;
+    kept.archived &= other.archived;
+    kept.trashed &= other.trashed;
+    kept.locked &= other.locked;
+}
+
+/// Finds the duplicates in the working folder, merges their metadata into the
+/// kept copies and takes them out of the working folder; with `keep_in` set
+/// (under `--transfer move`) they are moved into a `Duplicates` folder below it
+/// rather than deleted. Duplicates removed by an interrupted run are taken
+/// from `journal`. Returns every removed duplicate.
+pub fn remove_duplicates(
+    metadata: &mut Vec<MediaMetadata>,
+    failed_guess_paths: &mut Vec<PathBuf>,
+    working_dir: &Path,
+    keep_in: Option<&Path>,
+    logs_dir: &Path,
+    journal: &mut Journal,
+) -> Vec<Duplicate> {
+    let mut records: Vec<DuplicateRecord> = journal.entries(Stage::Dedup).into_iter()
+        .filter_map(|(_, data)| serde_json::from_value(data.clone()).ok())
+        .collect();
+    if !journal.is_stage_done(Stage::Dedup) {
+        let recorded: HashSet<PathBuf> = records.iter().map(|record| record.duplicate.path.clone()).collect();
+        let copies: HashMap<&Path, &MediaMetadata> = metadata.iter().map(|meta| (meta.media_path.as_path(), meta)).collect();
+        let mut found = Vec::new();
+        for duplicate in find_duplicates(metadata).into_iter().filter(|duplicate| !recorded.contains(&duplicate.path)) {
+            let Some(meta) = copies.get(duplicate.path.as_path()) else { continue };
+            let record = DuplicateRecord { duplicate, metadata: (*meta).clone() };
+            // Recorded before deleting, so the metadata survives a crash in between
+            if let Ok(data) = serde_json::to_value(&record) {
+                journal.record_with(Stage::Dedup, &record.duplicate.path, data);
+            }
+            found.push(record);
+        }
+        records.extend(found);
+    }
+    for Duplicate { path, kept, .. } in records.iter().map(|record| &record.duplicate) {
+        // Already gone when resuming an interrupted run
+        if path.exists() {
+            match leave_out(path, working_dir, keep_in.map(|dir| dir.join("Duplicates")).as_deref()) {
+                Ok(Some(dest)) => log_to_file(logs_dir, "dedup.log", &format!("✅ Removed {:?}: same contents as {:?}, moved to {:?}", path, kept, dest)),
+                Ok(None) => log_to_file(logs_dir, "dedup.log", &format!("✅ Removed {:?}: same contents as {:?}", path, kept)),
+                Err(e) => log_to_file(logs_dir, "dedup.log", &format!("❌ Failed to remove duplicate {:?}: {}", path, e)),
+            }
+        }
+    }
+    journal.finish_stage(Stage::Dedup);
+    let sources: Vec<(&Duplicate, MediaMetadata)> = records.iter()
+        .map(|record| (&record.duplicate, record.metadata.clone()))
+        .collect();
+    merge_into_kept(metadata, failed_guess_paths, &sources);
+    records.into_iter().map(|record| record.duplicate).collect()
+}
+
+/// Writes `duplicates.csv`: every removed duplicate with the copy that was kept.
+pub fn write_report(duplicates: &[Duplicate], path: &Path) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut wtr = csv::Writer::from_path(path)?;
+    wtr.write_record(["SL", "Removed", "Kept", "Bytes", "BLAKE3"])?;
+    for (i, duplicate) in duplicates.iter().enumerate() {
+        wtr.write_record([
+            (i + 1).to_string(),
+            duplicate.path.to_string_lossy().to_string(),
+            duplicate.kept.to_string_lossy().to_string(),
+            duplicate.size.to_string(),
+            duplicate.hash.clone(),
+        ])?;
+    }
+    wtr.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(path: &Path, contents: &str) -> MediaMetadata {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+        MediaMetadata { media_path: path.to_path_buf(), json_path: path.with_extension("jpg.json"), ..MediaMetadata::default() }
+    }
+
+    #[test]
+    fn only_files_with_the_same_size_and_hash_are_duplicates() {
+        let dir = tempfile::tempdir().unwrap();
+        let year = dir.path().join("Photos from 2021");
+        let metadata = vec![
+            write(&dir.path().join("Trip/IMG.jpg"), "photo"),
+            write(&year.join("IMG.jpg"), "photo"),
+            write(&year.join("other.jpg"), "PHOTO"),
+            write(&year.join("big.jpg"), "a bigger photo"),
+        ];
+        let duplicates = find_duplicates(&metadata);
+        assert_eq!(duplicates, [Duplicate {
+            path: dir.path().join("Trip/IMG.jpg"),
+            kept: year.join("IMG.jpg"),
+            size: 5,
+            hash: blake3::hash(b"photo").to_hex().to_string(),
+        }]);
+    }
+
+    #[test]
+    fn sizes_and_hashes_can_come_from_elsewhere() {
+        // Files that are not on disk, e.g. still inside an archive
+        let metadata: Vec<MediaMetadata> = ["a.jpg", "b.jpg", "c.jpg"].iter()
+            .map(|name| MediaMetadata { media_path: PathBuf::from(name), ..MediaMetadata::default() })
+            .collect();
+        let size = |_: &Path| Some(10);
+        let hash = |path: &Path| (path != Path::new("c.jpg")).then(|| "same".to_string());
+        let duplicates = find_duplicates_by(&metadata, size, hash);
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!((duplicates[0].path.as_path(), duplicates[0].kept.as_path()), (Path::new("b.jpg"), Path::new("a.jpg")));
+    }
+
+    #[test]
+    fn the_copy_with_a_json_in_a_year_folder_and_the_shortest_name_is_kept() {
+        let copy = |path: &str, json: bool| MediaMetadata {
+            media_path: PathBuf::from(path),
+            json_path: if json { PathBuf::from(format!("{}.json", path)) } else { PathBuf::new() },
+            ..MediaMetadata::default()
+        };
+        let mut copies = [
+            copy("Photos from 2021/IMG(1).jpg", true),
+            copy("Photos from 2021/IMG-edited.jpg", true),
+            copy("Trip/IMG.jpg", true),
+            copy("Photos from 2020/IMG.jpg", false),
+            copy("Photos from 2021/IMG.jpg", true),
+        ];
+        copies.sort_by_key(keep_order);
+        let order: Vec<&Path> = copies.iter().map(|meta| meta.media_path.as_path()).collect();
+        assert_eq!(order, [
+            Path::new("Photos from 2021/IMG.jpg"),
+            Path::new("Photos from 2021/IMG(1).jpg"),
+            Path::new("Photos from 2021/IMG-edited.jpg"),
+            Path::new("Trip/IMG.jpg"),
+            Path::new("Photos from 2020/IMG.jpg"),
+        ]);
+    }
+
+    #[test]
+    fn metadata_of_duplicates_is_merged_into_the_kept_copy() {
+        let (kept, other) = (PathBuf::from("Photos from 2021/IMG.jpg"), PathBuf::from("Trip/IMG.jpg"));
+        let mut metadata = vec![
+            MediaMetadata {
+                media_path: kept.clone(),
+                gps_latitude: Some(1.0),
+                people: vec!["Alex".to_string()],
+                albums: vec!["Trip".to_string()],
+                archived: true,
+                trashed: true,
+                ..MediaMetadata::default()
+            },
+            MediaMetadata {
+                media_path: other.clone(),
+                json_path: PathBuf::from("Trip/IMG.jpg.json"),
+                exif_date: Some("2021:05:01 10:30:00".to_string()),
+                exif_offset: Some("+02:00".to_string()),
+                gps_latitude: Some(2.0),
+                gps_longitude: Some(3.0),
+                camera_model: Some("Pixel 5".to_string()),
+                people: vec!["Alex".to_string(), "Sam".to_string()],
+                albums: vec!["Trip".to_string(), "Best of".to_string()],
+                favorited: true,
+                archived: true,
+                ..MediaMetadata::default()
+            },
+        ];
+        let mut failed_guess_paths = vec![kept.clone(), other.clone()];
+        let duplicates = [Duplicate { path: other, kept: kept.clone(), size: 1, hash: String::new() }];
+        merge_duplicates(&mut metadata, &mut failed_guess_paths, &duplicates);
+
+        assert_eq!(metadata.len(), 1);
+        let meta = &metadata[0];
+        assert_eq!(meta.json_path, Path::new("Trip/IMG.jpg.json"));
+        assert_eq!((meta.exif_date.as_deref(), meta.exif_offset.as_deref()), (Some("2021:05:01 10:30:00"), Some("+02:00")));
+        // Half a position is replaced by a whole one
+        assert_eq!((meta.gps_latitude, meta.gps_longitude), (Some(2.0), Some(3.0)));
+        assert_eq!(meta.camera_model.as_deref(), Some("Pixel 5"));
+        assert_eq!(meta.people, ["Alex", "Sam"]);
+        assert_eq!(meta.albums, ["Trip", "Best of"]);
+        assert!(meta.favorited && meta.archived && !meta.trashed);
+        // The kept copy now has a date
+        assert!(failed_guess_paths.is_empty());
+    }
+
+    #[test]
+    fn duplicates_removed_by_an_interrupted_run_are_taken_from_the_journal() {
+        let dir = tempfile::tempdir().unwrap();
+        let (working_dir, output, logs) = (dir.path().join("work"), dir.path().join("out"), dir.path().join("logs"));
+        let kept = write(&working_dir.join("Photos from 2021/IMG.jpg"), "photo");
+        let mut removed = write(&working_dir.join("Trip/IMG.jpg"), "photo");
+        removed.favorited = true;
+
+        // The interrupted run journalled the duplicate and deleted it
+        let mut journal = Journal::open(&output).unwrap();
+        let duplicate = find_duplicates(&[kept.clone(), removed.clone()]).remove(0);
+        let record = DuplicateRecord { duplicate: duplicate.clone(), metadata: removed.clone() };
+        journal.record_with(Stage::Dedup, &duplicate.path, serde_json::to_value(&record).unwrap());
+        fs::remove_file(&removed.media_path).unwrap();
+
+        let mut journal = Journal::open(&output).unwrap();
+        let mut metadata = vec![kept, removed];
+        let found = remove_duplicates(&mut metadata, &mut Vec::new(), &working_dir, None, &logs, &mut journal);
+        assert_eq!(found, [duplicate]);
+        assert_eq!(metadata.len(), 1);
+        assert!(metadata[0].favorited);
+        assert!(journal.is_stage_done(Stage::Dedup));
+        assert!(!logs.join("dedup.log").exists());
+    }
+}
@@ -0,0 +1,173 @@
+// edited.rs
+// Edited variant handling for MetaSort_v1.0.0 – Google Photos Takeout Organizer
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::metadata_extraction::MediaMetadata;
+use crate::transfer::leave_out;
+use crate::utils::log_to_file;
+
+/// File name suffixes Google Photos gives edited copies, per export language.
+/// Both composed and decomposed (macOS) forms of accented suffixes are listed.
+pub const EDITED_SUFFIXES: &[&str] = &[
+    "-edited", "-bearbeitet", "-modifié", "-modifie\u{301}", "-modificato", "-editado", "-bewerkt",
+    "-edytowane", "-editat", "-redigeret", "-redigert", "-redigerad", "-muokattu", "-upraveno", "-編集済み",
+];
+
+/// Which versions of a photo that was edited in Google Photos are kept.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EditedPolicy {
+    /// Keep the original and the edited copy
+    #[default]
+    KeepBoth,
+    /// Keep only the edited copy of edited photos
+    EditedOnly,
+    /// Keep only the original of edited photos
+    OriginalOnly,
+}
+
+/// The stem of the original of an edited copy, e.g. `IMG_1234` for
+/// `IMG_1234-edited.jpg`, or `None` if the file is not an edited copy.
+pub fn original_stem(path: &Path) -> Option<String> {
+    let stem = path.file_stem()?.to_str()?;
+    EDITED_SUFFIXES.iter().find_map(|suffix| {
+        let at = stem.len().checked_sub(suffix.len())?;
+        let tail = stem.get(at..)?;
+        (at > 0 && tail.to_lowercase() == *suffix).then(|| stem[..at].to_string())
+    })
+}
+
+pub fn is_edited(path: &Path) -> bool {
+    original_stem(path).is_some()
+}
+
+/// Files to leave out under `policy`. Edited copies share their original's
+/// JSON, which is how the two are matched; an edited copy without its
+/// original, or an original without an edited copy, is always kept.
+pub fn dropped_by_policy(metadata: &[MediaMetadata], policy: EditedPolicy) -> HashSet<PathBuf> {
+    if policy == EditedPolicy::KeepBoth {
+        return HashSet::new();
+    }
+    let mut by_json: HashMap<&Path, (Vec<&Path>, Vec<&Path>)> = HashMap::new();
+    for meta in metadata.iter().filter(|meta| !meta.json_path.as_os_str().is_empty()) {
+        let (originals, edited) = by_json.entry(meta.json_path.as_path()).or_default();
+        if is_edited(&meta.media_path) {
+            edited.push(&meta.media_path);
+        } else {
+            originals.push(&meta.media_path);
+        }
+    }
+    by_json.into_values()
+        .filter(|(originals, edited)| !originals.is_empty() && !edited.is_empty())
+        .flat_map(|(originals, edited)| if policy == EditedPolicy::EditedOnly { originals } else { edited })
+        .map(Path::to_path_buf)
+        .collect()
+}
+
+/// Drops the files `policy` leaves out from `metadata` and takes them out of
+/// the working folder; with `keep_in` set (under `--transfer move`) they are
+/// moved into an `Edited` folder below it rather than deleted. Returns how
+/// many were dropped.
+pub fn apply_edited_policy(
+    metadata: &mut Vec<MediaMetadata>,
+    policy: EditedPolicy,
+    working_dir: &Path,
+    keep_in: Option<&Path>,
+    logs_dir: &Path,
+) -> usize {
+    let dropped = dropped_by_policy(metadata, policy);
+    metadata.retain(|meta| !dropped.contains(&meta.media_path));
+    for path in &dropped {
+        // Already gone when resuming an interrupted run
+        if path.exists() {
+            match leave_out(path, working_dir, keep_in.map(|dir| dir.join("Edited")).as_deref()) {
+                Ok(Some(dest)) => log_to_file(logs_dir, "edited.log", &format!("✅ Left out {:?} ({:?}), moved to {:?}", path, policy, dest)),
+                Ok(None) => log_to_file(logs_dir, "edited.log", &format!("✅ Left out {:?} ({:?})", path, policy)),
+                Err(e) => log_to_file(logs_dir, "edited.log", &format!("❌ Failed to leave out {:?}: {}", path, e)),
+            }
+        }
+    }
+    dropped.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn meta(media: &Path, json: &Path) -> MediaMetadata {
+        MediaMetadata { media_path: media.to_path_buf(), json_path: json.to_path_buf(), ..MediaMetadata::default() }
+    }
+
+    /// An original, its edited copy, an edited copy whose original is missing
+    /// and an unedited photo, as `(metadata, [original, edited, lone edit, other])`.
+    fn library(dir: &Path) -> (Vec<MediaMetadata>, [PathBuf; 4]) {
+        let paths = ["IMG_1.jpg", "IMG_1-edited.jpg", "IMG_2-edited.jpg", "IMG_3.jpg"].map(|name| dir.join(name));
+        for path in &paths {
+            fs::write(path, "photo").unwrap();
+        }
+        let metadata = vec![
+            meta(&paths[0], &dir.join("IMG_1.jpg.json")),
+            meta(&paths[1], &dir.join("IMG_1.jpg.json")),
+            meta(&paths[2], &dir.join("IMG_2.jpg.json")),
+            meta(&paths[3], &dir.join("IMG_3.jpg.json")),
+        ];
+        (metadata, paths)
+    }
+
+    #[test]
+    fn edited_suffixes_of_every_language_are_recognized() {
+        for suffix in EDITED_SUFFIXES {
+            let name = format!("IMG_1234{}.jpg", suffix);
+            assert_eq!(original_stem(Path::new(&name)).as_deref(), Some("IMG_1234"), "{}", name);
+            let upper = format!("IMG_1234{}.JPG", suffix.to_uppercase());
+            assert_eq!(original_stem(Path::new(&upper)).as_deref(), Some("IMG_1234"), "{}", upper);
+        }
+        assert_eq!(original_stem(Path::new("Photos/PXL_2021.MP-bearbeitet.jpg")).as_deref(), Some("PXL_2021.MP"));
+        assert_eq!(original_stem(Path::new("-edited.jpg")), None);
+        assert_eq!(original_stem(Path::new("IMG_1234-edited-1.jpg")), None);
+        assert_eq!(original_stem(Path::new("IMG_1234.jpg")), None);
+        assert!(!is_edited(Path::new("edited.jpg")));
+    }
+
+    #[test]
+    fn originals_and_edits_are_matched_by_their_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut metadata, [original, edited, lone_edit, _]) = library(dir.path());
+        // A file without a JSON, like an extracted Motion Photo clip, is never matched
+        metadata.push(MediaMetadata { media_path: dir.path().join("IMG_1-edited.mp4"), ..MediaMetadata::default() });
+
+        assert!(dropped_by_policy(&metadata, EditedPolicy::KeepBoth).is_empty());
+        assert_eq!(dropped_by_policy(&metadata, EditedPolicy::EditedOnly), HashSet::from([original]));
+        assert_eq!(dropped_by_policy(&metadata, EditedPolicy::OriginalOnly), HashSet::from([edited]));
+        assert!(!dropped_by_policy(&metadata, EditedPolicy::OriginalOnly).contains(&lone_edit));
+    }
+
+    #[test]
+    fn each_policy_leaves_out_its_files() {
+        for (policy, dropped) in [(EditedPolicy::KeepBoth, None), (EditedPolicy::EditedOnly, Some(0)), (EditedPolicy::OriginalOnly, Some(1))] {
+            let dir = tempfile::tempdir().unwrap();
+            let (mut metadata, paths) = library(dir.path());
+            let count = apply_edited_policy(&mut metadata, policy, dir.path(), None, &dir.path().join("logs"));
+            assert_eq!(count, dropped.map_or(0, |_| 1), "{:?}", policy);
+            assert_eq!(metadata.len(), 4 - count);
+            for (i, path) in paths.iter().enumerate() {
+                assert_eq!(path.exists(), dropped != Some(i), "{:?} {:?}", policy, path);
+                assert_eq!(metadata.iter().any(|meta| &meta.media_path == path), dropped != Some(i));
+            }
+        }
+    }
+
+    #[test]
+    fn left_out_files_are_moved_when_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let (working_dir, left_out) = (dir.path().join("work"), dir.path().join("Left Out"));
+        fs::create_dir_all(&working_dir).unwrap();
+        let (mut metadata, paths) = library(&working_dir);
+        apply_edited_policy(&mut metadata, EditedPolicy::OriginalOnly, &working_dir, Some(&left_out), &dir.path().join("logs"));
+        assert!(!paths[1].exists());
+        assert_eq!(fs::read_to_string(left_out.join("Edited/IMG_1-edited.jpg")).unwrap(), "photo");
+    }
+}
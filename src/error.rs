@@ -0,0 +1,69 @@
+// error.rs
+// Error types for MetaSort_v1.0.0 – Google Photos Takeout Organizer
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Errors returned by the MetaSort library.
+#[derive(Debug)]
+pub enum Error {
+    /// exiftool could not be run.
+    ExifToolMissing,
+    /// No output folder was given.
+    MissingOutput,
+    /// The input folder does not exist or is not a folder.
+    InputNotFound(PathBuf),
+    /// An input is neither a folder nor a supported archive.
+    UnsupportedInput(PathBuf),
+    /// A Takeout archive could not be read.
+    Archive { path: PathBuf, message: String },
+    /// A config file could not be parsed or serialized.
+    Config { path: PathBuf, message: String },
+    /// A filesystem operation failed.
+    Io { path: PathBuf, source: io::Error },
+    /// Copying the input into the working folder failed.
+    Copy { path: PathBuf, message: String },
+    /// A file would land on a name that is taken, with the `fail` collision strategy.
+    Collision { path: PathBuf, existing: PathBuf },
+    /// A folder layout or file name template is invalid.
+    Layout { template: String, message: String },
+    /// A time zone is neither an IANA zone name nor a UTC offset.
+    TimeZone(String),
+}
+
+impl Error {
+    pub(crate) fn io(path: impl Into<PathBuf>, source: io::Error) -> Self {
+        Error::Io { path: path.into(), source }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::ExifToolMissing => write!(f, "ExifTool is not installed or not found in PATH"),
+            Error::MissingOutput => write!(f, "No output folder was specified"),
+            Error::InputNotFound(path) => write!(f, "Input folder {:?} does not exist", path),
+            Error::UnsupportedInput(path) => write!(f, "{:?} is neither a folder nor a .zip/.tgz/.tar archive", path),
+            Error::Archive { path, message } => write!(f, "Failed to read archive {:?}: {}", path, message),
+            Error::Config { path, message } => write!(f, "Invalid config {:?}: {}", path, message),
+            Error::Io { path, source } => write!(f, "{:?}: {}", path, source),
+            Error::Copy { path, message } => write!(f, "Failed to copy {:?}: {}", path, message),
+            Error::Collision { path, existing } => write!(f, "Cannot move {:?}: {:?} already exists", path, existing),
+            Error::Layout { template, message } => write!(f, "Invalid template {:?}: {}", template, message),
+            Error::TimeZone(zone) => write!(f, "Unknown time zone {:?}; use a name like Europe/Berlin or an offset like +02:00", zone),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Result alias used throughout the library.
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,298 @@
+// exiftool.rs
+// Persistent exiftool session for MetaSort_v1.0.0 – Google Photos Takeout Organizer
+
+use std::cell::RefCell;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use crate::platform::get_exiftool_command;
+
+/// Output of one exiftool command run in a session.
+#[derive(Debug, Clone, Default)]
+pub struct ExifToolOutput {
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl ExifToolOutput {
+    /// True unless exiftool reported an error for the file. Warnings and
+    /// "files unchanged" count as success, like exiftool's own exit status.
+    pub fn is_success(&self) -> bool {
+        !self.stderr.lines().any(|line| line.starts_with("Error"))
+            && !self.stdout.contains("weren't updated due to errors")
+    }
+
+    /// First error line reported by exiftool, if any.
+    pub fn error(&self) -> Option<&str> {
+        self.stderr.lines().find(|line| line.starts_with("Error")).map(str::trim)
+    }
+}
+
+/// A long-running `exiftool -stay_open True -@ -` process. Commands are sent
+/// as argument lines terminated by `-execute<n>`, and the output of each
+/// command ends with a `{ready<n>}` marker on stdout and, through `-echo4`,
+/// on stderr, so results are matched to the file they belong to. Stderr is
+/// drained on a thread of its own, so exiftool never stalls on a full pipe
+/// while stdout is being read.
+///
+/// Starting exiftool costs far more than processing one file, so a session is
+/// kept per thread for the whole run; see [`execute`].
+pub struct ExifTool {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    stderr: Receiver<String>,
+    next_id: u64,
+}
+
+impl ExifTool {
+    pub fn start() -> io::Result<ExifTool> {
+        let mut child = get_exiftool_command()
+            // File names are passed as UTF-8, also on Windows
+            .args(["-stay_open", "True", "-@", "-", "-common_args", "-charset", "filename=utf8"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let pipe_error = || io::Error::other("exiftool pipes are not available");
+        let stdin = child.stdin.take().ok_or_else(pipe_error)?;
+        let stdout = BufReader::new(child.stdout.take().ok_or_else(pipe_error)?);
+        let mut pipe = BufReader::new(child.stderr.take().ok_or_else(pipe_error)?);
+        let (sender, stderr) = mpsc::channel();
+        thread::spawn(move || {
+            while let Ok(Some(line)) = read_line(&mut pipe) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(ExifTool { child, stdin, stdout, stderr, next_id: 1 })
+    }
+
+    /// Runs one exiftool command (the arguments of a normal invocation) and
+    /// waits for its output.
+    pub fn execute<S: AsRef<OsStr>>(&mut self, args: &[S]) -> io::Result<ExifToolOutput> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let marker = format!("{{ready{}}}", id);
+        let mut command = String::new();
+        for arg in args {
+            // One argument per line; a line break inside a value would split it
+            let arg = arg.as_ref().to_string_lossy().replace(['\r', '\n'], " ");
+            command.push_str(&arg);
+            command.push('\n');
+        }
+        command.push_str(&format!("-echo4\n{}\n-execute{}\n", marker, id));
+        self.stdin.write_all(command.as_bytes())?;
+        self.stdin.flush()?;
+        let stdout = read_until_marker(|| read_line(&mut self.stdout), &marker)?;
+        let stderr = read_until_marker(|| Ok(self.stderr.recv().ok()), &marker)?;
+        Ok(ExifToolOutput { stdout, stderr })
+    }
+
+    /// Asks exiftool to exit and waits for it.
+    pub fn close(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        let _ = self.stdin.write_all(b"-stay_open\nFalse\n");
+        let _ = self.stdin.flush();
+        let _ = self.child.wait();
+    }
+}
+
+impl Drop for ExifTool {
+    fn drop(&mut self) {
+        if let Ok(None) = self.child.try_wait() {
+            self.shutdown();
+        }
+    }
+}
+
+/// One line of output with its line break, or `None` at the end.
+fn read_line(reader: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut line = Vec::new();
+    Ok((reader.read_until(b'\n', &mut line)? > 0).then(|| String::from_utf8_lossy(&line).into_owned()))
+}
+
+fn read_until_marker(mut next_line: impl FnMut() -> io::Result<Option<String>>, marker: &str) -> io::Result<String> {
+    let mut output = String::new();
+    loop {
+        let line = next_line()?.ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "exiftool exited unexpectedly"))?;
+        if line.trim_end() == marker {
+            return Ok(output);
+        }
+        output.push_str(&line);
+    }
+}
+
+thread_local! {
+    static SESSION: RefCell<Option<ExifTool>> = const { RefCell::new(None) };
+}
+
+/// Runs one command on this thread's persistent exiftool session, starting it
+/// on first use. A session that died is restarted once for the next command.
+pub fn execute<S: AsRef<OsStr>>(args: &[S]) -> io::Result<ExifToolOutput> {
+    SESSION.with(|session| {
+        let mut session = session.borrow_mut();
+        if session.is_none() {
+            *session = Some(ExifTool::start()?);
+        }
+        let result = session.as_mut().map_or_else(|| Err(io::Error::other("no exiftool session")), |s| s.execute(args));
+        if result.is_err() {
+            *session = None;
+        }
+        result
+    })
+}
+
+/// Runs one command on the file at `path`. Arguments are sent one per line,
+/// so a file whose path has a line break is renamed for the command, below
+/// the nearest folder whose path has none, and renamed back afterwards.
+pub fn execute_on<S: AsRef<OsStr>>(args: &[S], path: &Path) -> io::Result<ExifToolOutput> {
+    with_safe_path(path, |path| {
+        let mut args: Vec<&OsStr> = args.iter().map(AsRef::as_ref).collect();
+        args.push(path.as_os_str());
+        execute(&args)
+    })
+}
+
+/// Calls `run` with `path`, or with a temporary name without line breaks if
+/// `path` has one. The file gets its name back whatever `run` returns, and
+/// also if it panics.
+fn with_safe_path<T>(path: &Path, run: impl FnOnce(&Path) -> io::Result<T>) -> io::Result<T> {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    let has_break = |path: &Path| path.to_string_lossy().contains(['\r', '\n']);
+    if !has_break(path) {
+        return run(path);
+    }
+    let folder = path.ancestors().skip(1).find(|folder| !has_break(folder)).unwrap_or(Path::new(""));
+    let mut name = format!(".metasort-{}-{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed));
+    if let Some(ext) = path.extension() {
+        // exiftool tells some formats apart by their extension
+        name = format!("{}.{}", name, ext.to_string_lossy().replace(['\r', '\n'], ""));
+    }
+    let mut renamed = TempName::rename(path, folder.join(name))?;
+    let result = run(&renamed.temp);
+    renamed.restore()?;
+    result
+}
+
+/// A file moved to a temporary name, moved back when dropped.
+struct TempName<'a> {
+    original: &'a Path,
+    temp: PathBuf,
+    restored: bool,
+}
+
+impl<'a> TempName<'a> {
+    fn rename(original: &'a Path, temp: PathBuf) -> io::Result<TempName<'a>> {
+        fs::rename(original, &temp)?;
+        Ok(TempName { original, temp, restored: false })
+    }
+
+    /// Moves the file back; the error says where it was left.
+    fn restore(&mut self) -> io::Result<()> {
+        fs::rename(&self.temp, self.original).map_err(|e| {
+            io::Error::new(e.kind(), format!("could not rename {:?} back to {:?}: {}", self.temp, self.original, e))
+        })?;
+        self.restored = true;
+        Ok(())
+    }
+}
+
+impl Drop for TempName<'_> {
+    fn drop(&mut self) {
+        if !self.restored {
+            let _ = fs::rename(&self.temp, self.original);
+        }
+    }
+}
+
+/// Stops this thread's exiftool session, if one is running.
+pub fn close() {
+    SESSION.with(|session| {
+        if let Some(exiftool) = session.borrow_mut().take() {
+            exiftool.close();
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::panic;
+
+    #[test]
+    fn output_is_split_at_the_ready_markers() {
+        // Two commands in a row, the first with a line that only starts like a marker
+        let mut stdout = Cursor::new("[{\"SourceFile\": \"a.jpg\"}]\n{ready1} not a marker\n{ready1}\n    1 image files updated\n{ready2}\r\n");
+        let mut next = || read_line(&mut stdout);
+        assert_eq!(read_until_marker(&mut next, "{ready1}").unwrap(), "[{\"SourceFile\": \"a.jpg\"}]\n{ready1} not a marker\n");
+        assert_eq!(read_until_marker(&mut next, "{ready2}").unwrap(), "    1 image files updated\n");
+        // exiftool exited before the marker
+        let error = read_until_marker(&mut next, "{ready3}").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+
+        // Stderr lines as they come from the draining thread, the last without a line break
+        let mut lines = vec!["Warning: [minor] Bad MakerNotes\n", "Error: File not found - x.jpg\n", "{ready7}"].into_iter();
+        let stderr = read_until_marker(|| Ok(lines.next().map(str::to_string)), "{ready7}").unwrap();
+        let output = ExifToolOutput { stdout: String::new(), stderr };
+        assert!(!output.is_success());
+        assert_eq!(output.error(), Some("Error: File not found - x.jpg"));
+    }
+
+    #[test]
+    fn paths_with_line_breaks_are_renamed_for_the_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = dir.path().join("Trip\n2021");
+        fs::create_dir(&folder).unwrap();
+        let path = folder.join("IMG\n1.jpg");
+        fs::write(&path, "photo").unwrap();
+
+        let seen = with_safe_path(&path, |temp| {
+            assert!(!temp.to_string_lossy().contains('\n'));
+            assert_eq!(temp.parent(), Some(dir.path()));
+            assert_eq!(temp.extension().and_then(OsStr::to_str), Some("jpg"));
+            assert_eq!(fs::read_to_string(temp)?, "photo");
+            Ok(temp.to_path_buf())
+        }).unwrap();
+        assert!(!seen.exists());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "photo");
+
+        let plain = dir.path().join("IMG_2.jpg");
+        assert_eq!(with_safe_path(&plain, |temp| Ok(temp.to_path_buf())).unwrap(), plain);
+    }
+
+    #[test]
+    fn renamed_files_get_their_name_back_on_errors_and_panics() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("IMG\r\n1.jpg");
+        fs::write(&path, "photo").unwrap();
+
+        let error = with_safe_path(&path, |_| Err::<(), _>(io::Error::other("exiftool died"))).unwrap_err();
+        assert_eq!(error.to_string(), "exiftool died");
+        assert!(path.exists());
+
+        let panicked = panic::catch_unwind(|| with_safe_path(&path, |_| -> io::Result<()> { panic!("bug") }));
+        assert!(panicked.is_err());
+        assert!(path.exists());
+
+        // The name is taken meanwhile: the error says where the file is
+        let mut temp = PathBuf::new();
+        let error = with_safe_path(&path, |renamed| {
+            temp = renamed.to_path_buf();
+            fs::create_dir(&path)
+        }).unwrap_err();
+        assert!(error.to_string().contains(&format!("{:?}", temp)), "{}", error);
+        assert!(temp.exists());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+}
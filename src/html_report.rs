@@ -4,6 +4,7 @@ use std::path::Path;
 use std::path::PathBuf;
 use url::Url;
 
+#[allow(clippy::too_many_arguments)]
 pub fn generate_html_report(
     output_dir: &Path,
     total: usize,
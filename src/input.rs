@@ -0,0 +1,48 @@
+// input.rs
+// Input sources for MetaSort_v1.0.0 – Google Photos Takeout Organizer
+
+use std::path::{Path, PathBuf};
+use crate::archive::{archive_kind, archives_in};
+use crate::error::{Error, Result};
+
+/// One place Takeout data is read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputSource {
+    /// An extracted Takeout folder.
+    Directory(PathBuf),
+    /// A `takeout-*.zip` / `.tgz` part, read without extracting it by hand.
+    Archive(PathBuf),
+}
+
+impl InputSource {
+    pub fn path(&self) -> &Path {
+        match self {
+            InputSource::Directory(path) | InputSource::Archive(path) => path,
+        }
+    }
+}
+
+/// Turns the paths given by the user into input sources. An archive file is
+/// read as an archive; a folder that directly contains archives stands for
+/// those archives; any other folder is read as an extracted Takeout.
+pub fn resolve_inputs(paths: &[PathBuf]) -> Result<Vec<InputSource>> {
+    let mut sources = Vec::new();
+    for path in paths {
+        if path.is_file() {
+            if archive_kind(path).is_none() {
+                return Err(Error::UnsupportedInput(path.clone()));
+            }
+            sources.push(InputSource::Archive(path.clone()));
+        } else if path.is_dir() {
+            let archives = archives_in(path);
+            if archives.is_empty() {
+                sources.push(InputSource::Directory(path.clone()));
+            } else {
+                sources.extend(archives.into_iter().map(InputSource::Archive));
+            }
+        } else {
+            return Err(Error::InputNotFound(path.clone()));
+        }
+    }
+    Ok(sources)
+}
@@ -0,0 +1,109 @@
+// item_status.rs
+// Archived, trashed and locked-folder handling for MetaSort_v1.0.0 – Google Photos Takeout Organizer
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::metadata_extraction::MediaMetadata;
+use crate::transfer::leave_out;
+use crate::utils::log_to_file;
+
+/// What to do with files Google Photos had archived, trashed or locked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StatusPolicy {
+    /// Sort them like every other file
+    Sort,
+    /// Sort them into their own folder ('Archived', 'Trash' or 'Locked Folder')
+    Separate,
+    /// Leave them out of the output
+    Exclude,
+}
+
+/// The policy for each flag. By default archived files are sorted normally
+/// and trashed or locked ones are kept apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatusPolicies {
+    pub archived: StatusPolicy,
+    pub trashed: StatusPolicy,
+    pub locked: StatusPolicy,
+}
+
+impl Default for StatusPolicies {
+    fn default() -> Self {
+        Self { archived: StatusPolicy::Sort, trashed: StatusPolicy::Separate, locked: StatusPolicy::Separate }
+    }
+}
+
+/// Folders under `Media Files` that separated files are sorted into.
+pub const STATUS_FOLDERS: [&str; 3] = ["Trash", "Locked Folder", "Archived"];
+
+impl StatusPolicies {
+    /// The status folder of a file and the policy for it. A file with several
+    /// flags counts as trashed first, then locked, then archived, skipping
+    /// flags whose policy is `sort`; `None` means it is sorted normally.
+    pub fn status_of(&self, meta: &MediaMetadata) -> Option<(&'static str, StatusPolicy)> {
+        [
+            (meta.trashed, STATUS_FOLDERS[0], self.trashed),
+            (meta.locked, STATUS_FOLDERS[1], self.locked),
+            (meta.archived, STATUS_FOLDERS[2], self.archived),
+        ]
+        .into_iter()
+        .find(|(flagged, _, policy)| *flagged && *policy != StatusPolicy::Sort)
+        .map(|(_, folder, policy)| (folder, policy))
+    }
+}
+
+/// Drops excluded files from `metadata` and takes them out of the working
+/// folder, so they are neither embedded nor sorted. With `keep_in` set (under
+/// `--transfer move`) they are moved into a folder per status below it rather
+/// than deleted. Returns the status folder of every file that is sorted
+/// separately, by path, and how many files were excluded.
+pub fn apply_status_policies(
+    metadata: &mut Vec<MediaMetadata>,
+    policies: &StatusPolicies,
+    working_dir: &Path,
+    keep_in: Option<&Path>,
+    logs_dir: &Path,
+) -> (HashMap<PathBuf, &'static str>, usize) {
+    let mut separated = HashMap::new();
+    let mut excluded = 0;
+    metadata.retain(|meta| match policies.status_of(meta) {
+        Some((folder, StatusPolicy::Separate)) => {
+            separated.insert(meta.media_path.clone(), folder);
+            true
+        }
+        Some((folder, _)) => {
+            // Already gone when resuming an interrupted run
+            if meta.media_path.exists() {
+                match leave_out(&meta.media_path, working_dir, keep_in.map(|dir| dir.join(folder)).as_deref()) {
+                    Ok(Some(dest)) => log_to_file(logs_dir, "item_status.log", &format!("✅ Excluded {:?} ({}), moved to {:?}", meta.media_path, folder, dest)),
+                    Ok(None) => log_to_file(logs_dir, "item_status.log", &format!("✅ Excluded {:?} ({})", meta.media_path, folder)),
+                    Err(e) => log_to_file(logs_dir, "item_status.log", &format!("❌ Failed to exclude {:?} ({}): {}", meta.media_path, folder, e)),
+                }
+            }
+            excluded += 1;
+            false
+        }
+        None => true,
+    });
+    for (path, folder) in &separated {
+        log_to_file(logs_dir, "item_status.log", &format!("Sorting {:?} into '{}'", path, folder));
+    }
+    (separated, excluded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_sorted_normally_do_not_hide_later_ones() {
+        let meta = MediaMetadata { trashed: true, archived: true, ..MediaMetadata::default() };
+        let policies = StatusPolicies { archived: StatusPolicy::Exclude, trashed: StatusPolicy::Sort, locked: StatusPolicy::Separate };
+        assert_eq!(policies.status_of(&meta), Some(("Archived", StatusPolicy::Exclude)));
+        let policies = StatusPolicies { trashed: StatusPolicy::Separate, ..policies };
+        assert_eq!(policies.status_of(&meta), Some(("Trash", StatusPolicy::Separate)));
+        assert_eq!(StatusPolicies::default().status_of(&MediaMetadata { archived: true, ..MediaMetadata::default() }), None);
+    }
+}
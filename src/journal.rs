@@ -0,0 +1,143 @@
+// journal.rs
+// Persistent job journal for resumable MetaSort_v1.0.0 runs – Google Photos Takeout Organizer
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::error::{Error, Result};
+
+/// Name of the journal file kept in the output folder while a run is in progress.
+pub const JOURNAL_NAME: &str = "MetaSort_journal.jsonl";
+
+/// Pipeline stages that record their progress in the journal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    Copy,
+    JsonCleaning,
+    Separation,
+    Albums,
+    Extraction,
+    Dedup,
+    Embedding,
+    Sorting,
+}
+
+/// Key used to mark a whole stage as finished.
+const STAGE_DONE: &str = "*";
+
+#[derive(Serialize, Deserialize)]
+struct Record {
+    stage: Stage,
+    file: String,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    data: Value,
+}
+
+/// Append-only record of the files each stage has finished, so that an
+/// interrupted run can be restarted without redoing completed work.
+///
+/// Every record is one JSON line, flushed as soon as it is written. A record
+/// may carry data (e.g. extracted metadata) that a resumed run reuses instead
+/// of recomputing it.
+#[derive(Debug, Default)]
+pub struct Journal {
+    path: Option<PathBuf>,
+    file: Option<File>,
+    done: HashMap<(Stage, String), Value>,
+}
+
+impl Journal {
+    /// Opens the journal in `output_dir`, loading the records of a previous run.
+    pub fn open(output_dir: &Path) -> Result<Journal> {
+        fs::create_dir_all(output_dir).map_err(|e| Error::io(output_dir, e))?;
+        let path = output_dir.join(JOURNAL_NAME);
+        let mut done = HashMap::new();
+        if let Ok(existing) = File::open(&path) {
+            // A line cut short by a crash is ignored; its file is simply redone
+            for line in BufReader::new(existing).lines().map_while(|l| l.ok()) {
+                if let Ok(record) = serde_json::from_str::<Record>(&line) {
+                    done.insert((record.stage, record.file), record.data);
+                }
+            }
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| Error::io(&path, e))?;
+        Ok(Journal { path: Some(path), file: Some(file), done })
+    }
+
+    /// A journal that records nothing on disk, for dry runs and library callers
+    /// that do not need resuming.
+    pub fn disabled() -> Journal {
+        Journal::default()
+    }
+
+    /// True when records of an earlier, interrupted run were loaded.
+    pub fn is_resumed(&self) -> bool {
+        !self.done.is_empty()
+    }
+
+    pub fn is_done(&self, stage: Stage, file: &Path) -> bool {
+        self.done.contains_key(&(stage, key(file)))
+    }
+
+    /// Data stored with a finished file, if any.
+    pub fn data(&self, stage: Stage, file: &Path) -> Option<&Value> {
+        self.done.get(&(stage, key(file))).filter(|v| !v.is_null())
+    }
+
+    pub fn record(&mut self, stage: Stage, file: &Path) {
+        self.record_with(stage, file, Value::Null);
+    }
+
+    /// Marks a file as finished for `stage`, storing `data` for a resumed run.
+    pub fn record_with(&mut self, stage: Stage, file: &Path, data: Value) {
+        self.write(Record { stage, file: key(file), data });
+    }
+
+    /// Files finished for `stage` with the data stored for them, sorted by path.
+    pub fn entries(&self, stage: Stage) -> Vec<(&str, &Value)> {
+        let mut entries: Vec<(&str, &Value)> = self.done.iter()
+            .filter(|((s, file), data)| *s == stage && file != STAGE_DONE && !data.is_null())
+            .map(|((_, file), data)| (file.as_str(), data))
+            .collect();
+        entries.sort_by_key(|(file, _)| *file);
+        entries
+    }
+
+    pub fn is_stage_done(&self, stage: Stage) -> bool {
+        self.done.contains_key(&(stage, STAGE_DONE.to_string()))
+    }
+
+    pub fn finish_stage(&mut self, stage: Stage) {
+        self.write(Record { stage, file: STAGE_DONE.to_string(), data: Value::Null });
+    }
+
+    /// Deletes the journal file once a run has completed.
+    pub fn remove(mut self) {
+        self.file = None;
+        if let Some(path) = &self.path {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    fn write(&mut self, record: Record) {
+        if let Some(file) = &mut self.file {
+            if let Ok(line) = serde_json::to_string(&record) {
+                let _ = writeln!(file, "{}", line);
+                let _ = file.flush();
+            }
+        }
+        self.done.insert((record.stage, record.file), record.data);
+    }
+}
+
+fn key(file: &Path) -> String {
+    file.to_string_lossy().to_string()
+}
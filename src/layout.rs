@@ -0,0 +1,442 @@
+// layout.rs
+// Output folder layout templates for MetaSort_v1.0.0 – Google Photos Takeout Organizer
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{Datelike, NaiveDateTime};
+use crate::error::{Error, Result};
+use crate::locale::Locale;
+
+/// The layout of earlier versions: `Photos/2021/May`.
+pub const DEFAULT_LAYOUT: &str = "{category}/{year}/{month_name}";
+
+/// Fields a layout template can use.
+pub const FIELDS: [&str; 10] = ["category", "year", "month", "month_name", "day", "date", "camera_make", "camera_model", "album", "country"];
+
+/// Fields a file name template can use on top of [`FIELDS`].
+pub const NAME_FIELDS: [&str; 3] = ["name", "ext", "seq"];
+
+/// Fields that take a zero-padded width, as in `{month:02}`.
+const NUMERIC_FIELDS: [&str; 4] = ["year", "month", "day", "seq"];
+
+/// `{date}` without a format, e.g. `20190101_120000`.
+const DEFAULT_DATE_FORMAT: &str = "%Y%m%d_%H%M%S";
+
+/// Characters that cannot be used in folder names on every platform.
+const RESERVED: &str = "/\\:*?\"<>|";
+
+/// Left out of a file name together with a missing value.
+const SEPARATORS: [char; 3] = ['_', '-', ' '];
+
+/// A validated folder layout template such as `{category}/{year}/{month:02}-{month_name}`.
+/// Each `/` starts a folder level below `Media Files`. It can come with a
+/// file name template, in which case dated files are renamed, and names its
+/// folders in the language of its locale.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layout {
+    template: String,
+    levels: Vec<Vec<Part>>,
+    names: Option<NameTemplate>,
+    locale: Locale,
+}
+
+/// A validated file name template such as `{date:%Y-%m-%d_%H%M%S}_{camera_model}_{seq}.{ext}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameTemplate {
+    template: String,
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Field { name: &'static str, format: Format },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Format {
+    Plain,
+    /// Zero-padded to this width.
+    Width(usize),
+    /// A chrono `strftime` format for `{date}`.
+    Date(String),
+}
+
+/// What a file offers to fill in a layout.
+#[derive(Debug, Clone, Default)]
+pub struct LayoutValues<'a> {
+    /// Language of `{category}` and `{month_name}`.
+    pub locale: Locale,
+    /// English name of the category, as returned by `destination_folder`.
+    pub category: &'a str,
+    pub date: Option<NaiveDateTime>,
+    pub camera_make: Option<&'a str>,
+    pub camera_model: Option<&'a str>,
+    /// The first album the file is in.
+    pub album: Option<&'a str>,
+    pub country: Option<&'a str>,
+}
+
+impl Layout {
+    /// Parses a template, rejecting unknown fields, bad formats, empty, `.`
+    /// and `..` folder levels and characters not allowed in folder names.
+    pub fn parse(template: &str) -> Result<Layout> {
+        let invalid = |message: String| Error::Layout { template: template.to_string(), message };
+        let mut levels = Vec::new();
+        for level in template.split(['/', '\\']) {
+            if level.trim().is_empty() || level == "." || level == ".." {
+                return Err(invalid(format!("{:?} is not a folder name", level)));
+            }
+            levels.push(parse_parts(level, &[]).map_err(invalid)?);
+        }
+        Ok(Layout { template: template.to_string(), levels, names: None, locale: Locale::default() })
+    }
+
+    /// Names folders in the language of `locale`.
+    pub fn with_locale(mut self, locale: Locale) -> Self {
+        self.locale = locale;
+        self
+    }
+
+    pub fn locale(&self) -> Locale {
+        self.locale
+    }
+
+    /// Renames dated files with `names` when they are sorted.
+    pub fn with_names(mut self, names: NameTemplate) -> Self {
+        self.names = Some(names);
+        self
+    }
+
+    pub fn template(&self) -> &str {
+        &self.template
+    }
+
+    pub fn names(&self) -> Option<&NameTemplate> {
+        self.names.as_ref()
+    }
+
+    /// A renamer for this layout's file name template, if it has one.
+    pub fn renamer(&self) -> Option<Renamer<'_>> {
+        self.names.as_ref().map(|names| Renamer { names, locale: self.locale, counters: HashMap::new() })
+    }
+
+    /// Folder for a file below `root`. A folder level that uses a value the
+    /// file does not have, like `{album}` for a file in no album, is left out.
+    pub fn folder(&self, root: &Path, values: &LayoutValues) -> PathBuf {
+        let mut folder = root.to_path_buf();
+        for level in &self.levels {
+            let name: Option<String> = level.iter().map(|part| match part {
+                Part::Text(text) => Some(text.clone()),
+                Part::Field { name, format } => values.get(name, format),
+            }).collect();
+            if let Some(name) = name.map(|name| name.trim().to_string()).filter(|name| !name.is_empty()) {
+                folder.push(name);
+            }
+        }
+        folder
+    }
+}
+
+impl NameTemplate {
+    /// Parses a file name template; it must keep the extension with `{ext}`.
+    pub fn parse(template: &str) -> Result<NameTemplate> {
+        let invalid = |message: String| Error::Layout { template: template.to_string(), message };
+        let parts = parse_parts(template, &NAME_FIELDS).map_err(invalid)?;
+        if !parts.iter().any(|part| matches!(part, Part::Field { name: "ext", .. })) {
+            return Err(invalid("file names must keep their extension with {ext}".to_string()));
+        }
+        Ok(NameTemplate { template: template.to_string(), parts })
+    }
+
+    pub fn template(&self) -> &str {
+        &self.template
+    }
+
+    /// True when the template numbers files with `{seq}`.
+    pub fn is_numbered(&self) -> bool {
+        self.parts.iter().any(|part| matches!(part, Part::Field { name: "seq", .. }))
+    }
+
+    /// The new name of the file called `original`, numbered `seq`. A missing
+    /// value is left out along with the separator (`_`, `-` or space) before
+    /// it; if nothing is left but the extension, the original name is kept.
+    pub fn file_name(&self, values: &LayoutValues, original: &str, seq: usize) -> String {
+        let path = Path::new(original);
+        let mut name = String::new();
+        for part in &self.parts {
+            let value = match part {
+                Part::Text(text) => Some(text.clone()),
+                Part::Field { name: "name", .. } => path.file_stem().map(|stem| stem.to_string_lossy().to_string()),
+                Part::Field { name: "ext", .. } => path.extension().map(|ext| ext.to_string_lossy().to_string()),
+                Part::Field { name: "seq", format } => Some(pad(seq.to_string(), format)),
+                Part::Field { name, format } => values.get(name, format),
+            };
+            match value {
+                Some(value) => name.push_str(&value),
+                None => name.truncate(name.trim_end_matches(SEPARATORS).len()),
+            }
+        }
+        let name = name.trim_start_matches(SEPARATORS);
+        if name.is_empty() || name.starts_with('.') { original.to_string() } else { name.to_string() }
+    }
+}
+
+/// Names files with a [`NameTemplate`], counting `{seq}` up from 1 among the
+/// files of a folder whose names only differ by it.
+#[derive(Debug, Clone)]
+pub struct Renamer<'a> {
+    names: &'a NameTemplate,
+    locale: Locale,
+    counters: HashMap<PathBuf, usize>,
+}
+
+impl Renamer<'_> {
+    pub fn locale(&self) -> Locale {
+        self.locale
+    }
+
+    /// Path of the file called `original` in `folder`. Numbers of names that
+    /// are `taken`, e.g. by an earlier run, are skipped.
+    pub fn path(&mut self, folder: &Path, values: &LayoutValues, original: &str, taken: impl Fn(&Path) -> bool) -> PathBuf {
+        if !self.names.is_numbered() {
+            return folder.join(self.names.file_name(values, original, 0));
+        }
+        let counter = self.counters.entry(folder.join(self.names.file_name(values, original, 0))).or_insert(0);
+        loop {
+            *counter += 1;
+            let path = folder.join(self.names.file_name(values, original, *counter));
+            if !taken(&path) {
+                return path;
+            }
+        }
+    }
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout::parse(DEFAULT_LAYOUT).expect("the default layout is valid")
+    }
+}
+
+impl LayoutValues<'_> {
+    fn get(&self, field: &str, format: &Format) -> Option<String> {
+        let text = |value: Option<&str>| value.map(str::trim).filter(|value| !value.is_empty()).map(safe_name);
+        let number = |value: Option<u32>| value.map(|value| pad(value.to_string(), format));
+        match field {
+            "category" => text(Some(self.locale.folder(self.category))),
+            "year" => number(self.date.map(|date| date.year() as u32)),
+            "month" => number(self.date.map(|date| date.month())),
+            "month_name" => self.date.map(|date| self.locale.month_name(date.month()).to_string()),
+            "day" => number(self.date.map(|date| date.day())),
+            "date" => self.date.and_then(|date| {
+                let format = match format {
+                    Format::Date(format) => format.as_str(),
+                    _ => DEFAULT_DATE_FORMAT,
+                };
+                let mut text = String::new();
+                write!(text, "{}", date.format(format)).ok().map(|_| safe_name(&text))
+            }),
+            "camera_make" => text(self.camera_make),
+            "camera_model" => text(self.camera_model),
+            "album" => text(self.album),
+            "country" => text(self.country),
+            _ => None,
+        }
+    }
+}
+
+/// Parses the text of one folder level or file name. `extra` are the fields
+/// allowed on top of [`FIELDS`].
+fn parse_parts(text: &str, extra: &[&'static str]) -> std::result::Result<Vec<Part>, String> {
+    let mut parts = Vec::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        if let Some(field) = rest.strip_prefix('{') {
+            let end = field.find('}').ok_or_else(|| format!("unclosed '{{' in {:?}", text))?;
+            parts.push(parse_field(&field[..end], extra)?);
+            rest = &field[end + 1..];
+        } else {
+            let end = rest.find('{').unwrap_or(rest.len());
+            let literal = &rest[..end];
+            if let Some(c) = literal.chars().find(|c| *c == '}' || RESERVED.contains(*c)) {
+                return Err(format!("{:?} cannot be used in a file or folder name", c));
+            }
+            parts.push(Part::Text(literal.to_string()));
+            rest = &rest[end..];
+        }
+    }
+    Ok(parts)
+}
+
+fn parse_field(spec: &str, extra: &[&'static str]) -> std::result::Result<Part, String> {
+    let (name, format) = match spec.split_once(':') {
+        Some((name, format)) => (name, Some(format)),
+        None => (spec, None),
+    };
+    let known = || FIELDS.iter().chain(extra).copied();
+    let name = known().find(|field| *field == name)
+        .ok_or_else(|| format!("unknown field {{{}}}; use one of {}", name, known().collect::<Vec<_>>().join(", ")))?;
+    let format = match format {
+        None => Format::Plain,
+        Some(format) if NUMERIC_FIELDS.contains(&name) => format.strip_prefix('0')
+            .and_then(|width| width.parse().ok())
+            .map(Format::Width)
+            .ok_or_else(|| format!("invalid format {{{}:{}}}; numbers are padded like {{{}:02}}", name, format, name))?,
+        Some(format) if name == "date" => {
+            if format.is_empty() || StrftimeItems::new(format).any(|item| item == Item::Error) {
+                return Err(format!("invalid date format {:?}; use strftime codes like %Y-%m-%d", format));
+            }
+            // Codes such as %z and %Z need a time zone, which file dates do not have.
+            if write!(String::new(), "{}", NaiveDateTime::default().format(format)).is_err() {
+                return Err(format!("invalid date format {:?}; file dates have no time zone, so %z and %Z cannot be used", format));
+            }
+            Format::Date(format.to_string())
+        }
+        Some(_) => return Err(format!("{{{}}} takes no format", name)),
+    };
+    Ok(Part::Field { name, format })
+}
+
+fn pad(value: String, format: &Format) -> String {
+    match format {
+        Format::Width(width) => format!("{:0>width$}", value, width = width),
+        _ => value,
+    }
+}
+
+/// A value as a file or folder name, with reserved characters replaced by `_`.
+fn safe_name(value: &str) -> String {
+    let name: String = value.chars().map(|c| if RESERVED.contains(c) || c.is_control() { '_' } else { c }).collect();
+    if name == "." || name == ".." { "_".to_string() } else { name }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(text: &str) -> Option<NaiveDateTime> {
+        NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S").ok()
+    }
+
+    fn values<'a>() -> LayoutValues<'a> {
+        LayoutValues {
+            category: "Photos",
+            date: date("2019-05-04 13:07:09"),
+            camera_make: Some("Canon"),
+            camera_model: Some("EOS 5D Mark IV"),
+            ..LayoutValues::default()
+        }
+    }
+
+    #[test]
+    fn templates_are_parsed_into_text_and_fields() {
+        let layout = Layout::parse("{category}/{year}/{month:02}-{month_name}").unwrap();
+        assert_eq!(layout.levels.len(), 3);
+        assert_eq!(layout.levels[0], vec![Part::Field { name: "category", format: Format::Plain }]);
+        assert_eq!(layout.levels[2], vec![
+            Part::Field { name: "month", format: Format::Width(2) },
+            Part::Text("-".to_string()),
+            Part::Field { name: "month_name", format: Format::Plain },
+        ]);
+        let names = NameTemplate::parse("{date:%Y-%m-%d}_{seq:03}.{ext}").unwrap();
+        assert_eq!(names.parts[0], Part::Field { name: "date", format: Format::Date("%Y-%m-%d".to_string()) });
+        assert!(names.is_numbered());
+        assert_eq!(Layout::default().template(), DEFAULT_LAYOUT);
+    }
+
+    #[test]
+    fn invalid_templates_are_rejected() {
+        for template in [
+            "", "{category}//{year}", "{category}/../{year}", "./{year}", "{year", "{year}}", "{colour}", "{name}", "{year:abc}",
+            "{month:2}", "{category:02}", "{date:}", "{date:%Q}", "Photos: {year}", "{year}*",
+        ] {
+            assert!(Layout::parse(template).is_err(), "{:?}", template);
+        }
+        for template in ["{name}", "{date}_{seq}", "{name}.jpg", "{album}/{name}.{ext}"] {
+            assert!(NameTemplate::parse(template).is_err(), "{:?}", template);
+        }
+        assert!(Layout::parse("{category}\\{year}").is_ok());
+    }
+
+    #[test]
+    fn safe_names_replace_reserved_characters() {
+        assert_eq!(safe_name("AC/DC: Live?"), "AC_DC_ Live_");
+        assert_eq!(safe_name("a\\b*c\"d<e>f|g"), "a_b_c_d_e_f_g");
+        assert_eq!(safe_name("line\nbreak\t"), "line_break_");
+        assert_eq!(safe_name("."), "_");
+        assert_eq!(safe_name(".."), "_");
+        assert_eq!(safe_name("...and more"), "...and more");
+        assert_eq!(safe_name("Ünïcødé 写真"), "Ünïcødé 写真");
+    }
+
+    #[test]
+    fn folders_are_rendered_from_file_values() {
+        let root = Path::new("out");
+        let layout = Layout::parse("{category}/{year}/{month:02}-{month_name}/{day:02}").unwrap();
+        assert_eq!(layout.folder(root, &values()), Path::new("out/Photos/2019/05-May/04"));
+        let layout = Layout::parse("{camera_make}/{camera_model}/{date:%Y-%m}").unwrap();
+        assert_eq!(layout.folder(root, &values()), Path::new("out/Canon/EOS 5D Mark IV/2019-05"));
+        let german = LayoutValues { locale: Locale::De, ..values() };
+        assert_eq!(Layout::default().folder(root, &german), Path::new("out/Fotos/2019/Mai"));
+    }
+
+    #[test]
+    fn folder_levels_with_missing_values_are_left_out() {
+        let root = Path::new("out");
+        let layout = Layout::parse("{category}/Album {album}/{country}/{year}").unwrap();
+        assert_eq!(layout.folder(root, &values()), Path::new("out/Photos/2019"));
+        let in_album = LayoutValues { album: Some("Trip: Rome/Naples"), country: Some("  "), ..values() };
+        assert_eq!(layout.folder(root, &in_album), Path::new("out/Photos/Album Trip_ Rome_Naples/2019"));
+        let undated = LayoutValues { date: None, ..values() };
+        assert_eq!(layout.folder(root, &undated), Path::new("out/Photos"));
+    }
+
+    #[test]
+    fn file_names_are_rendered_from_file_values() {
+        let names = NameTemplate::parse("{date:%Y-%m-%d_%H%M%S}_{camera_model}_{seq:02}.{ext}").unwrap();
+        assert_eq!(names.file_name(&values(), "IMG_1234.JPG", 7), "2019-05-04_130709_EOS 5D Mark IV_07.JPG");
+        let names = NameTemplate::parse("{date}-{name}.{ext}").unwrap();
+        assert_eq!(names.file_name(&values(), "IMG_1234.jpg", 0), "20190504_130709-IMG_1234.jpg");
+    }
+
+    #[test]
+    fn missing_values_drop_their_separator() {
+        let names = NameTemplate::parse("{date:%Y%m%d}_{camera_make}-{album}.{ext}").unwrap();
+        assert_eq!(names.file_name(&values(), "a.jpg", 0), "20190504_Canon.jpg");
+        let bare = LayoutValues { camera_make: None, ..values() };
+        assert_eq!(names.file_name(&bare, "a.jpg", 0), "20190504.jpg");
+        let names = NameTemplate::parse("{album} {date:%Y}.{ext}").unwrap();
+        assert_eq!(names.file_name(&values(), "a.jpg", 0), "2019.jpg");
+        // Nothing but the extension left: the original name is kept
+        let names = NameTemplate::parse("{album}.{ext}").unwrap();
+        assert_eq!(names.file_name(&values(), "IMG_1.jpg", 0), "IMG_1.jpg");
+    }
+
+    #[test]
+    fn renamer_numbers_files_per_name_and_skips_taken_ones() {
+        let layout = Layout::default().with_names(NameTemplate::parse("{date:%Y%m%d}_{seq:03}.{ext}").unwrap());
+        let mut renamer = layout.renamer().unwrap();
+        let folder = Path::new("out/Photos/2019/May");
+        let taken = |path: &Path| path.ends_with("20190504_002.jpg");
+        assert_eq!(renamer.path(folder, &values(), "a.jpg", taken), folder.join("20190504_001.jpg"));
+        assert_eq!(renamer.path(folder, &values(), "b.jpg", taken), folder.join("20190504_003.jpg"));
+        // Another extension, another name to count for
+        assert_eq!(renamer.path(folder, &values(), "c.png", taken), folder.join("20190504_001.png"));
+        let other_day = LayoutValues { date: date("2019-05-05 08:00:00"), ..values() };
+        assert_eq!(renamer.path(folder, &other_day, "d.jpg", taken), folder.join("20190505_001.jpg"));
+        assert!(Layout::default().renamer().is_none());
+    }
+
+    #[test]
+    fn date_formats_with_a_time_zone_are_rejected() {
+        for format in ["%z", "%Z", "%:z", "%Y-%m-%d %z", "%+"] {
+            let template = format!("{{date:{}}}", format);
+            assert!(Layout::parse(&template).is_err(), "layout {}", template);
+            assert!(NameTemplate::parse(&format!("{}.{{ext}}", template)).is_err(), "name {}", template);
+        }
+        assert!(Layout::parse("{date:%Y-%m-%d %H%M}").is_ok());
+    }
+}
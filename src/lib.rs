@@ -0,0 +1,63 @@
+// lib.rs
+// Library entry point for MetaSort_v1.0.0 – Google Photos Takeout Organizer
+
+//! MetaSort organizes a Google Photos Takeout export: it pairs media with their
+//! JSON sidecars, embeds the Google Photos metadata with exiftool and sorts the
+//! files into folders by type and date.
+//!
+//! [`Pipeline`] runs everything end to end. The individual stages are public
+//! too, e.g. [`metadata_extraction::extract_metadata`],
+//! [`filename_date_guess::extract_date_from_filename`] and
+//! [`sort_to_folders::sort_files_to_folders`].
+
+pub mod albums;
+pub mod archive;
+pub mod collision;
+pub mod config;
+pub mod csv_report;
+pub mod dedup;
+pub mod edited;
+pub mod error;
+pub mod exiftool;
+pub mod filename_date_guess;
+pub mod html_report;
+pub mod input;
+pub mod item_status;
+pub mod journal;
+pub mod layout;
+pub mod live_photos;
+pub mod locale;
+pub mod media_cleaning;
+pub mod media_info;
+pub mod merge;
+pub mod metadata_embed;
+pub mod metadata_extraction;
+pub mod native_metadata;
+pub mod near_duplicates;
+pub mod pipeline;
+pub mod plan;
+pub mod platform;
+pub mod quicktime;
+pub mod sidecar;
+pub mod sort_to_folders;
+pub mod timezone;
+pub mod transfer;
+pub mod ui;
+pub mod utils;
+
+pub use albums::AlbumMode;
+pub use collision::CollisionStrategy;
+pub use config::Config;
+pub use edited::EditedPolicy;
+pub use error::{Error, Result};
+pub use item_status::StatusPolicy;
+pub use layout::Layout;
+pub use live_photos::MotionPhotoMode;
+pub use locale::Locale;
+pub use metadata_embed::DateSource;
+pub use metadata_extraction::{MediaMetadata, UnpairedPolicy};
+pub use native_metadata::MetadataWriter;
+pub use pipeline::{Pipeline, RunSummary};
+pub use plan::{Plan, PlanEntry};
+pub use timezone::Zone;
+pub use transfer::TransferMode;
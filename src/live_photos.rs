@@ -0,0 +1,309 @@
+// live_photos.rs
+// Live Photo and Motion Photo handling for MetaSort_v1.0.0 – Google Photos Takeout Organizer
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use chrono::NaiveDateTime;
+use regex::bytes::Regex;
+use serde::{Deserialize, Serialize};
+use crate::metadata_extraction::MediaMetadata;
+use crate::native_metadata::replace_file;
+use crate::timezone::parse_offset;
+use crate::utils::log_to_file;
+
+const STILL_EXTENSIONS: [&str; 4] = ["heic", "heif", "jpg", "jpeg"];
+const CLIP_EXTENSIONS: [&str; 2] = ["mov", "mp4"];
+/// Motion Photos announce their video in the XMP near the start of the file.
+const XMP_SCAN_LEN: usize = 256 * 1024;
+/// Most seconds between the capture times of a photo and its clip; a Live
+/// Photo clip starts shortly before the photo is taken.
+const MAX_CAPTURE_GAP_SECS: i64 = 10;
+
+/// Where the embedded video starts, counted from the end of the file: the
+/// old `MicroVideoOffset` or the length of the Container `MotionPhoto` item.
+static VIDEO_OFFSET: LazyLock<[Regex; 2]> = LazyLock::new(|| [
+    Regex::new(r#"MicroVideoOffset(?:="|>)(\d+)"#).unwrap(),
+    Regex::new(r#"(?s)Item:Semantic="MotionPhoto"[^>]*?Item:Length="(\d+)"|Item:Length="(\d+)"[^>]*?Item:Semantic="MotionPhoto""#).unwrap(),
+]);
+
+/// What to do with the video embedded in a Pixel or Samsung Motion Photo.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MotionPhotoMode {
+    /// Leave the photo as it is
+    #[default]
+    Keep,
+    /// Also save the video as an .mp4 next to the photo
+    Extract,
+    /// Remove the video, keeping only the still photo
+    Strip,
+}
+
+/// Handles embedded Motion Photo videos according to `mode`, then pairs
+/// every still photo with the clip of the same name (`IMG_1234.HEIC` and
+/// `IMG_1234.MOV`, `PXL_..MP.jpg` and `PXL_..MP.mp4`) taken at about the same
+/// time. Each clip takes the date, place and flags of its photo, so the two
+/// are sorted together.
+///
+/// Returns the photo of every clip, by clip path.
+pub fn pair_live_photos(
+    metadata: &mut Vec<MediaMetadata>,
+    failed_guess_paths: &mut Vec<PathBuf>,
+    mode: MotionPhotoMode,
+    logs_dir: &Path,
+) -> HashMap<PathBuf, PathBuf> {
+    if mode != MotionPhotoMode::Keep {
+        let known: HashSet<PathBuf> = metadata.iter().map(|meta| meta.media_path.clone()).collect();
+        let mut extracted = Vec::new();
+        for meta in metadata.iter().filter(|meta| has_extension(&meta.media_path, &["jpg", "jpeg"])) {
+            match handle_motion_photo(&meta.media_path, mode) {
+                Ok(false) => {}
+                Ok(true) if mode == MotionPhotoMode::Strip => {
+                    log_to_file(logs_dir, "live_photos.log", &format!("✅ Removed the video of {:?}", meta.media_path));
+                }
+                Ok(true) => {
+                    let clip = motion_clip_path(&meta.media_path);
+                    log_to_file(logs_dir, "live_photos.log", &format!("✅ Extracted the video of {:?} to {:?}", meta.media_path, clip));
+                    // The clip has no JSON of its own, so it is not taken for an edit of the photo
+                    if !known.contains(&clip) {
+                        extracted.push(MediaMetadata { media_path: clip, json_path: PathBuf::new(), ..meta.clone() });
+                    }
+                }
+                Err(e) => log_to_file(logs_dir, "live_photos.log", &format!("❌ Failed to process Motion Photo {:?}: {}", meta.media_path, e)),
+            }
+        }
+        metadata.extend(extracted);
+    }
+
+    let mut groups: HashMap<(PathBuf, String), (Vec<usize>, Vec<usize>)> = HashMap::new();
+    for (i, meta) in metadata.iter().enumerate() {
+        let (Some(parent), Some(key)) = (meta.media_path.parent(), live_key(&meta.media_path)) else {
+            continue;
+        };
+        let (stills, clips) = groups.entry((parent.to_path_buf(), key)).or_default();
+        if has_extension(&meta.media_path, &STILL_EXTENSIONS) {
+            stills.push(i);
+        } else if has_extension(&meta.media_path, &CLIP_EXTENSIONS) {
+            clips.push(i);
+        }
+    }
+    let mut pairs = HashMap::new();
+    for (stills, clips) in groups.into_values() {
+        let (&[still], &[clip]) = (stills.as_slice(), clips.as_slice()) else {
+            continue;
+        };
+        if let Some(gap) = capture_gap(&metadata[still], &metadata[clip]).filter(|gap| *gap > MAX_CAPTURE_GAP_SECS) {
+            log_to_file(logs_dir, "live_photos.log", &format!(
+                "Not paired {:?} with {:?}: taken {} seconds apart", metadata[clip].media_path, metadata[still].media_path, gap
+            ));
+            continue;
+        }
+        let photo = metadata[still].clone();
+        let video = &mut metadata[clip];
+        if photo.exif_date.is_some() {
+            video.exif_date = photo.exif_date.clone();
+            video.exif_offset = photo.exif_offset.clone();
+        }
+        video.gps_latitude = video.gps_latitude.or(photo.gps_latitude);
+        video.gps_longitude = video.gps_longitude.or(photo.gps_longitude);
+        video.gps_altitude = video.gps_altitude.or(photo.gps_altitude);
+        video.favorited = photo.favorited;
+        video.archived = photo.archived;
+        video.trashed = photo.trashed;
+        video.locked = photo.locked;
+        failed_guess_paths.retain(|path| *path != video.media_path);
+        log_to_file(logs_dir, "live_photos.log", &format!("Paired {:?} with {:?}", video.media_path, photo.media_path));
+        pairs.insert(video.media_path.clone(), photo.media_path);
+    }
+    pairs
+}
+
+/// Seconds between the capture times of two files, compared in UTC when
+/// both offsets are known. `None` when either has no date.
+fn capture_gap(a: &MediaMetadata, b: &MediaMetadata) -> Option<i64> {
+    let time = |meta: &MediaMetadata| {
+        let local = NaiveDateTime::parse_from_str(meta.exif_date.as_deref()?, "%Y:%m:%d %H:%M:%S").ok()?;
+        Some((local, meta.exif_offset.as_deref().and_then(parse_offset)))
+    };
+    let ((a, a_offset), (b, b_offset)) = (time(a)?, time(b)?);
+    let gap = match (a_offset, b_offset) {
+        (Some(a_offset), Some(b_offset)) => (a - a_offset) - (b - b_offset),
+        _ => a - b,
+    };
+    Some(gap.num_seconds().abs())
+}
+
+/// The name shared by a photo and its clip: the file stem, without the
+/// `.MP` Pixel Motion Photos add.
+fn live_key(path: &Path) -> Option<String> {
+    let stem = path.file_stem()?.to_str()?;
+    let key = match stem.len().checked_sub(3).and_then(|at| stem.get(at..)) {
+        Some(tail) if tail.eq_ignore_ascii_case(".mp") => &stem[..stem.len() - 3],
+        _ => stem,
+    };
+    Some(key.to_string())
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension().and_then(|e| e.to_str()).is_some_and(|ext| extensions.contains(&ext.to_lowercase().as_str()))
+}
+
+/// The `.mp4` an extracted Motion Photo video is saved as.
+fn motion_clip_path(path: &Path) -> PathBuf {
+    path.with_extension("mp4")
+}
+
+/// Extracts or strips the video of a Motion Photo. Returns false for photos
+/// without an embedded video, including ones stripped before whose XMP still
+/// announces it.
+fn handle_motion_photo(path: &Path, mode: MotionPhotoMode) -> Result<bool, String> {
+    let mut head = Vec::new();
+    File::open(path).and_then(|file| file.take(XMP_SCAN_LEN as u64).read_to_end(&mut head)).map_err(|e| e.to_string())?;
+    if !head.windows(10).any(|w| w == b"MicroVideo" || w == b"MotionPhot") {
+        return Ok(false);
+    }
+    let data = fs::read(path).map_err(|e| e.to_string())?;
+    let Some(start) = embedded_video_start(&head, data.len()).filter(|&start| is_mp4_at(&data, start)) else {
+        return Ok(false);
+    };
+    match mode {
+        MotionPhotoMode::Keep => {}
+        MotionPhotoMode::Extract => {
+            let clip = motion_clip_path(path);
+            // Already extracted by an interrupted run, or a clip of that name came with the Takeout
+            if !clip.exists() {
+                fs::write(&clip, &data[start..]).map_err(|e| e.to_string())?;
+            }
+        }
+        MotionPhotoMode::Strip => replace_file(path, data[..start].to_vec().into())?,
+    }
+    Ok(true)
+}
+
+fn embedded_video_start(xmp: &[u8], file_len: usize) -> Option<usize> {
+    VIDEO_OFFSET.iter().find_map(|regex| {
+        let caps = regex.captures(xmp)?;
+        let length = caps.iter().skip(1).flatten().next()?;
+        let length: usize = std::str::from_utf8(length.as_bytes()).ok()?.parse().ok()?;
+        file_len.checked_sub(length).filter(|start| *start > 0)
+    })
+}
+
+fn is_mp4_at(data: &[u8], start: usize) -> bool {
+    data.get(start + 4..start + 8) == Some(b"ftyp")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VIDEO: &[u8] = b"\0\0\0\x18ftypmp42\0\0\0\0mp42isomvideo data";
+
+    fn meta(path: &Path, date: Option<&str>, offset: Option<&str>) -> MediaMetadata {
+        MediaMetadata {
+            media_path: path.to_path_buf(),
+            json_path: path.with_extension("json"),
+            exif_date: date.map(str::to_string),
+            exif_offset: offset.map(str::to_string),
+            ..MediaMetadata::default()
+        }
+    }
+
+    /// A JPEG whose XMP announces the video appended to it.
+    fn motion_photo(path: &Path, xmp: &str) {
+        let mut data = b"\xff\xd8\xff\xe1".to_vec();
+        data.extend_from_slice(xmp.replace("{len}", &VIDEO.len().to_string()).as_bytes());
+        data.extend_from_slice(b"\xff\xd9");
+        data.extend_from_slice(VIDEO);
+        fs::write(path, data).unwrap();
+    }
+
+    #[test]
+    fn clips_taken_with_their_photo_are_paired() {
+        let dir = tempfile::tempdir().unwrap();
+        let (photo, clip) = (dir.path().join("IMG_1234.HEIC"), dir.path().join("IMG_1234.MOV"));
+        let mut photo_meta = meta(&photo, Some("2021:05:01 10:30:02"), Some("+02:00"));
+        photo_meta.gps_latitude = Some(48.1);
+        photo_meta.favorited = true;
+        // The clip's offset is unknown, its date starts a little earlier
+        let mut metadata = vec![photo_meta, meta(&clip, Some("2021:05:01 10:30:00"), None)];
+        let mut failed = vec![clip.clone()];
+        let pairs = pair_live_photos(&mut metadata, &mut failed, MotionPhotoMode::Keep, dir.path());
+
+        assert_eq!(pairs, HashMap::from([(clip.clone(), photo.clone())]));
+        assert_eq!(metadata[1].exif_date.as_deref(), Some("2021:05:01 10:30:02"));
+        assert_eq!(metadata[1].exif_offset.as_deref(), Some("+02:00"));
+        assert_eq!(metadata[1].gps_latitude, Some(48.1));
+        assert!(metadata[1].favorited);
+        assert!(failed.is_empty());
+    }
+
+    #[test]
+    fn clips_taken_at_another_time_are_not_paired() {
+        let dir = tempfile::tempdir().unwrap();
+        let (photo, clip) = (dir.path().join("IMG_1234.JPG"), dir.path().join("IMG_1234.MOV"));
+        // Same name from a camera whose counter wrapped around
+        let mut metadata = vec![
+            meta(&photo, Some("2019:07:01 12:00:00"), None),
+            meta(&clip, Some("2021:05:01 10:30:00"), None),
+        ];
+        assert!(pair_live_photos(&mut metadata, &mut Vec::new(), MotionPhotoMode::Keep, dir.path()).is_empty());
+        assert_eq!(metadata[1].exif_date.as_deref(), Some("2021:05:01 10:30:00"));
+
+        // The same local time in two zones is hours apart
+        let mut metadata = vec![
+            meta(&photo, Some("2021:05:01 10:30:00"), Some("+02:00")),
+            meta(&clip, Some("2021:05:01 10:30:00"), Some("-05:00")),
+        ];
+        assert!(pair_live_photos(&mut metadata, &mut Vec::new(), MotionPhotoMode::Keep, dir.path()).is_empty());
+    }
+
+    #[test]
+    fn motion_videos_are_extracted_at_the_micro_video_offset() {
+        let dir = tempfile::tempdir().unwrap();
+        let photo = dir.path().join("PXL_20210501_103000.MP.jpg");
+        motion_photo(&photo, r#"<x:xmpmeta><rdf:Description GCamera:MicroVideo="1" GCamera:MicroVideoOffset="{len}"/></x:xmpmeta>"#);
+        let mut metadata = vec![meta(&photo, Some("2021:05:01 10:30:00"), None)];
+        let pairs = pair_live_photos(&mut metadata, &mut Vec::new(), MotionPhotoMode::Extract, dir.path());
+
+        let clip = dir.path().join("PXL_20210501_103000.MP.mp4");
+        assert_eq!(fs::read(&clip).unwrap(), VIDEO);
+        assert_eq!(pairs, HashMap::from([(clip.clone(), photo.clone())]));
+        assert_eq!(metadata.len(), 2);
+        assert_eq!(metadata[1].media_path, clip);
+        assert_eq!(metadata[1].json_path, PathBuf::new());
+        assert!(fs::read(&photo).unwrap().ends_with(VIDEO));
+    }
+
+    #[test]
+    fn motion_videos_are_stripped_at_the_container_item_length() {
+        let dir = tempfile::tempdir().unwrap();
+        let photo = dir.path().join("20210501_103000.jpg");
+        motion_photo(&photo, concat!(
+            r#"<x:xmpmeta><Camera:MotionPhoto>1</Camera:MotionPhoto><Container:Directory><rdf:Seq>"#,
+            r#"<rdf:li><Container:Item Item:Mime="image/jpeg" Item:Semantic="Primary"/></rdf:li>"#,
+            r#"<rdf:li><Container:Item Item:Mime="video/mp4" Item:Semantic="MotionPhoto" Item:Length="{len}"/></rdf:li>"#,
+            r#"</rdf:Seq></Container:Directory></x:xmpmeta>"#,
+        ));
+        let original = fs::read(&photo).unwrap();
+        let mut metadata = vec![meta(&photo, None, None)];
+        pair_live_photos(&mut metadata, &mut Vec::new(), MotionPhotoMode::Strip, dir.path());
+
+        assert_eq!(fs::read(&photo).unwrap(), &original[..original.len() - VIDEO.len()]);
+        assert_eq!(metadata.len(), 1);
+        // Stripped once, the XMP still announces a video that is gone
+        assert_eq!(handle_motion_photo(&photo, MotionPhotoMode::Strip), Ok(false));
+    }
+
+    #[test]
+    fn offsets_not_pointing_at_a_video_are_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        let photo = dir.path().join("IMG.jpg");
+        motion_photo(&photo, r#"<x:xmpmeta GCamera:MicroVideo="1" GCamera:MicroVideoOffset="7"/>"#);
+        assert_eq!(handle_motion_photo(&photo, MotionPhotoMode::Extract), Ok(false));
+        assert!(!dir.path().join("IMG.mp4").exists());
+    }
+}
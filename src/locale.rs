@@ -0,0 +1,78 @@
+// locale.rs
+// Localized folder names for MetaSort_v1.0.0 – Google Photos Takeout Organizer
+
+use serde::{Deserialize, Serialize};
+
+/// Language of the month and category folder names in the output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Locale {
+    /// English
+    #[default]
+    En,
+    /// Japanese
+    Ja,
+    /// Russian
+    Ru,
+    /// Spanish
+    Es,
+    /// French
+    Fr,
+    /// German
+    De,
+    /// Italian
+    It,
+    /// Norwegian
+    No,
+    /// Swedish
+    Sv,
+    /// Turkish
+    Tr,
+    /// Polish
+    Pl,
+}
+
+/// English names of the localized folders; the tables in [`Locale::folder`]
+/// follow this order. "Whatsapp" and "mkv_files" are never translated.
+const FOLDERS: [&str; 9] = [
+    "Media Files", "Photos", "Videos", "Screenshots", "Unknown Time", "Failed Filename Guess", "Archived", "Trash", "Locked Folder",
+];
+
+impl Locale {
+    /// Name of `month` (1 to 12).
+    pub fn month_name(self, month: u32) -> &'static str {
+        let months: [&'static str; 12] = match self {
+            Locale::En => ["January", "February", "March", "April", "May", "June", "July", "August", "September", "October", "November", "December"],
+            Locale::Ja => ["1月", "2月", "3月", "4月", "5月", "6月", "7月", "8月", "9月", "10月", "11月", "12月"],
+            Locale::Ru => ["Январь", "Февраль", "Март", "Апрель", "Май", "Июнь", "Июль", "Август", "Сентябрь", "Октябрь", "Ноябрь", "Декабрь"],
+            Locale::Es => ["Enero", "Febrero", "Marzo", "Abril", "Mayo", "Junio", "Julio", "Agosto", "Septiembre", "Octubre", "Noviembre", "Diciembre"],
+            Locale::Fr => ["Janvier", "Février", "Mars", "Avril", "Mai", "Juin", "Juillet", "Août", "Septembre", "Octobre", "Novembre", "Décembre"],
+            Locale::De => ["Januar", "Februar", "März", "April", "Mai", "Juni", "Juli", "August", "September", "Oktober", "November", "Dezember"],
+            Locale::It => ["Gennaio", "Febbraio", "Marzo", "Aprile", "Maggio", "Giugno", "Luglio", "Agosto", "Settembre", "Ottobre", "Novembre", "Dicembre"],
+            Locale::No => ["Januar", "Februar", "Mars", "April", "Mai", "Juni", "Juli", "August", "September", "Oktober", "November", "Desember"],
+            Locale::Sv => ["Januari", "Februari", "Mars", "April", "Maj", "Juni", "Juli", "Augusti", "September", "Oktober", "November", "December"],
+            Locale::Tr => ["Ocak", "Şubat", "Mart", "Nisan", "Mayıs", "Haziran", "Temmuz", "Ağustos", "Eylül", "Ekim", "Kasım", "Aralık"],
+            Locale::Pl => ["Styczeń", "Luty", "Marzec", "Kwiecień", "Maj", "Czerwiec", "Lipiec", "Sierpień", "Wrzesień", "Październik", "Listopad", "Grudzień"],
+        };
+        month.checked_sub(1).and_then(|i| months.get(i as usize)).copied().unwrap_or("Unknown")
+    }
+
+    /// Name of `Media Files` or of a category folder, given by its English name.
+    /// Other names are returned unchanged.
+    pub fn folder(self, name: &str) -> &str {
+        let names: [&'static str; 9] = match self {
+            Locale::En => FOLDERS,
+            Locale::Ja => ["メディアファイル", "写真", "動画", "スクリーンショット", "日時不明", "ファイル名から日付を取得できず", "アーカイブ", "ゴミ箱", "ロックされたフォルダ"],
+            Locale::Ru => ["Медиафайлы", "Фото", "Видео", "Снимки экрана", "Без даты", "Дата по имени не определена", "Архив", "Корзина", "Личная папка"],
+            Locale::Es => ["Archivos multimedia", "Fotos", "Vídeos", "Capturas de pantalla", "Fecha desconocida", "Fecha no deducida del nombre", "Archivo", "Papelera", "Carpeta bloqueada"],
+            Locale::Fr => ["Fichiers multimédias", "Photos", "Vidéos", "Captures d'écran", "Date inconnue", "Date introuvable dans le nom", "Archives", "Corbeille", "Dossier verrouillé"],
+            Locale::De => ["Mediendateien", "Fotos", "Videos", "Bildschirmfotos", "Unbekanntes Datum", "Datum aus Dateiname nicht erkannt", "Archiv", "Papierkorb", "Gesperrter Ordner"],
+            Locale::It => ["File multimediali", "Foto", "Video", "Screenshot", "Data sconosciuta", "Data non ricavata dal nome", "Archivio", "Cestino", "Cartella bloccata"],
+            Locale::No => ["Mediefiler", "Bilder", "Videoer", "Skjermbilder", "Ukjent tidspunkt", "Dato ikke funnet i filnavn", "Arkiv", "Papirkurv", "Låst mappe"],
+            Locale::Sv => ["Mediefiler", "Foton", "Videor", "Skärmbilder", "Okänd tid", "Datum hittades inte i filnamn", "Arkiv", "Papperskorg", "Låst mapp"],
+            Locale::Tr => ["Medya Dosyaları", "Fotoğraflar", "Videolar", "Ekran Görüntüleri", "Bilinmeyen Zaman", "Dosya Adından Tarih Bulunamadı", "Arşiv", "Çöp Kutusu", "Kilitli Klasör"],
+            Locale::Pl => ["Pliki multimedialne", "Zdjęcia", "Filmy", "Zrzuty ekranu", "Nieznana data", "Nie odczytano daty z nazwy", "Archiwum", "Kosz", "Zablokowany folder"],
+        };
+        FOLDERS.iter().position(|folder| *folder == name).map_or(name, |i| names[i])
+    }
+}
@@ -1,213 +1,170 @@
 // main.rs
 // Entry point for MetaSort_v1.0.0 – Google Photos Takeout Organizer
 
-mod media_cleaning;
-mod metadata_extraction;
-mod metadata_embed;
-mod sort_to_folders;
-mod html_report;
-mod utils;
-mod csv_report;
-mod filename_date_guess;
-mod platform;
-mod ui;
+mod cli;
 
 use std::io;
 use std::path::PathBuf;
-use std::fs;
-use walkdir;
-use fs_extra;
-use crate::platform::{is_exiftool_available, get_installation_instructions};
-use crate::ui::MetaSortUI;
-
-fn get_folder_size(path: &str) -> u64 {
-    walkdir::WalkDir::new(path)
-        .into_iter()
-        .filter_map(Result::ok)
-        .filter(|e| e.path().is_file())
-        .map(|e| e.metadata().map(|m| m.len()).unwrap_or(0))
-        .sum()
-}
-
-fn human_readable_size(size: u64) -> String {
-    const KB: u64 = 1024;
-    const MB: u64 = KB * 1024;
-    const GB: u64 = MB * 1024;
-    match size {
-        s if s >= GB => format!("{:.2} GB", s as f64 / GB as f64),
-        s if s >= MB => format!("{:.2} MB", s as f64 / MB as f64),
-        s if s >= KB => format!("{:.2} KB", s as f64 / KB as f64),
-        _ => format!("{} B", size),
-    }
-}
+use clap::Parser;
+use metasort::config::DEFAULT_CONFIG_NAME;
+use metasort::input::{resolve_inputs, InputSource};
+use metasort::platform::{is_exiftool_available, get_installation_instructions, set_exiftool_path};
+use metasort::ui::MetaSortUI;
+use metasort::utils::{folder_size, human_readable_size};
+use metasort::{Config, Error, Pipeline};
+use crate::cli::Cli;
 
 fn main() {
+    let cli = Cli::parse();
     MetaSortUI::print_header();
 
-    // Check if exiftool is available
-    if !is_exiftool_available() {
-        MetaSortUI::print_error("ExifTool is not installed or not found in PATH!");
-        println!("{}", get_installation_instructions());
-        println!("\nPress Enter to exit...");
-        let mut input = String::new();
-        io::stdin().read_line(&mut input).expect("Failed to read line");
+    // Load saved settings before any prompting; command-line flags win over the file
+    let mut config = match &cli.config {
+        Some(path) => Config::load(path).unwrap_or_else(|e| {
+            MetaSortUI::print_error(&e.to_string());
+            std::process::exit(2);
+        }),
+        None => Config::default(),
+    };
+    cli.apply_to(&mut config);
+    if let Err(e) = config.layout().and_then(|_| config.timezone()) {
+        MetaSortUI::print_error(&e.to_string());
+        std::process::exit(2);
+    }
+    if let Some(path) = &config.exiftool_path {
+        set_exiftool_path(path.clone());
+    }
+
+    // Check if exiftool is available. Without it the built-in writer handles
+    // JPEG, PNG, MP4 and MOV, unless exiftool was asked for explicitly.
+    if is_exiftool_available() {
+        MetaSortUI::print_success("ExifTool found and ready!");
+    } else if config.requires_exiftool() && !cli.dry_run {
+        exit_exiftool_missing(cli.yes);
         return;
+    } else {
+        MetaSortUI::print_warning("ExifTool not found: metadata is written with the built-in writer for JPEG, PNG, MP4 and MOV only.");
     }
-    
-    MetaSortUI::print_success("ExifTool found and ready!");
-    println!("\n📂 Please drag and drop your Google Photos Takeout folder here, or specify the folder path:");
-    let mut input = String::new();
-    io::stdin().read_line(&mut input).expect("Failed to read line");
-    let input_dir = input.trim();
+    let inputs = if !cli.input.is_empty() {
+        cli.input.clone()
+    } else if cli.yes {
+        exit_missing_argument("--input")
+    } else {
+        println!("\n📂 Please drag and drop your Google Photos Takeout folder (or takeout .zip/.tgz files) here, or specify the path:");
+        let mut input = String::new();
+        io::stdin().read_line(&mut input).expect("Failed to read line");
+        vec![PathBuf::from(input.trim())]
+    };
 
-    // Calculate input folder size and prompt for required space
-    let folder_size = get_folder_size(input_dir);
-    let required_space = folder_size * 3;
-    MetaSortUI::print_info(&format!("Input folder size: {}", human_readable_size(folder_size)));
+    // Calculate input size and prompt for required space. Archives are
+    // extracted once, straight into the working folder; folders need as much
+    // as the transfer mode takes.
+    let sources = resolve_inputs(&inputs).unwrap_or_else(|e| {
+        MetaSortUI::print_error(&e.to_string());
+        std::process::exit(2);
+    });
+    let mut size = 0;
+    let mut required_space = 0;
+    for source in &sources {
+        let source_size = folder_size(source.path());
+        size += source_size;
+        required_space += match source {
+            InputSource::Directory(_) => source_size * config.transfer.unwrap_or_default().space_factor(),
+            InputSource::Archive(_) => source_size,
+        };
+    }
+    MetaSortUI::print_info(&format!("Input size: {}", human_readable_size(size)));
     MetaSortUI::print_info(&format!("Recommended free space: {}", human_readable_size(required_space)));
-    println!("Continue? (y/n)");
-    let mut cont = String::new();
-    io::stdin().read_line(&mut cont).expect("Failed to read line");
-    if !matches!(cont.trim().to_lowercase().as_str(), "y" | "yes") {
-        MetaSortUI::print_warning("Aborted by user.");
-        return;
+    if !cli.yes && !cli.dry_run {
+        println!("Continue? (y/n)");
+        let mut cont = String::new();
+        io::stdin().read_line(&mut cont).expect("Failed to read line");
+        if !matches!(cont.trim().to_lowercase().as_str(), "y" | "yes") {
+            MetaSortUI::print_warning("Aborted by user.");
+            return;
+        }
     }
 
     // Prompt for output folder
-    println!("\n📁 Please specify the output folder where MetaSort should work (originals will be untouched):");
-    let mut output = String::new();
-    io::stdin().read_line(&mut output).expect("Failed to read line");
-    let output_dir = PathBuf::from(output.trim());
-    let temp_dir = output_dir.join("MetaSort_temp");
-
-    // Copy input folder to MetaSort_temp in output directory
-    MetaSortUI::print_section_header("Copying Files");
-    MetaSortUI::print_info("Copying input folder to working directory...");
-    
-    let mut ui = MetaSortUI::new();
-    let total_files = count_files_in_directory(input_dir);
-    ui.start_main_progress(total_files as u64, "Copying files");
-    
-    let mut copy_options = fs_extra::dir::CopyOptions::new();
-    copy_options.copy_inside = true;
-    fs_extra::dir::copy(input_dir, &temp_dir, &copy_options).expect("Failed to copy input folder to output working directory");
-    
-    ui.finish_progress("Copy complete!");
-    MetaSortUI::print_success(&format!("All processing will happen in: {}", temp_dir.display()));
-
-    // 1. Clean and pair media files with their JSONs (fix weird JSON names)
-    MetaSortUI::print_section_header("Cleaning and Pairing Files");
-    MetaSortUI::print_info("Cleaning and pairing media files with JSONs...");
-    media_cleaning::clean_json_filenames(temp_dir.to_str().unwrap());
-    MetaSortUI::print_success("JSON filename cleaning and pairing complete!");
-
-    // 1b. Ask if WhatsApp/Screenshots should be separated
-    println!("\nDo you want to separate WhatsApp and Screenshot images? (y/n)");
-    let mut wa_sc_input = String::new();
-    io::stdin().read_line(&mut wa_sc_input).expect("Failed to read line");
-    let separate_wa_sc = matches!(wa_sc_input.trim().to_lowercase().as_str(), "y" | "yes");
-    if separate_wa_sc {
-        MetaSortUI::print_success("WhatsApp and Screenshot images will be sorted into their own folders by year/month.");
-    } else {
-        MetaSortUI::print_info("WhatsApp and Screenshot images will be treated as regular photos.");
+    if config.output.is_none() {
+        if cli.yes {
+            exit_missing_argument("--output");
+        }
+        println!("\n📁 Please specify the output folder where MetaSort should work (originals will be untouched):");
+        let mut output = String::new();
+        io::stdin().read_line(&mut output).expect("Failed to read line");
+        config.output = Some(PathBuf::from(output.trim()));
     }
-    media_cleaning::ask_and_separate_whatsapp_screenshots(temp_dir.to_str().unwrap(), separate_wa_sc);
 
-    // 2. Extract metadata from JSON and embed into media files
-    MetaSortUI::print_section_header("Metadata Extraction and Embedding");
-    MetaSortUI::print_info("Extracting metadata from JSON and embedding into media files...");
-    let (metadata, failed_guess_paths) = metadata_extraction::extract_metadata(temp_dir.to_str().unwrap());
-    metadata_embed::embed_metadata_all(&metadata, &temp_dir);
-    MetaSortUI::print_success("Metadata extraction and embedding complete!");
-
-    // 3. Sort files using the embedded metadata (DateTimeOriginal)
-    MetaSortUI::print_section_header("Sorting Files");
-    MetaSortUI::print_info("Sorting files using embedded metadata...");
-    let final_output_dir = output_dir.join("MetaSort_Output");
-    sort_to_folders::sort_files_to_folders(&temp_dir, &final_output_dir, &failed_guess_paths, separate_wa_sc);
-    MetaSortUI::print_success("All done! Check your output and logs for details.");
+    // With --yes, settings not given on the command line or config fall back to their defaults
+    if cli.yes {
+        config.fill_defaults();
+    }
 
-    // 4. Move technical folders into Technical Files
-    let technical_dir = final_output_dir.join("Technical Files");
-    let _ = fs::create_dir_all(&technical_dir);
-    for folder in &["CSV Report", "Json Files", "logs"] {
-        let src = final_output_dir.join(folder);
-        let dst = technical_dir.join(folder);
-        if src.exists() {
-            let _ = fs_extra::dir::move_dir(&src, &dst, &fs_extra::dir::CopyOptions::new());
+    // Settings still unset are asked for when the run reaches them
+    let mut pipeline = Pipeline::from_config(inputs[0].clone(), config).expect("output folder is set above").interactive(!cli.yes);
+    for input in &inputs[1..] {
+        pipeline = pipeline.add_input(input.clone());
+    }
+    if cli.dry_run {
+        let plan_dir = pipeline.plan_dir();
+        match pipeline.plan().and_then(|plan| plan.write_to(&plan_dir).map(|_| plan)) {
+            Ok(plan) => {
+                MetaSortUI::print_success(&format!(
+                    "Dry run complete: {} files planned, {} JSON renames. Nothing was copied, renamed or embedded.",
+                    plan.entries.len(), plan.json_renames.len()
+                ));
+                MetaSortUI::print_info(&format!("Plan written to: {}", plan_dir.display()));
+            }
+            Err(e) => {
+                MetaSortUI::print_error(&e.to_string());
+                std::process::exit(1);
+            }
         }
+        return;
     }
-
-    // 5. HTML summary report
-    let photos = count_files(&final_output_dir.join("Media Files/Photos"));
-    let videos = count_files(&final_output_dir.join("Media Files/Videos"));
-    let whatsapp = count_files(&final_output_dir.join("Media Files/Whatsapp"));
-    let screenshots = count_files(&final_output_dir.join("Media Files/Screenshots"));
-    let unknown = count_files(&final_output_dir.join("Media Files/Unknown Time"));
-    let mkv = count_files(&final_output_dir.join("Media Files/mkv_files"));
-    let total = photos + videos + whatsapp + screenshots + unknown + mkv;
-    let errors = count_log_errors(&final_output_dir.join("Technical Files/logs"));
-    let csv_files = vec!["photos.csv", "videos.csv", "unknown_time.csv", "mkv_files.csv"];
-    let log_files = vec!["media_cleaning.log", "metadata_extraction.log", "metadata_embedding.log", "sorting.log"];
-    let metadata_fields: Vec<&str> = if let Some(meta) = metadata.first() {
-        let mut fields = vec!["media_path", "json_path"];
-        if meta.exif_date.is_some() { fields.push("exif_date"); }
-        if meta.gps_latitude.is_some() { fields.push("gps_latitude"); }
-        if meta.gps_longitude.is_some() { fields.push("gps_longitude"); }
-        if meta.gps_altitude.is_some() { fields.push("gps_altitude"); }
-        if meta.camera_make.is_some() { fields.push("camera_make"); }
-        if meta.camera_model.is_some() { fields.push("camera_model"); }
-        fields
-    } else {
-        vec!["media_path", "json_path", "exif_date", "gps_latitude", "gps_longitude", "gps_altitude", "camera_make", "camera_model"]
+    let summary = match pipeline.run() {
+        Ok(summary) => summary,
+        Err(Error::ExifToolMissing) => {
+            exit_exiftool_missing(cli.yes);
+            return;
+        }
+        Err(e) => {
+            MetaSortUI::print_error(&e.to_string());
+            std::process::exit(1);
+        }
     };
-    html_report::generate_html_report(
-        &final_output_dir,
-        total, photos, videos, whatsapp, screenshots, unknown, mkv, errors,
-        &csv_files, &log_files, &metadata_fields,
-    );
 
     // Print summary
     MetaSortUI::print_summary(
-        photos, videos, whatsapp, screenshots, unknown, mkv, errors,
-        &final_output_dir.to_string_lossy()
+        summary.photos, summary.videos, summary.whatsapp, summary.screenshots, summary.unknown, summary.mkv, summary.separated, summary.errors,
+        &summary.output_dir.to_string_lossy()
     );
 
-    // Delete MetaSort_temp folder after all processing
-    if temp_dir.exists() {
-        match fs_extra::dir::remove(&temp_dir) {
-            Ok(_) => MetaSortUI::print_info(&format!("Deleted temporary folder: {}", temp_dir.display())),
-            Err(e) => MetaSortUI::print_warning(&format!("Could not delete temporary folder: {} (Error: {})", temp_dir.display(), e)),
+    // Save the answers of an interactive run so it can be repeated exactly
+    if !cli.yes {
+        let config_path = cli.config.clone().unwrap_or_else(|| pipeline.output_dir().join(DEFAULT_CONFIG_NAME));
+        match pipeline.config().save(&config_path) {
+            Ok(_) => MetaSortUI::print_info(&format!("Settings saved to {}. Rerun with --config to repeat this run.", config_path.display())),
+            Err(e) => MetaSortUI::print_warning(&e.to_string()),
         }
     }
     
     MetaSortUI::print_footer();
 }
 
-fn count_files(dir: &PathBuf) -> usize {
-    walkdir::WalkDir::new(dir)
-        .into_iter()
-        .filter_map(Result::ok)
-        .filter(|e| e.path().is_file())
-        .count()
+fn exit_exiftool_missing(headless: bool) {
+    MetaSortUI::print_error("ExifTool is not installed or not found in PATH!");
+    println!("{}", get_installation_instructions());
+    if headless {
+        std::process::exit(1);
+    }
+    println!("\nPress Enter to exit...");
+    let mut input = String::new();
+    io::stdin().read_line(&mut input).expect("Failed to read line");
 }
 
-fn count_files_in_directory(path: &str) -> usize {
-    walkdir::WalkDir::new(path)
-        .into_iter()
-        .filter_map(Result::ok)
-        .filter(|e| e.path().is_file())
-        .count()
+fn exit_missing_argument(flag: &str) -> ! {
+    MetaSortUI::print_error(&format!("{} is required when running with --yes", flag));
+    std::process::exit(2);
 }
-
-fn count_log_errors(logs_dir: &PathBuf) -> usize {
-    let mut errors = 0;
-    if let Ok(entries) = fs::read_dir(logs_dir) {
-        for entry in entries.flatten() {
-            if let Ok(content) = fs::read_to_string(entry.path()) {
-                errors += content.matches("❌").count();
-            }
-        }
-    }
-    errors
-} 
\ No newline at end of file
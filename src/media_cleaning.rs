@@ -2,24 +2,58 @@
 // JSON renaming/cleaning logic for MetaSort_v1.0.0 – Google Photos Takeout Organizer 
 
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
 use walkdir::WalkDir;
 use regex::Regex;
 use std::io::{self, Write};
+use crate::collision::{self, CollisionStrategy, Resolution};
+use crate::error;
+use crate::journal::{Journal, Stage};
+use crate::sidecar::{log_report, resolve_sidecars, SidecarMatches};
 use crate::utils::log_to_file;
 
-pub fn ask_and_separate_whatsapp_screenshots(base_path: &str, separate_wa_sc: bool) {
-    if !separate_wa_sc {
-        return;
+static WHATSAPP_PATTERNS: LazyLock<[Regex; 1]> = LazyLock::new(|| [
+    Regex::new(r"(?i)^(IMG-\d{8}-WA\d+|IMG-WA\d+|WA\d+|VID-\d{8}-WA\d+|VID-WA\d+|WhatsApp Image \d{4}-\d{2}-\d{2} at \d{2}\.\d{2}\.\d{2}|WhatsApp Video \d{4}-\d{2}-\d{2} at \d{2}\.\d{2}\.\d{2})").unwrap(),
+]);
+static SCREENSHOT_PATTERNS: LazyLock<[Regex; 1]> = LazyLock::new(|| [
+    Regex::new(r"(?i)^(Screenshot(_| )?\d{4}-\d{2}-\d{2}(-| )?\d{2}(-|\.|:)?\d{2}(-|\.|:)?\d{2}|Screenshot \(\d+\)|Screen Shot \d{4}-\d{2}-\d{2} at \d{2}\.\d{2}\.\d{2}|Screenshot_\d+|Screenshot_\d{8}-\d{6}|スクリーンショット|Снимок экрана|Captura de pantalla|Capture d'écran|Bildschirmfoto|Istantanea|Skjermbilde|Skärmbild|Ekran görüntüsü|Zrzut ekranu|PrtSc|Snip)").unwrap(),
+]);
+
+/// Folder under "Other Images" that a file is moved to when WhatsApp/Screenshot
+/// separation is on: "Whatsapp", "Screenshots", or `None` for regular media.
+pub fn separation_folder(filename: &str) -> Option<&'static str> {
+    if WHATSAPP_PATTERNS.iter().any(|re| re.is_match(filename)) {
+        Some("Whatsapp")
+    } else if SCREENSHOT_PATTERNS.iter().any(|re| re.is_match(filename)) {
+        Some("Screenshots")
+    } else {
+        None
+    }
+}
+
+/// Where a file is now, following its move by WhatsApp/Screenshot separation.
+pub fn separated_path(journal: &Journal, path: &Path) -> PathBuf {
+    journal.data(Stage::Separation, path)
+        .and_then(|dest| dest.as_str())
+        .map_or_else(|| path.to_path_buf(), PathBuf::from)
+}
+
+/// Moves WhatsApp and Screenshot images, with their JSON, into "Other Images".
+/// A name already taken there is handled with `on_collision` and recorded in
+/// `collisions_csv`.
+pub fn ask_and_separate_whatsapp_screenshots(
+    base_path: &str,
+    separate_wa_sc: bool,
+    on_collision: CollisionStrategy,
+    collisions_csv: &Path,
+    journal: &mut Journal,
+) -> error::Result<()> {
+    if !separate_wa_sc || journal.is_stage_done(Stage::Separation) {
+        return Ok(());
     }
     let logs_dir = Path::new(base_path).join("logs");
     log_to_file(&logs_dir, "media_cleaning.log", "User chose to separate WhatsApp and Screenshot images.");
-    let whatsapp_patterns = vec![
-        Regex::new(r"(?i)^(IMG-\d{8}-WA\d+|IMG-WA\d+|WA\d+|VID-\d{8}-WA\d+|VID-WA\d+|WhatsApp Image \d{4}-\d{2}-\d{2} at \d{2}\.\d{2}\.\d{2}|WhatsApp Video \d{4}-\d{2}-\d{2} at \d{2}\.\d{2}\.\d{2})").unwrap(),
-    ];
-    let screenshot_patterns = vec![
-        Regex::new(r"(?i)^(Screenshot(_| )?\d{4}-\d{2}-\d{2}(-| )?\d{2}(-|\.|:)?\d{2}(-|\.|:)?\d{2}|Screenshot \(\d+\)|Screen Shot \d{4}-\d{2}-\d{2} at \d{2}\.\d{2}\.\d{2}|Screenshot_\d+|Screenshot_\d{8}-\d{6}|スクリーンショット|Снимок экрана|Captura de pantalla|Capture d'écran|Bildschirmfoto|Istantanea|Skjermbilde|Skärmbild|Ekran görüntüsü|Zrzut ekranu|PrtSc|Snip)").unwrap(),
-    ];
     let other_images_dir = Path::new(base_path).join("Other Images");
     let whatsapp_dir = other_images_dir.join("Whatsapp");
     let screenshots_dir = other_images_dir.join("Screenshots");
@@ -30,45 +64,74 @@ pub fn ask_and_separate_whatsapp_screenshots(base_path: &str, separate_wa_sc: bo
     let mut processed = 0;
     for entry in all_files {
         let path = entry.path();
+        // Already separated by an interrupted earlier run
+        if path.starts_with(&other_images_dir) {
+            continue;
+        }
         if let Some(filename) = path.file_name().and_then(|n| n.to_str()) {
+            let separation = separation_folder(filename);
             // WhatsApp
-            if whatsapp_patterns.iter().any(|re| re.is_match(filename)) {
-                let dest = whatsapp_dir.join(filename);
+            if separation == Some("Whatsapp") {
+                let dest = separation_destination(path, &whatsapp_dir.join(filename), on_collision, &logs_dir, collisions_csv)?;
                 let _ = fs::rename(path, &dest);
                 log_to_file(&logs_dir, "media_cleaning.log", &format!("Moved WhatsApp image {:?} to {:?}", path, dest));
                 // Move .json if exists
                 let json_path = path.with_extension(format!("{}.json", path.extension().and_then(|e| e.to_str()).unwrap_or("")));
                 if json_path.exists() {
-                    let json_dest = whatsapp_dir.join(json_path.file_name().unwrap());
+                    let json_dest = json_sidecar(&dest);
                     let _ = fs::rename(&json_path, &json_dest);
                     log_to_file(&logs_dir, "media_cleaning.log", &format!("Moved WhatsApp JSON {:?} to {:?}", json_path, json_dest));
                 }
+                journal.record_with(Stage::Separation, path, dest.to_string_lossy().into());
                 processed += 1;
                 print_progress(processed, total, path);
                 continue;
             }
             // Screenshot
-            if screenshot_patterns.iter().any(|re| re.is_match(filename)) {
-                let dest = screenshots_dir.join(filename);
+            if separation == Some("Screenshots") {
+                let dest = separation_destination(path, &screenshots_dir.join(filename), on_collision, &logs_dir, collisions_csv)?;
                 let _ = fs::rename(path, &dest);
                 log_to_file(&logs_dir, "media_cleaning.log", &format!("Moved Screenshot image {:?} to {:?}", path, dest));
                 // Move .json if exists
                 let json_path = path.with_extension(format!("{}.json", path.extension().and_then(|e| e.to_str()).unwrap_or("")));
                 if json_path.exists() {
-                    let json_dest = screenshots_dir.join(json_path.file_name().unwrap());
+                    let json_dest = json_sidecar(&dest);
                     let _ = fs::rename(&json_path, &json_dest);
                     log_to_file(&logs_dir, "media_cleaning.log", &format!("Moved Screenshot JSON {:?} to {:?}", json_path, json_dest));
                 }
+                journal.record_with(Stage::Separation, path, dest.to_string_lossy().into());
                 processed += 1;
                 print_progress(processed, total, path);
             }
         }
     }
+    journal.finish_stage(Stage::Separation);
     println!("\n🧹 WhatsApp/Screenshot separation complete! Processed {} files.", processed);
+    Ok(())
+}
+
+/// Where a separated file goes. An identical file already there is kept too,
+/// under a number: deduplication removes it later and merges its JSON.
+fn separation_destination(path: &Path, dest: &Path, on_collision: CollisionStrategy, logs_dir: &Path, collisions_csv: &Path) -> error::Result<PathBuf> {
+    let strategy = if on_collision == CollisionStrategy::SkipIdentical { CollisionStrategy::Number } else { on_collision };
+    match collision::land(path, dest, strategy)? {
+        Some(Resolution::Renamed(renamed)) => {
+            collision::record(path, dest, &Resolution::Renamed(renamed.clone()), logs_dir, "media_cleaning.log", collisions_csv);
+            Ok(renamed)
+        }
+        _ => Ok(dest.to_path_buf()),
+    }
+}
+
+/// `<file>.<ext>.json`, the sidecar name after JSON cleaning.
+pub(crate) fn json_sidecar(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".json");
+    PathBuf::from(name)
 }
 
 fn print_progress(done: usize, total: usize, file: &Path) {
-    let percent = if total > 0 { (done * 100) / total } else { 100 };
+    let percent = (done * 100).checked_div(total).unwrap_or(100);
     let bar = "🧹".repeat(percent / 4);
     let fname = file.file_name().and_then(|n| n.to_str()).unwrap_or("");
     print!("\r🧹 Cleaning: {} {}% ({}/{}) | {}", bar, percent, done, total, fname);
@@ -78,51 +141,55 @@ fn print_progress(done: usize, total: usize, file: &Path) {
     }
 }
 
-pub fn clean_json_filenames(base_path: &str) {
-    let media_extensions = vec![
-        // Images
-        "jpg", "jpeg", "png", "webp", "heic", "heif", "bmp", "tiff", "gif", "avif", "jxl", "jfif",
-        // Videos
-        "mp4", "mov", "mkv", "avi", "webm", "3gp", "m4v", "mpg", "mpeg", "mts", "m2ts", "ts", "flv",
-        "f4v", "wmv", "asf", "rm", "rmvb", "vob", "ogv", "mxf", "dv", "divx", "xvid"
-    ];
+/// Finds JSON sidecars whose name differs from `<media name>.json` (see
+/// [`resolve_sidecars`]) and returns the `(current, cleaned)` renames without
+/// touching the disk.
+pub fn plan_json_renames(base_path: &str) -> Vec<(PathBuf, PathBuf)> {
+    let files: Vec<PathBuf> = WalkDir::new(base_path).sort_by_file_name()
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|e| e.path().is_file())
+        .map(|e| e.into_path())
+        .collect();
+    plan_json_renames_in(&files)
+}
+
+/// Like [`plan_json_renames`], but over a list of file paths instead of a
+/// folder on disk, so it also works on a merged multi-part tree.
+pub fn plan_json_renames_in(files: &[PathBuf]) -> Vec<(PathBuf, PathBuf)> {
+    resolve_sidecars(files).renames
+}
+
+/// Renames every JSON sidecar to `<media name>.json` and reports ambiguous
+/// and orphaned JSON files in `sidecars.log`.
+pub fn clean_json_filenames(base_path: &str, logs_dir: &Path, journal: &mut Journal) -> SidecarMatches {
+    if journal.is_stage_done(Stage::JsonCleaning) {
+        return SidecarMatches::default();
+    }
+    let files: Vec<PathBuf> = WalkDir::new(base_path).sort_by_file_name()
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|e| e.path().is_file())
+        .map(|e| e.into_path())
+        .collect();
+    let matches = resolve_sidecars(&files);
+    log_report(&matches, logs_dir);
 
     let temp_dir = Path::new(base_path).join("`MetaSort_temp");
     let _ = fs::create_dir_all(&temp_dir);
     let log_path = temp_dir.join("rename_log.txt");
     let mut log_file = fs::File::create(&log_path).expect("Failed to create log file");
 
-    for entry in WalkDir::new(base_path).into_iter().filter_map(Result::ok) {
-        let path = entry.path();
-        if path.is_file() {
-            if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
-                let ext_lc = ext.to_lowercase();
-                if media_extensions.contains(&ext_lc.as_str()) {
-                    let filename = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
-                    let parent = path.parent().unwrap_or(Path::new(""));
-                    if let Ok(entries) = fs::read_dir(parent) {
-                        for json_entry in entries {
-                            if let Ok(json_file) = json_entry {
-                                let json_path = json_file.path();
-                                if json_path.is_file() {
-                                    if let Some(json_name) = json_path.file_name().and_then(|n| n.to_str()) {
-                                        if json_name.starts_with(filename) && json_name.ends_with(".json") && json_name != format!("{}.json", filename) {
-                                            let new_json_path = parent.join(format!("{}.json", filename));
-                                            if let Err(e) = fs::rename(&json_path, &new_json_path) {
-                                                let _ = log_file.write_all(format!("❌ Failed to rename {:?} to {:?}: {}\n", json_path, new_json_path, e).as_bytes());
-                                            } else {
-                                                let _ = log_file.write_all(format!("✅ Renamed JSON {:?} to {:?}\n", json_path, new_json_path).as_bytes());
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+    for (json_path, new_json_path) in &matches.renames {
+        if let Err(e) = fs::rename(json_path, new_json_path) {
+            let _ = log_file.write_all(format!("❌ Failed to rename {:?} to {:?}: {}\n", json_path, new_json_path, e).as_bytes());
+        } else {
+            let _ = log_file.write_all(format!("✅ Renamed JSON {:?} to {:?}\n", json_path, new_json_path).as_bytes());
+            journal.record(Stage::JsonCleaning, json_path);
         }
     }
-    let summary = format!("\n🧹 JSON filename cleaning complete.\n");
+    journal.finish_stage(Stage::JsonCleaning);
+    let summary = "\n🧹 JSON filename cleaning complete.\n";
     let _ = log_file.write_all(summary.as_bytes());
-} 
\ No newline at end of file
+    matches
+}
@@ -0,0 +1,370 @@
+// media_info.rs
+// Typed media metadata reader for MetaSort_v1.0.0 – Google Photos Takeout Organizer
+
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use chrono::{FixedOffset, NaiveDateTime};
+use exif::{In, Reader, Tag, Value};
+use img_parts::jpeg::Jpeg;
+use serde::{Deserialize, Deserializer};
+use crate::exiftool;
+use crate::native_metadata::{native_format, NativeFormat};
+use crate::platform::is_exiftool_available;
+use crate::quicktime;
+use crate::timezone::parse_offset;
+
+/// What a media file says about itself, as read from the file (not from the
+/// Takeout JSON).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MediaInfo {
+    /// `DateTimeOriginal`, or the QuickTime creation date of a video, in EXIF
+    /// format (`YYYY:MM:DD HH:MM:SS`) and local time.
+    pub date_taken: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub mime_type: Option<String>,
+    /// exiftool's file type name, e.g. `JPEG` or `MP4`.
+    pub file_type: Option<String>,
+    pub gps_latitude: Option<f64>,
+    pub gps_longitude: Option<f64>,
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    /// Country of the IPTC/XMP location, only read with exiftool.
+    pub country: Option<String>,
+}
+
+impl MediaInfo {
+    /// `WIDTHxHEIGHT`, as shown in the CSV reports.
+    pub fn image_size(&self) -> Option<String> {
+        Some(format!("{}x{}", self.width?, self.height?))
+    }
+
+    pub fn is_video(&self) -> bool {
+        self.mime_type.as_deref().is_some_and(|mime| mime.starts_with("video/"))
+    }
+}
+
+/// Reads a file's metadata with exiftool when it is installed, and with the
+/// built-in readers (EXIF, PNG/JPEG headers, QuickTime atoms) otherwise or
+/// when exiftool fails on the file.
+pub fn read_media_info(path: &Path) -> MediaInfo {
+    if is_exiftool_available() {
+        if let Some(info) = read_with_exiftool(path) {
+            return info;
+        }
+    }
+    read_native(path)
+}
+
+/// One file of exiftool's `-json -n` output. With `-n` numbers come out as
+/// JSON numbers, but a value exiftool could not convert stays a string, so
+/// every field accepts both.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "PascalCase")]
+struct ExifToolRecord {
+    #[serde(deserialize_with = "text")]
+    date_time_original: Option<String>,
+    #[serde(deserialize_with = "text")]
+    creation_date: Option<String>,
+    #[serde(rename = "MIMEType", deserialize_with = "text")]
+    mime_type: Option<String>,
+    #[serde(deserialize_with = "text")]
+    file_type: Option<String>,
+    #[serde(deserialize_with = "number")]
+    image_width: Option<f64>,
+    #[serde(deserialize_with = "number")]
+    image_height: Option<f64>,
+    #[serde(rename = "GPSLatitude", deserialize_with = "number")]
+    gps_latitude: Option<f64>,
+    #[serde(rename = "GPSLongitude", deserialize_with = "number")]
+    gps_longitude: Option<f64>,
+    #[serde(deserialize_with = "text")]
+    make: Option<String>,
+    #[serde(deserialize_with = "text")]
+    model: Option<String>,
+    #[serde(deserialize_with = "text")]
+    country: Option<String>,
+    #[serde(rename = "Country-PrimaryLocationName", deserialize_with = "text")]
+    country_primary_location_name: Option<String>,
+}
+
+fn text<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    Ok(match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::String(s) => Some(s),
+        serde_json::Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    })
+}
+
+fn number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f64>, D::Error> {
+    Ok(match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::Number(n) => n.as_f64(),
+        serde_json::Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    })
+}
+
+fn read_with_exiftool(path: &Path) -> Option<MediaInfo> {
+    // The signed Composite GPS values, not the unsigned EXIF ones
+    let args = [
+        "-json", "-n", "-DateTimeOriginal", "-CreationDate", "-MIMEType", "-FileType",
+        "-ImageWidth", "-ImageHeight", "-Composite:GPSLatitude", "-Composite:GPSLongitude",
+        "-Make", "-Model", "-Country", "-Country-PrimaryLocationName",
+    ].map(OsStr::new);
+    let output = exiftool::execute_on(&args, path).ok()?;
+    parse_exiftool_json(&output.stdout)
+}
+
+/// The [`MediaInfo`] of the first file in exiftool's `-json -n` output.
+fn parse_exiftool_json(output: &str) -> Option<MediaInfo> {
+    let record = serde_json::from_str::<Vec<ExifToolRecord>>(output).ok()?.into_iter().next()?;
+    let dimension = |value: Option<f64>| value.filter(|v| *v >= 1.0).map(|v| v as u32);
+    Some(MediaInfo {
+        date_taken: record.date_time_original.as_deref().and_then(exif_date)
+            .or_else(|| record.creation_date.as_deref().and_then(exif_date)),
+        width: dimension(record.image_width),
+        height: dimension(record.image_height),
+        mime_type: record.mime_type,
+        file_type: record.file_type,
+        gps_latitude: record.gps_latitude,
+        gps_longitude: record.gps_longitude,
+        camera_make: record.make,
+        camera_model: record.model,
+        country: record.country.or(record.country_primary_location_name),
+    })
+}
+
+/// The `YYYY:MM:DD HH:MM:SS` part of an exiftool date, dropping sub-seconds
+/// and time zones. Zeroed dates ("0000:00:00 00:00:00") count as missing.
+fn exif_date(value: &str) -> Option<String> {
+    let date = value.trim().get(..19)?;
+    NaiveDateTime::parse_from_str(date, "%Y:%m:%d %H:%M:%S").ok()?;
+    Some(date.to_string())
+}
+
+fn read_native(path: &Path) -> MediaInfo {
+    let (file_type, mime_type) = file_type(path);
+    let mut info = MediaInfo { file_type, mime_type, ..MediaInfo::default() };
+    if native_format(path) == Some(NativeFormat::QuickTime) {
+        info.date_taken = quicktime::read_creation_date(path);
+        (info.width, info.height) = quicktime::read_dimensions(path).unzip();
+        (info.gps_latitude, info.gps_longitude) = quicktime::read_location(path).unzip();
+        return info;
+    }
+    let exif = File::open(path).ok()
+        .and_then(|file| Reader::new().read_from_container(&mut BufReader::new(file)).ok());
+    if let Some(exif) = &exif {
+        info.date_taken = match exif.get_field(Tag::DateTimeOriginal, In::PRIMARY).map(|f| &f.value) {
+            Some(Value::Ascii(values)) => values.first().and_then(|v| exif_date(&String::from_utf8_lossy(v))),
+            _ => None,
+        };
+        info.width = exif.get_field(Tag::PixelXDimension, In::PRIMARY).and_then(|f| f.value.get_uint(0));
+        info.height = exif.get_field(Tag::PixelYDimension, In::PRIMARY).and_then(|f| f.value.get_uint(0));
+        info.gps_latitude = exif_coordinate(exif, Tag::GPSLatitude, Tag::GPSLatitudeRef, b'S');
+        info.gps_longitude = exif_coordinate(exif, Tag::GPSLongitude, Tag::GPSLongitudeRef, b'W');
+        info.camera_make = exif_text(exif, Tag::Make);
+        info.camera_model = exif_text(exif, Tag::Model);
+    }
+    if info.width.is_none() || info.height.is_none() {
+        (info.width, info.height) = image_dimensions(path).unzip();
+    }
+    info
+}
+
+/// UTC offset of the date a file was taken, as the camera stored it: EXIF
+/// `OffsetTimeOriginal`, or the offset of a video's QuickTime creation date.
+/// Only the built-in readers are used, as this runs for every file.
+pub fn read_time_offset(path: &Path) -> Option<FixedOffset> {
+    if native_format(path) == Some(NativeFormat::QuickTime) {
+        return quicktime::read_creation_offset(path);
+    }
+    let exif = Reader::new().read_from_container(&mut BufReader::new(File::open(path).ok()?)).ok()?;
+    exif_text(&exif, Tag::OffsetTimeOriginal).and_then(|offset| parse_offset(&offset))
+}
+
+fn exif_text(exif: &exif::Exif, tag: Tag) -> Option<String> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(values) => values.first()
+            .map(|v| String::from_utf8_lossy(v).trim_end_matches('\0').trim().to_string())
+            .filter(|v| !v.is_empty()),
+        _ => None,
+    }
+}
+
+/// Signed degrees of an EXIF GPS coordinate; `negative` is the reference
+/// letter of the southern or western hemisphere.
+fn exif_coordinate(exif: &exif::Exif, tag: Tag, ref_tag: Tag, negative: u8) -> Option<f64> {
+    let Value::Rational(parts) = &exif.get_field(tag, In::PRIMARY)?.value else {
+        return None;
+    };
+    let degrees = parts.iter().zip([1.0, 60.0, 3600.0]).map(|(part, scale)| part.to_f64() / scale).sum::<f64>();
+    let is_negative = match &exif.get_field(ref_tag, In::PRIMARY).map(|f| &f.value) {
+        Some(Value::Ascii(values)) => values.first().and_then(|v| v.first()) == Some(&negative),
+        _ => false,
+    };
+    Some(if is_negative { -degrees } else { degrees })
+}
+
+/// Pixel size from the PNG `IHDR` or the JPEG frame header.
+fn image_dimensions(path: &Path) -> Option<(u32, u32)> {
+    let mut header = [0u8; 24];
+    File::open(path).ok()?.read_exact(&mut header).ok()?;
+    if header.starts_with(b"\x89PNG\r\n\x1a\n") && &header[12..16] == b"IHDR" {
+        let width = u32::from_be_bytes(header[16..20].try_into().ok()?);
+        let height = u32::from_be_bytes(header[20..24].try_into().ok()?);
+        return Some((width, height));
+    }
+    if header.starts_with(&[0xFF, 0xD8]) {
+        let jpeg = Jpeg::from_bytes(std::fs::read(path).ok()?.into()).ok()?;
+        // SOF0-SOF15, except DHT (C4), JPG (C8) and DAC (CC)
+        let frame = jpeg.segments().iter()
+            .find(|s| (0xC0..=0xCF).contains(&s.marker()) && ![0xC4, 0xC8, 0xCC].contains(&s.marker()))?;
+        let contents = frame.contents();
+        let height = u16::from_be_bytes(contents.get(1..3)?.try_into().ok()?);
+        let width = u16::from_be_bytes(contents.get(3..5)?.try_into().ok()?);
+        return Some((width.into(), height.into()));
+    }
+    None
+}
+
+/// `(FileType, MIMEType)` of a file by its extension, as exiftool names them.
+pub fn file_type(path: &Path) -> (Option<String>, Option<String>) {
+    let ext = path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    let (file_type, mime) = match ext.as_str() {
+        "jpg" | "jpeg" | "jfif" => ("JPEG", "image/jpeg"),
+        "png" => ("PNG", "image/png"),
+        "gif" => ("GIF", "image/gif"),
+        "webp" => ("WEBP", "image/webp"),
+        "heic" => ("HEIC", "image/heic"),
+        "heif" => ("HEIF", "image/heif"),
+        "avif" => ("AVIF", "image/avif"),
+        "bmp" => ("BMP", "image/bmp"),
+        "tif" | "tiff" => ("TIFF", "image/tiff"),
+        "dng" => ("DNG", "image/x-adobe-dng"),
+        "mp4" | "m4v" => ("MP4", "video/mp4"),
+        "mov" => ("MOV", "video/quicktime"),
+        "3gp" => ("3GP", "video/3gpp"),
+        "mkv" => ("MKV", "video/x-matroska"),
+        "avi" => ("AVI", "video/x-msvideo"),
+        "webm" => ("WEBM", "video/webm"),
+        "mpg" | "mpeg" => ("MPEG", "video/mpeg"),
+        "mts" | "m2ts" | "ts" => ("M2TS", "video/m2ts"),
+        "wmv" => ("WMV", "video/x-ms-wmv"),
+        "" => return (None, None),
+        _ => return (Some(ext.to_uppercase()), None),
+    };
+    (Some(file_type.to_string()), Some(mime.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageFormat, RgbImage};
+    use crate::metadata_extraction::MediaMetadata;
+    use crate::native_metadata::write_metadata;
+
+    #[test]
+    fn exiftool_numbers_and_strings_are_both_read() {
+        let output = r#"[{
+            "SourceFile": "IMG_0001.jpg",
+            "DateTimeOriginal": "2021:05:01 10:30:00.123+02:00",
+            "MIMEType": "image/jpeg",
+            "FileType": "JPEG",
+            "ImageWidth": 4032,
+            "ImageHeight": "3024",
+            "GPSLatitude": -33.8568,
+            "GPSLongitude": "151.2153",
+            "Make": "Google",
+            "Model": 5,
+            "Country-PrimaryLocationName": "Australia"
+        }]"#;
+        let info = parse_exiftool_json(output).unwrap();
+        assert_eq!(info, MediaInfo {
+            date_taken: Some("2021:05:01 10:30:00".to_string()),
+            width: Some(4032),
+            height: Some(3024),
+            mime_type: Some("image/jpeg".to_string()),
+            file_type: Some("JPEG".to_string()),
+            gps_latitude: Some(-33.8568),
+            gps_longitude: Some(151.2153),
+            camera_make: Some("Google".to_string()),
+            camera_model: Some("5".to_string()),
+            country: Some("Australia".to_string()),
+        });
+        assert_eq!(info.image_size().as_deref(), Some("4032x3024"));
+    }
+
+    #[test]
+    fn missing_and_unconvertible_exiftool_values_are_none() {
+        // A video without DateTimeOriginal, with a zeroed creation date and a dimension exiftool left as text
+        let output = r#"[{
+            "SourceFile": "clip.mp4",
+            "CreationDate": "0000:00:00 00:00:00",
+            "MIMEType": "video/mp4",
+            "ImageWidth": "n/a",
+            "ImageHeight": 0,
+            "GPSLatitude": null
+        }]"#;
+        let info = parse_exiftool_json(output).unwrap();
+        assert_eq!(info, MediaInfo { mime_type: Some("video/mp4".to_string()), ..MediaInfo::default() });
+        assert!(info.is_video());
+        assert_eq!(info.image_size(), None);
+
+        let output = r#"[{"CreationDate": "2020:12:24 18:00:05-05:00"}]"#;
+        assert_eq!(parse_exiftool_json(output).unwrap().date_taken.as_deref(), Some("2020:12:24 18:00:05"));
+        assert_eq!(parse_exiftool_json("[]"), None);
+        assert_eq!(parse_exiftool_json("Error: File not found"), None);
+    }
+
+    #[test]
+    fn exif_dates_drop_sub_seconds_and_offsets() {
+        assert_eq!(exif_date("2021:05:01 10:30:00").as_deref(), Some("2021:05:01 10:30:00"));
+        assert_eq!(exif_date(" 2021:05:01 10:30:00.45 ").as_deref(), Some("2021:05:01 10:30:00"));
+        assert_eq!(exif_date("2021:05:01 10:30:00Z").as_deref(), Some("2021:05:01 10:30:00"));
+        assert_eq!(exif_date("0000:00:00 00:00:00"), None);
+        assert_eq!(exif_date("2021:05:01"), None);
+    }
+
+    #[test]
+    fn native_reader_reads_the_exif_of_a_jpeg() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("photo.jpg");
+        RgbImage::new(8, 6).save_with_format(&path, ImageFormat::Jpeg).unwrap();
+        assert_eq!(read_time_offset(&path), None);
+        let meta = MediaMetadata {
+            media_path: path.clone(),
+            gps_latitude: Some(-33.8568),
+            gps_longitude: Some(-70.5),
+            camera_make: Some("Google".to_string()),
+            camera_model: Some("Pixel 5".to_string()),
+            ..MediaMetadata::default()
+        };
+        write_metadata(&meta, Some("2021:05:01 10:30:00"), Some("-04:00")).unwrap();
+
+        let info = read_native(&path);
+        assert_eq!(info.date_taken.as_deref(), Some("2021:05:01 10:30:00"));
+        assert_eq!((info.width, info.height), (Some(8), Some(6)));
+        assert_eq!((info.file_type.as_deref(), info.mime_type.as_deref()), (Some("JPEG"), Some("image/jpeg")));
+        assert!((info.gps_latitude.unwrap() + 33.8568).abs() < 1e-6);
+        assert!((info.gps_longitude.unwrap() + 70.5).abs() < 1e-6);
+        assert_eq!((info.camera_make.as_deref(), info.camera_model.as_deref()), (Some("Google"), Some("Pixel 5")));
+        assert_eq!(read_time_offset(&path), parse_offset("-04:00"));
+    }
+
+    #[test]
+    fn native_reader_falls_back_to_the_png_header() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        RgbImage::new(5, 3).save_with_format(&path, ImageFormat::Png).unwrap();
+        let info = read_native(&path);
+        assert_eq!(info, MediaInfo {
+            width: Some(5),
+            height: Some(3),
+            file_type: Some("PNG".to_string()),
+            mime_type: Some("image/png".to_string()),
+            ..MediaInfo::default()
+        });
+    }
+}
@@ -0,0 +1,479 @@
+// merge.rs
+// Multi-part Takeout merging for MetaSort_v1.0.0 – Google Photos Takeout Organizer
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+use walkdir::WalkDir;
+use crate::archive::{archive_kind, list_entries, read_archive};
+use crate::dedup::hash_file;
+use crate::error::Result;
+use crate::sidecar::{resolve_sidecars, SidecarMatches};
+use crate::metadata_extraction::pair_files;
+
+/// Path of a file inside the Takeout, independent of where its part was
+/// extracted: everything up to and including the last `Takeout` folder is
+/// dropped, so `takeout-001/Takeout/Google Photos/x.jpg` and
+/// `Google Photos/x.jpg` (a part opened at its `Takeout` folder) are the same file.
+pub fn logical_path(relative: &Path) -> PathBuf {
+    let components: Vec<Component> = relative.components().collect();
+    let start = components
+        .iter()
+        .rposition(|c| c.as_os_str().eq_ignore_ascii_case("Takeout"))
+        .map_or(0, |i| i + 1);
+    components[start..].iter().collect()
+}
+
+/// Several Takeout parts seen as one folder tree.
+///
+/// A split export repeats folders such as `Google Photos/Photos from 2019` in
+/// every part, and a media file's JSON may sit in another part than the media
+/// itself. The tree maps each logical path to the real file providing it, so
+/// pairing, cleaning and sorting see a single library. A file present in
+/// several parts with identical contents is kept once; a different file at an
+/// already taken path gets a ` (part N)` suffix instead of hiding the first one,
+/// and its JSON sidecar is renamed with it.
+///
+/// A part can be an archive, whose files are listed without extracting them.
+/// Their real path is the archive's path followed by the path inside it,
+/// e.g. `takeout-002.zip/Takeout/Google Photos/x.jpg`; no file exists there.
+#[derive(Debug, Clone, Default)]
+pub struct VirtualTree {
+    files: BTreeMap<PathBuf, PathBuf>,
+    logical: HashMap<PathBuf, PathBuf>,
+    duplicates: Vec<PathBuf>,
+    renamed: Vec<(PathBuf, PathBuf)>,
+    /// Where files brought over by an interrupted run are now, by real path.
+    transferred: HashMap<PathBuf, PathBuf>,
+    /// Files inside archives, by real path.
+    archived: HashMap<PathBuf, ArchivedFile>,
+    /// BLAKE3 hashes of the archived files read so far, by real path.
+    hashes: HashMap<PathBuf, String>,
+    /// Archived files read out to disk, by where they are now.
+    unpacked: HashMap<PathBuf, PathBuf>,
+}
+
+#[derive(Debug, Clone)]
+struct ArchivedFile {
+    archive: PathBuf,
+    /// Path inside the archive.
+    entry: PathBuf,
+    size: u64,
+}
+
+impl VirtualTree {
+    /// Merges the files below every root, folder or archive, in order;
+    /// earlier roots win a path.
+    pub fn build(roots: &[PathBuf]) -> Result<VirtualTree> {
+        VirtualTree::build_resumed(roots, &[], Path::new(""))
+    }
+
+    /// Like [`VirtualTree::build`], for a run resumed after the files in
+    /// `transferred`, given as `(real, logical)` paths, were brought into
+    /// `working_dir`. They keep their logical path even when they are gone
+    /// from the input (`--transfer move`), so a file of a later part that
+    /// clashes with one of them is still renamed instead of landing on it.
+    pub fn build_resumed(roots: &[PathBuf], transferred: &[(PathBuf, PathBuf)], working_dir: &Path) -> Result<VirtualTree> {
+        let mut tree = VirtualTree::default();
+        for (real, logical) in transferred {
+            tree.logical.insert(real.clone(), logical.clone());
+            tree.files.insert(logical.clone(), real.clone());
+            tree.transferred.insert(real.clone(), working_dir.join(logical));
+        }
+        let mut parts: Vec<Vec<(PathBuf, PathBuf)>> = Vec::new();
+        for root in roots {
+            let mut files = Vec::new();
+            if root.is_file() && archive_kind(root).is_some() {
+                for entry in list_entries(root)? {
+                    let real = root.join(&entry.path);
+                    if tree.transferred.contains_key(&real) {
+                        continue;
+                    }
+                    files.push((logical_path(&entry.path), real.clone()));
+                    tree.archived.insert(real, ArchivedFile { archive: root.clone(), entry: entry.path, size: entry.size });
+                }
+                // In the order of a folder, so a media file comes before its JSON
+                files.sort_by(|a, b| a.1.cmp(&b.1));
+            } else {
+                for entry in WalkDir::new(root).sort_by_file_name().into_iter().filter_map(|e| e.ok()) {
+                    let real = entry.path();
+                    if real.is_file() {
+                        files.push((logical_path(real.strip_prefix(root).unwrap_or(real)), real.to_path_buf()));
+                    }
+                }
+            }
+            files.retain(|(_, real)| !tree.transferred.contains_key(real));
+            parts.push(files);
+        }
+        tree.hash_clashing(&parts)?;
+        for (part, files) in parts.into_iter().enumerate() {
+            for (logical, real) in files {
+                tree.insert(logical, real, part + 1);
+            }
+        }
+        Ok(tree)
+    }
+
+    /// Hashes the archived files that share their logical path and size with
+    /// another file, so [`VirtualTree::same_contents`] can compare them.
+    fn hash_clashing(&mut self, parts: &[Vec<(PathBuf, PathBuf)>]) -> Result<()> {
+        let mut by_path: HashMap<&Path, Vec<&Path>> = HashMap::new();
+        for (logical, real) in &self.files {
+            by_path.entry(logical).or_default().push(real);
+        }
+        for (logical, real) in parts.iter().flatten() {
+            by_path.entry(logical).or_default().push(real);
+        }
+        let mut wanted = HashSet::new();
+        for reals in by_path.values().filter(|reals| reals.len() > 1) {
+            let sizes: Vec<Option<u64>> = reals.iter().map(|real| self.size_of(real)).collect();
+            for (real, size) in reals.iter().zip(&sizes) {
+                if size.is_some() && sizes.iter().filter(|other| *other == size).count() > 1 {
+                    wanted.insert(real.to_path_buf());
+                }
+            }
+        }
+        self.read_archives(&wanted, &HashSet::new(), Path::new(""))
+    }
+
+    fn insert(&mut self, logical: PathBuf, real: PathBuf, part: usize) {
+        // A JSON follows its media file when that was renamed, so they still pair
+        if let Some(renamed) = self.renamed_sidecar(&real).filter(|renamed| !self.files.contains_key(renamed)) {
+            self.renamed.push((real.clone(), renamed.clone()));
+            self.logical.insert(real.clone(), renamed.clone());
+            self.files.insert(renamed, real);
+            return;
+        }
+        let logical = match self.files.get(&logical) {
+            None => logical,
+            Some(existing) if self.same_contents(existing, &real) => {
+                self.duplicates.push(real);
+                return;
+            }
+            Some(_) => {
+                let renamed = part_path(&logical, part);
+                self.renamed.push((real.clone(), renamed.clone()));
+                renamed
+            }
+        };
+        self.logical.insert(real.clone(), logical.clone());
+        self.files.insert(logical, real);
+    }
+
+    /// The logical path of a JSON sidecar whose media file, next to it in the
+    /// same part, was renamed: `IMG.jpg.json` becomes `IMG (part 2).jpg.json`.
+    fn renamed_sidecar(&self, real: &Path) -> Option<PathBuf> {
+        let name = real.file_name()?.to_str()?;
+        if !name.to_lowercase().ends_with(".json") {
+            return None;
+        }
+        name.rmatch_indices('.').find_map(|(at, _)| {
+            let media = self.logical.get(&real.with_file_name(&name[..at]))?;
+            let renamed = media.file_name()?.to_str()?;
+            (renamed != &name[..at]).then(|| media.with_file_name(format!("{}{}", renamed, &name[at..])))
+        })
+    }
+
+    /// Reads what a dry run needs from the archives in the tree, without
+    /// extracting them: JSON files are written below `dir` and read from there
+    /// (see [`VirtualTree::source_of`]), and every file whose size another
+    /// file in the tree shares is hashed, so copies can be found.
+    pub fn read_for_planning(&mut self, dir: &Path) -> Result<()> {
+        let mut sizes: HashMap<u64, usize> = HashMap::new();
+        for real in self.files.values() {
+            if let Some(size) = self.size_of(real) {
+                *sizes.entry(size).or_default() += 1;
+            }
+        }
+        let (mut hashed, mut unpacked) = (HashSet::new(), HashSet::new());
+        for real in self.files.values() {
+            let Some(file) = self.archived.get(real) else { continue };
+            if file.entry.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("json")) {
+                unpacked.insert(real.clone());
+            } else if sizes.get(&file.size).is_some_and(|count| *count > 1) {
+                hashed.insert(real.clone());
+            }
+        }
+        self.read_archives(&hashed, &unpacked, dir)
+    }
+
+    /// Reads the archived files in `hashed` and `unpacked` in one pass per
+    /// archive: the first are hashed, the second written below `dir`, each
+    /// archive in a numbered folder of its own, and used from there.
+    fn read_archives(&mut self, hashed: &HashSet<PathBuf>, unpacked: &HashSet<PathBuf>, dir: &Path) -> Result<()> {
+        let mut archives: BTreeMap<&Path, (HashSet<PathBuf>, HashMap<PathBuf, PathBuf>)> = BTreeMap::new();
+        for real in hashed.iter().chain(unpacked) {
+            if let Some(file) = self.archived.get(real) {
+                archives.entry(file.archive.as_path()).or_default();
+            }
+        }
+        let numbers: HashMap<&Path, usize> = archives.keys().enumerate().map(|(i, archive)| (*archive, i + 1)).collect();
+        for real in hashed {
+            if let Some(file) = self.archived.get(real) {
+                archives.entry(file.archive.as_path()).or_default().0.insert(file.entry.clone());
+            }
+        }
+        for real in unpacked {
+            if let Some(file) = self.archived.get(real) {
+                let dest = dir.join(format!("{:03}", numbers[file.archive.as_path()])).join(&file.entry);
+                archives.entry(file.archive.as_path()).or_default().1.insert(file.entry.clone(), dest);
+            }
+        }
+        let mut hashes = Vec::new();
+        let mut moves = Vec::new();
+        for (archive, (hashed, destinations)) in &archives {
+            for (entry, hash) in read_archive(archive, destinations, hashed, |_| {})? {
+                hashes.push((archive.join(entry), hash));
+            }
+            moves.extend(destinations.iter().map(|(entry, dest)| (archive.join(entry), dest.clone())));
+        }
+        self.hashes.extend(hashes);
+        for (real, dest) in moves {
+            if let Some(logical) = self.logical.remove(&real) {
+                self.files.insert(logical.clone(), dest.clone());
+                self.logical.insert(dest.clone(), logical);
+                self.unpacked.insert(dest, real);
+            }
+        }
+        Ok(())
+    }
+
+    /// The file a real path of the tree stands for: for a file read out of
+    /// an archive by [`VirtualTree::read_for_planning`], its path in the
+    /// archive; else the path itself.
+    pub fn source_of<'a>(&'a self, real: &'a Path) -> &'a Path {
+        self.unpacked.get(real).map_or(real, PathBuf::as_path)
+    }
+
+    /// The archive a real path of the tree lies in, with the path inside it.
+    pub fn archive_entry(&self, real: &Path) -> Option<(&Path, &Path)> {
+        self.archived.get(real).map(|file| (file.archive.as_path(), file.entry.as_path()))
+    }
+
+    /// Size of a file of the tree, also when it lies in an archive.
+    pub fn size_of(&self, real: &Path) -> Option<u64> {
+        match self.archived.get(real) {
+            Some(file) => Some(file.size),
+            None => fs::metadata(self.transferred.get(real).map_or(real, PathBuf::as_path)).ok().map(|meta| meta.len()),
+        }
+    }
+
+    /// BLAKE3 hash of a file of the tree. An archived file only has one once
+    /// it was read, see [`VirtualTree::read_for_planning`].
+    pub fn hash_of(&self, real: &Path) -> Option<String> {
+        match self.archived.get(real) {
+            Some(_) => self.hashes.get(real).cloned(),
+            None => hash_file(self.transferred.get(real).map_or(real, PathBuf::as_path)).ok(),
+        }
+    }
+
+    /// True when two files of the tree have the same contents. Archived
+    /// files are compared by their hashes; without one they count as different.
+    pub fn same_contents(&self, a: &Path, b: &Path) -> bool {
+        if !self.archived.contains_key(a) && !self.archived.contains_key(b) {
+            let current = |real: &'_ Path| self.transferred.get(real).cloned().unwrap_or_else(|| real.to_path_buf());
+            return same_contents(&current(a), &current(b));
+        }
+        self.size_of(a).is_some() && self.size_of(a) == self.size_of(b)
+            && self.hash_of(a).is_some_and(|hash| self.hash_of(b) == Some(hash))
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// `(logical, real)` path of every file, sorted by logical path.
+    pub fn files(&self) -> impl Iterator<Item = (&Path, &Path)> {
+        self.files.iter().map(|(logical, real)| (logical.as_path(), real.as_path()))
+    }
+
+    pub fn real_path(&self, logical: &Path) -> Option<&Path> {
+        self.files.get(logical).map(PathBuf::as_path)
+    }
+
+    pub fn logical_path_of(&self, real: &Path) -> Option<&Path> {
+        self.logical.get(real).map(PathBuf::as_path)
+    }
+
+    /// Files left out because an identical copy came from an earlier part.
+    pub fn duplicates(&self) -> &[PathBuf] {
+        &self.duplicates
+    }
+
+    /// `(real, logical)` paths of files renamed because their path was taken
+    /// by a different file from an earlier part.
+    pub fn renamed(&self) -> &[(PathBuf, PathBuf)] {
+        &self.renamed
+    }
+
+    /// The JSON renames the cleaning stage would apply to the merged tree, as
+    /// `(current, cleaned)` real paths. The cleaned name stays next to the
+    /// JSON, even when its media lives in another part.
+    pub fn plan_json_renames(&self) -> Vec<(PathBuf, PathBuf)> {
+        self.resolve_sidecars().renames
+    }
+
+    /// Matches the JSON sidecars of the merged tree like the cleaning stage,
+    /// reported with real paths.
+    pub fn resolve_sidecars(&self) -> SidecarMatches {
+        let logical_files: Vec<PathBuf> = self.files.keys().cloned().collect();
+        let matches = resolve_sidecars(&logical_files);
+        let real = |logical: &PathBuf| self.real_path(logical).map(Path::to_path_buf).unwrap_or_else(|| logical.clone());
+        SidecarMatches {
+            matched: matches.matched.iter().map(|(json, media)| (real(json), real(media))).collect(),
+            renames: matches.renames.iter()
+                .filter_map(|(from, to)| {
+                    let real = self.real_path(from)?;
+                    Some((real.to_path_buf(), real.with_file_name(to.file_name()?)))
+                })
+                .collect(),
+            ambiguous: matches.ambiguous.iter().map(|(json, media)| (real(json), media.iter().map(real).collect())).collect(),
+            orphans: matches.orphans.iter().map(real).collect(),
+        }
+    }
+
+    /// Pairs media with their JSON across all parts, as if `renames` (from
+    /// [`VirtualTree::plan_json_renames`]) had been applied. Returns real
+    /// `(media, json)` paths and the real paths of media without a JSON.
+    pub fn pair_media_with_json(&self, renames: &[(PathBuf, PathBuf)]) -> (Vec<(PathBuf, PathBuf)>, Vec<PathBuf>) {
+        let logical_files: Vec<PathBuf> = self.files.keys().cloned().collect();
+        let logical_renames: Vec<(PathBuf, PathBuf)> = renames
+            .iter()
+            .filter_map(|(from, to)| {
+                let logical = self.logical_path_of(from)?;
+                Some((logical.to_path_buf(), logical.with_file_name(to.file_name()?)))
+            })
+            .collect();
+        let (pairs, unpaired) = pair_files(&logical_files, &logical_renames);
+        let real = |logical: &Path| self.real_path(logical).map(Path::to_path_buf).unwrap_or_else(|| logical.to_path_buf());
+        (
+            pairs.iter().map(|(media, json)| (real(media), real(json))).collect(),
+            unpaired.iter().map(|media| real(media)).collect(),
+        )
+    }
+}
+
+/// `dir/name (part N).ext` for a file that clashes with one from an earlier part.
+fn part_path(logical: &Path, part: usize) -> PathBuf {
+    let stem = logical.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let name = match logical.extension() {
+        Some(ext) => format!("{} (part {}).{}", stem, part, ext.to_string_lossy()),
+        None => format!("{} (part {})", stem, part),
+    };
+    logical.with_file_name(name)
+}
+
+pub(crate) fn same_contents(a: &Path, b: &Path) -> bool {
+    let (Ok(meta_a), Ok(meta_b)) = (fs::metadata(a), fs::metadata(b)) else {
+        return false;
+    };
+    if meta_a.len() != meta_b.len() {
+        return false;
+    }
+    let (Ok(file_a), Ok(file_b)) = (File::open(a), File::open(b)) else {
+        return false;
+    };
+    let (mut reader_a, mut reader_b) = (BufReader::new(file_a), BufReader::new(file_b));
+    let (mut buf_a, mut buf_b) = ([0u8; 8192], [0u8; 8192]);
+    loop {
+        let read = match reader_a.read(&mut buf_a) {
+            Ok(0) => return true,
+            Ok(n) => n,
+            Err(_) => return false,
+        };
+        if reader_b.read_exact(&mut buf_b[..read]).is_err() || buf_a[..read] != buf_b[..read] {
+            return false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn write_part(part: &Path, files: &[(&str, &str)]) {
+        for (name, contents) in files {
+            let path = part.join("Takeout/Google Photos").join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+    }
+
+    fn write_zip(path: &Path, files: &[(&str, &str)]) {
+        let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+        for (name, contents) in files {
+            zip.start_file(format!("Takeout/Google Photos/{}", name), zip::write::SimpleFileOptions::default()).unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    fn logical_files(tree: &VirtualTree) -> Vec<String> {
+        tree.files().map(|(logical, _)| logical.to_string_lossy().to_string()).collect()
+    }
+
+    #[test]
+    fn logical_paths_start_below_the_takeout_folder() {
+        assert_eq!(logical_path(Path::new("takeout-001/Takeout/Google Photos/x.jpg")), Path::new("Google Photos/x.jpg"));
+        assert_eq!(logical_path(Path::new("Google Photos/x.jpg")), Path::new("Google Photos/x.jpg"));
+    }
+
+    #[test]
+    fn identical_files_are_kept_once_and_different_ones_renamed() {
+        let dir = tempfile::tempdir().unwrap();
+        let parts = [dir.path().join("part1"), dir.path().join("part2")];
+        write_part(&parts[0], &[("2021/same.jpg", "same"), ("2021/IMG.jpg", "first")]);
+        write_part(&parts[1], &[("2021/same.jpg", "same"), ("2021/IMG.jpg", "other"), ("2021/IMG.jpg.json", "{}")]);
+        let tree = VirtualTree::build(&parts).unwrap();
+        assert_eq!(logical_files(&tree), [
+            "Google Photos/2021/IMG (part 2).jpg",
+            "Google Photos/2021/IMG (part 2).jpg.json",
+            "Google Photos/2021/IMG.jpg",
+            "Google Photos/2021/same.jpg",
+        ]);
+        assert_eq!(tree.duplicates(), [parts[1].join("Takeout/Google Photos/2021/same.jpg")]);
+        assert_eq!(tree.renamed().len(), 2);
+    }
+
+    #[test]
+    fn archived_parts_are_compared_by_their_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let (folder, zip) = (dir.path().join("part1"), dir.path().join("takeout-002.zip"));
+        write_part(&folder, &[("2021/same.jpg", "same"), ("2021/IMG.jpg", "first")]);
+        write_zip(&zip, &[("2021/same.jpg", "same"), ("2021/IMG.jpg", "other"), ("2021/new.jpg", "new")]);
+        let tree = VirtualTree::build(&[folder, zip.clone()]).unwrap();
+        assert_eq!(logical_files(&tree), [
+            "Google Photos/2021/IMG (part 2).jpg",
+            "Google Photos/2021/IMG.jpg",
+            "Google Photos/2021/new.jpg",
+            "Google Photos/2021/same.jpg",
+        ]);
+        let new = zip.join("Takeout/Google Photos/2021/new.jpg");
+        assert_eq!(tree.real_path(Path::new("Google Photos/2021/new.jpg")), Some(new.as_path()));
+        assert_eq!(tree.archive_entry(&new), Some((zip.as_path(), Path::new("Takeout/Google Photos/2021/new.jpg"))));
+        assert_eq!(tree.size_of(&new), Some(3));
+    }
+
+    #[test]
+    fn planning_reads_json_files_out_of_archives() {
+        let dir = tempfile::tempdir().unwrap();
+        let zip = dir.path().join("takeout.zip");
+        write_zip(&zip, &[("2021/IMG.jpg", "photo"), ("2021/IMG.jpg.json", "{}"), ("2021/copy.jpg", "photo")]);
+        let mut tree = VirtualTree::build(std::slice::from_ref(&zip)).unwrap();
+        tree.read_for_planning(&dir.path().join("scratch")).unwrap();
+
+        let json = tree.real_path(Path::new("Google Photos/2021/IMG.jpg.json")).unwrap().to_path_buf();
+        assert_eq!(fs::read_to_string(&json).unwrap(), "{}");
+        assert_eq!(tree.source_of(&json), zip.join("Takeout/Google Photos/2021/IMG.jpg.json"));
+        // Files of the same size are hashed, so copies can be found
+        let (photo, copy) = (zip.join("Takeout/Google Photos/2021/IMG.jpg"), zip.join("Takeout/Google Photos/2021/copy.jpg"));
+        assert_eq!(tree.hash_of(&photo), Some(blake3::hash(b"photo").to_hex().to_string()));
+        assert!(tree.same_contents(&photo, &copy));
+    }
+}
@@ -2,88 +2,178 @@
 // Embedding metadata logic for MetaSort_v1.0.0 – Google Photos Takeout Organizer 
 
 use std::fs::{self, File};
-use std::io::{self, Write};
+use std::io::Write;
 use std::path::Path;
 use crate::metadata_extraction::MediaMetadata;
 use crate::filename_date_guess::extract_date_from_filename;
-use crate::utils::log_to_file;
-use crate::platform::get_exiftool_command;
+use crate::journal::{Journal, Stage};
+use crate::utils::{for_each_ordered, log_to_file};
+use crate::exiftool;
+use crate::native_metadata::{self, native_format, MetadataWriter};
+use crate::platform::is_exiftool_available;
 
-pub fn embed_metadata_all(metadata_list: &[MediaMetadata], log_dir: &Path) {
+/// Where the embedded date/time of WhatsApp & Screenshot images comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DateSource {
+    /// Date from the Takeout JSON metadata
+    Metadata,
+    /// Date guessed from the filename, falling back to metadata
+    Filename,
+}
+
+/// Embeds metadata into every file, with the built-in writer where `writer`
+/// allows it and the persistent exiftool session otherwise. `date_source` says
+/// where the dates of WhatsApp & Screenshot images come from. Files embedded by
+/// an interrupted run are skipped.
+pub fn embed_metadata_all(metadata_list: &[MediaMetadata], log_dir: &Path, date_source: DateSource, writer: MetadataWriter, journal: &mut Journal) {
     let logs_dir = log_dir.join("logs");
     let log_path = logs_dir.join("metadata_embedding.log");
     let _ = fs::create_dir_all(&logs_dir);
-    let _log_file = File::create(&log_path).expect("Failed to create log file");
-    println!("\n🧐Do you want to embed date/time for WhatsApp & Screenshot images based on their  \n1. Metadata\n2. Filename\n");
-    let mut input = String::new();
-    io::stdin().read_line(&mut input).expect("Failed to read line");
-    let use_filename = matches!(input.trim(), "2");
+    // Keep the log of the interrupted run when resuming
+    if !journal.is_resumed() {
+        let _log_file = File::create(&log_path).expect("Failed to create log file");
+    }
+    let use_filename = date_source == DateSource::Filename;
     let total = metadata_list.len();
-    let mut processed = 0;
-    for meta in metadata_list {
-        let mut args = Vec::new();
-        let filename = meta.media_path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    let pending: Vec<&MediaMetadata> = metadata_list.iter()
+        .filter(|meta| !journal.is_done(Stage::Embedding, &meta.media_path))
+        .collect();
+    let mut processed = total - pending.len();
+    let exiftool_available = is_exiftool_available();
+    // Each worker thread writes through its own exiftool session
+    let embed = |meta: &&MediaMetadata| {
         let parent = meta.media_path.parent().and_then(|p| p.file_name()).and_then(|n| n.to_str()).unwrap_or("");
-        let is_wa = parent.eq_ignore_ascii_case("Whatsapp");
-        let is_sc = parent.eq_ignore_ascii_case("Screenshots");
-        let mut used = "metadata";
-        let mut date_to_embed = meta.exif_date.clone();
-        if use_filename && (is_wa || is_sc) {
-            if let Some(date) = extract_date_from_filename(filename) {
-                date_to_embed = Some(date);
-                used = "filename";
+        let is_wa_sc = parent.eq_ignore_ascii_case("Whatsapp") || parent.eq_ignore_ascii_case("Screenshots");
+        let (date_to_embed, used) = choose_date(meta, use_filename, is_wa_sc);
+        // Dates from file names are local time in an unknown zone
+        let offset = if used == "metadata" { meta.exif_offset.as_deref() } else { None };
+        let log_msg = format!(
+            "File: {:?}, Used: {}, Date: {:?}, Offset: {:?}, Lat: {:?}, Lon: {:?}, Alt: {:?}, Make: {:?}, Model: {:?}, Title: {:?}, Description: {:?}, People: {:?}, Favorite: {}",
+            meta.media_path.file_name().unwrap_or_default(), used, date_to_embed, offset, meta.gps_latitude, meta.gps_longitude, meta.gps_altitude, meta.camera_make, meta.camera_model,
+            meta.caption_title(), meta.description, meta.people, meta.favorited
+        );
+        (log_msg, write_one(meta, date_to_embed.as_deref(), offset, writer, exiftool_available))
+    };
+    for_each_ordered(&pending, embed, |meta, (log_msg, result)| {
+        match result {
+            Ok(used_writer) => {
+                log_to_file(&logs_dir, "metadata_embedding.log", &format!("✅ Embedded metadata ({}). {}", used_writer, log_msg));
+                journal.record(Stage::Embedding, &meta.media_path);
             }
-        }
-        if date_to_embed.is_none() {
-            used = "metadata (fallback)";
-        }
-        if let Some(ref date) = date_to_embed {
-            if meta.media_path.extension().map(|e| e.to_ascii_lowercase()) == Some("png".into()) {
-                args.push(format!("-XMP:DateTimeOriginal={}", date));
-            } else {
-                args.push(format!("-DateTimeOriginal={}", date));
+            Err(e) => {
+                log_to_file(&logs_dir, "metadata_embedding.log", &format!("❌ Failed to embed metadata ({}). {}", e, log_msg));
             }
         }
-        if let (Some(lat), Some(lon)) = (meta.gps_latitude, meta.gps_longitude) {
-            args.push(format!("-GPSLatitude={}", lat));
-            args.push(format!("-GPSLongitude={}", lon));
-        }
-        if let Some(alt) = meta.gps_altitude {
-            args.push(format!("-GPSAltitude={}", alt));
-        }
-        if let Some(ref make) = meta.camera_make {
-            args.push(format!("-Make={}", make));
+        processed += 1;
+        print_progress(processed, total);
+    });
+    println!("\n✅ Metadata embedding complete! Embedded metadata for {} files. Log: {:?}", processed, log_path);
+}
+
+/// Writes the tags of one file and returns which writer did it. The built-in
+/// writer is tried first for the formats it supports; exiftool covers the rest
+/// and any file the built-in writer could not handle.
+fn write_one(meta: &MediaMetadata, date: Option<&str>, offset: Option<&str>, writer: MetadataWriter, exiftool_available: bool) -> Result<&'static str, String> {
+    let mut native_error = None;
+    if writer == MetadataWriter::Native && native_format(&meta.media_path).is_some() {
+        match native_metadata::write_metadata(meta, date, offset) {
+            Ok(()) => return Ok("built-in"),
+            Err(e) => native_error = Some(e),
         }
-        if let Some(ref model) = meta.camera_model {
-            args.push(format!("-Model={}", model));
+    }
+    if !exiftool_available {
+        return Err(native_error.unwrap_or_else(|| "format needs exiftool, which is not installed".to_string()));
+    }
+    let mut args = exiftool_tag_args(meta, date, offset);
+    // Add more fields as needed
+    args.push("-overwrite_original".to_string());
+    match exiftool::execute_on(&args, &meta.media_path) {
+        Ok(output) if output.is_success() => Ok(if native_error.is_some() { "exiftool fallback" } else { "exiftool" }),
+        Ok(output) => Err(output.error().unwrap_or("unknown error").to_string()),
+        Err(e) => Err(format!("error running exiftool: {}", e)),
+    }
+}
+
+/// Decides which date gets embedded for a file and where it came from:
+/// "metadata", "filename" or "metadata (fallback)".
+pub fn choose_date(meta: &MediaMetadata, use_filename: bool, is_wa_sc: bool) -> (Option<String>, &'static str) {
+    let filename = meta.media_path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    let mut used = "metadata";
+    let mut date_to_embed = meta.exif_date.clone();
+    if use_filename && is_wa_sc {
+        if let Some(date) = extract_date_from_filename(filename) {
+            date_to_embed = Some(date);
+            used = "filename";
         }
-        // Add more fields as needed
-        args.push("-overwrite_original".to_string());
-        args.push(meta.media_path.to_string_lossy().to_string());
-        let log_msg = format!(
-            "File: {:?}, Used: {}, Date: {:?}, Lat: {:?}, Lon: {:?}, Alt: {:?}, Make: {:?}, Model: {:?}",
-            meta.media_path.file_name().unwrap_or_default(), used, date_to_embed, meta.gps_latitude, meta.gps_longitude, meta.gps_altitude, meta.camera_make, meta.camera_model
-        );
-        let status = get_exiftool_command()
-            .args(&args)
-            .status();
-        if let Ok(status) = status {
-            if status.success() {
-                log_to_file(&logs_dir, "metadata_embedding.log", &format!("✅ Embedded metadata. {}", log_msg));
-            } else {
-                log_to_file(&logs_dir, "metadata_embedding.log", &format!("❌ Failed to embed metadata. {}", log_msg));
-            }
+    }
+    if date_to_embed.is_none() {
+        used = "metadata (fallback)";
+    }
+    (date_to_embed, used)
+}
+
+/// exiftool tag assignments for one file, without `-overwrite_original` or the path.
+/// `date` is local time; its UTC `offset` goes into `OffsetTimeOriginal` and
+/// `OffsetTime`, or into the XMP date of a PNG. Values are C-escaped (`-ec`)
+/// so that line breaks in captions survive the one-argument-per-line exiftool session.
+pub fn exiftool_tag_args(meta: &MediaMetadata, date: Option<&str>, offset: Option<&str>) -> Vec<String> {
+    let ext = meta.media_path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    let escape = |value: &str| value.replace('\\', "\\\\").replace('\r', "").replace('\n', "\\n");
+    let mut args = vec!["-ec".to_string()];
+    if let Some(date) = date {
+        if ext == "png" {
+            args.push(format!("-XMP:DateTimeOriginal={}{}", date, offset.unwrap_or_default()));
         } else {
-            log_to_file(&logs_dir, "metadata_embedding.log", &format!("❌ Error running exiftool. {}", log_msg));
+            args.push(format!("-DateTimeOriginal={}", date));
+            if let Some(offset) = offset {
+                args.push(format!("-OffsetTimeOriginal={}", offset));
+                args.push(format!("-OffsetTime={}", offset));
+            }
         }
-        processed += 1;
-        print_progress(processed, total);
     }
-    println!("\n✅ Metadata embedding complete! Embedded metadata for {} files. Log: {:?}", processed, log_path);
+    if let (Some(lat), Some(lon)) = (meta.gps_latitude, meta.gps_longitude) {
+        args.push(format!("-GPSLatitude={}", lat));
+        args.push(format!("-GPSLongitude={}", lon));
+    }
+    if let Some(alt) = meta.gps_altitude {
+        args.push(format!("-GPSAltitude={}", alt));
+    }
+    if let Some(ref make) = meta.camera_make {
+        args.push(format!("-Make={}", escape(make)));
+    }
+    if let Some(ref model) = meta.camera_model {
+        args.push(format!("-Model={}", escape(model)));
+    }
+    if let Some(title) = meta.caption_title() {
+        args.push(format!("-XMP-dc:Title={}", escape(title)));
+    }
+    if let Some(ref description) = meta.description {
+        args.push(format!("-XMP-dc:Description={}", escape(description)));
+        // IPTC only exists in JPEG and TIFF based files
+        if IPTC_EXTENSIONS.contains(&ext.as_str()) {
+            args.push("-IPTC:CodedCharacterSet=UTF8".to_string());
+            args.push(format!("-IPTC:Caption-Abstract={}", escape(description)));
+        }
+    }
+    // Takeout has no face positions, so people are written by name only
+    for person in &meta.people {
+        args.push(format!("-XMP-iptcExt:PersonInImage={}", escape(person)));
+    }
+    if meta.favorited {
+        args.push("-XMP-xmp:Rating=5".to_string());
+    }
+    if let Some(ref name) = meta.preserved_file_name {
+        args.push(format!("-XMP-xmpMM:PreservedFileName={}", escape(name)));
+    }
+    args
 }
 
+/// Formats exiftool writes IPTC into.
+const IPTC_EXTENSIONS: [&str; 6] = ["jpg", "jpeg", "jfif", "tif", "tiff", "dng"];
+
 fn print_progress(done: usize, total: usize) {
-    let percent = if total > 0 { (done * 100) / total } else { 100 };
+    let percent = (done * 100).checked_div(total).unwrap_or(100);
     let bar = format!("{}{}", "🟦".repeat(percent / 4), "⬜".repeat(25 - percent / 4));
     print!("\r✍️  Embedding metadata: [{}] {}% ({} / {})", bar, percent, done, total);
     let _ = std::io::stdout().flush();
@@ -1,154 +1,351 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
 use std::fs;
 use std::path::{Path, PathBuf};
 use walkdir::WalkDir;
 use serde_json::Value;
-use chrono::{TimeZone, Utc};
-use crate::utils::log_to_file;
-use std::io;
+use crate::utils::{for_each_ordered, log_to_file};
 use std::io::Write;
+use crate::edited;
 use crate::filename_date_guess::extract_date_from_filename;
+use crate::journal::{Journal, Stage};
+use crate::media_info::read_time_offset;
+use crate::timezone::{self, OffsetSource, Zone};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct MediaMetadata {
     pub media_path: PathBuf,
-    pub _json_path: PathBuf,
+    pub json_path: PathBuf,
+    /// Local date taken, in EXIF format.
     pub exif_date: Option<String>,
+    /// UTC offset of `exif_date` (`+02:00`); unknown for dates guessed from file names.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exif_offset: Option<String>,
     pub gps_latitude: Option<f64>,
     pub gps_longitude: Option<f64>,
     pub gps_altitude: Option<f64>,
     pub camera_make: Option<String>,
     pub camera_model: Option<String>,
+    /// Title in Google Photos; the original file name unless it was changed.
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Names of the people tagged in Google Photos.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub people: Vec<String>,
+    #[serde(default)]
+    pub favorited: bool,
+    /// Archived, in the trash, or in the Locked Folder in Google Photos.
+    #[serde(default)]
+    pub archived: bool,
+    #[serde(default)]
+    pub trashed: bool,
+    #[serde(default)]
+    pub locked: bool,
+    /// Titles of the Google Photos albums the file is in.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub albums: Vec<String>,
+    /// Name before the sort stage renames the file, kept in XMP `PreservedFileName`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preserved_file_name: Option<String>,
 }
 
-pub fn extract_metadata(base_path: &str) -> (Vec<MediaMetadata>, Vec<PathBuf>) {
-    let mut media_json_pairs: Vec<(PathBuf, PathBuf)> = Vec::new();
-    let mut all_media_files: Vec<PathBuf> = Vec::new();
-    let media_extensions = vec![
-        // Images
-        "jpg", "jpeg", "png", "webp", "heic", "heif", "bmp", "tiff", "gif", "avif", "jxl", "jfif",
-        "raw", "cr2", "nef", "orf", "sr2", "arw", "dng", "pef", "raf", "rw2", "srw", "3fr", "erf",
-        "k25", "kdc", "mef", "mos", "mrw", "nrw", "srf", "x3f", "svg", "ico", "psd", "ai", "eps",
-        // Videos
-        "mp4", "mov", "mkv", "avi", "webm", "3gp", "m4v", "mpg", "mpeg", "mts", "m2ts", "ts", "flv",
-        "f4v", "wmv", "asf", "rm", "rmvb", "vob", "ogv", "mxf", "dv", "divx", "xvid"
-    ];
+impl MediaMetadata {
+    /// The Google Photos title, unless it is just a file name (which it is
+    /// for most photos).
+    pub fn caption_title(&self) -> Option<&str> {
+        let title = self.title.as_deref()?;
+        let is_file_name = Path::new(title).extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| ext.len() <= 4 && ext.chars().all(|c| c.is_ascii_alphanumeric()));
+        (!is_file_name).then_some(title)
+    }
+}
+
+/// What to do with media files that have no matching .json.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UnpairedPolicy {
+    /// Skip and move to 'Unknown Time'
+    UnknownTime,
+    /// Try to guess timestamp from filename
+    Guess,
+}
+
+/// Extraction result of one file as stored in the journal.
+#[derive(Serialize, Deserialize)]
+struct ExtractedRecord {
+    metadata: MediaMetadata,
+    #[serde(default)]
+    failed_guess: bool,
+}
 
+/// Pairs media with their JSON and extracts metadata. Media without a JSON are
+/// handled by `unpaired_policy`.
+///
+/// Dates are converted to the local time they were taken at; `timezone` is
+/// used for files whose time zone is neither in the file nor known from GPS.
+pub fn extract_metadata(base_path: &str, unpaired_policy: UnpairedPolicy, timezone: Option<&Zone>, journal: &mut Journal) -> (Vec<MediaMetadata>, Vec<PathBuf>) {
     let logs_dir = Path::new(base_path).join("logs");
+    let (media_json_pairs, unpaired_media) = pair_media_with_json(base_path, &[]);
+    extract_paired_metadata(media_json_pairs, unpaired_media, unpaired_policy, timezone, &logs_dir, journal)
+}
 
-    // Find all media files and their matching .json
-    for entry in WalkDir::new(base_path).into_iter().filter_map(Result::ok) {
-        let path = entry.path();
-        if path.is_file() {
-            if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
-                let ext_lc = ext.to_lowercase();
-                if media_extensions.contains(&ext_lc.as_str()) {
-                    all_media_files.push(path.to_path_buf());
-                    let json_path = path.with_extension(format!("{}.json", ext_lc));
-                    let json_path_alt = path.with_extension(format!("{}", "json"));
-                    // Try both: IMG_001.JPG.json and IMG_001.jpg.json
-                    if json_path.exists() {
-                        media_json_pairs.push((path.to_path_buf(), json_path));
-                    } else if json_path_alt.exists() {
-                        media_json_pairs.push((path.to_path_buf(), json_path_alt));
-                    }
+/// Like [`extract_metadata`], but for media already paired with their JSON
+/// (by [`pair_media_with_json`] or a [`crate::merge::VirtualTree`]) and logging
+/// into `logs_dir`. Nothing besides the log is written, which makes it usable
+/// for planning a run.
+///
+/// Files already extracted by an interrupted run are taken from `journal`.
+pub fn extract_paired_metadata(
+    media_json_pairs: Vec<(PathBuf, PathBuf)>,
+    mut unpaired_media: Vec<PathBuf>,
+    unpaired_policy: UnpairedPolicy,
+    timezone: Option<&Zone>,
+    logs_dir: &Path,
+    journal: &mut Journal,
+) -> (Vec<MediaMetadata>, Vec<PathBuf>) {
+    let mut metadata_list = Vec::new();
+    let mut failed_guess_paths = Vec::new();
+    // Reuse what an interrupted run already extracted
+    let resumed = |media_path: &Path, metadata_list: &mut Vec<MediaMetadata>, failed_guess_paths: &mut Vec<PathBuf>| {
+        let record = journal.data(Stage::Extraction, media_path)
+            .and_then(|data| serde_json::from_value::<ExtractedRecord>(data.clone()).ok());
+        match record {
+            Some(record) => {
+                if record.failed_guess {
+                    failed_guess_paths.push(record.metadata.media_path.clone());
                 }
+                metadata_list.push(record.metadata);
+                true
             }
+            None => false,
         }
-    }
-
-    let paired_media: Vec<PathBuf> = media_json_pairs.iter().map(|(m, _)| m.clone()).collect();
-    let unpaired_media: Vec<PathBuf> = all_media_files.into_iter().filter(|m| !paired_media.contains(m)).collect();
-    let mut metadata_list = Vec::new();
-    let mut failed_guess_paths = Vec::new();
+    };
+    let media_json_pairs: Vec<_> = media_json_pairs.into_iter()
+        .filter(|(media_path, _)| !resumed(media_path, &mut metadata_list, &mut failed_guess_paths))
+        .collect();
+    unpaired_media.retain(|media_path| !resumed(media_path, &mut metadata_list, &mut failed_guess_paths));
     let total = media_json_pairs.len();
     let mut processed = 0;
-    for (media_path, json_path) in &media_json_pairs {
-        let json_str = match fs::read_to_string(json_path) {
-            Ok(s) => s,
-            Err(e) => {
-                log_to_file(&logs_dir, "metadata_extraction.log", &format!("Failed to read JSON for {:?}: {}", json_path, e));
-                continue;
-            }
-        };
-        let v: Value = match serde_json::from_str(&json_str) {
-            Ok(val) => val,
-            Err(e) => {
-                log_to_file(&logs_dir, "metadata_extraction.log", &format!("Failed to parse JSON for {:?}: {}", json_path, e));
-                continue;
+    let mut offset_sources = Vec::new();
+    // JSON files are parsed in parallel; results are recorded in input order
+    for_each_ordered(&media_json_pairs, |(media_path, json_path)| read_sidecar(media_path, json_path, timezone), |_, result| {
+        match result {
+            Ok((meta, source)) => {
+                offset_sources.extend(source);
+                record_extracted(journal, &meta, false);
+                metadata_list.push(meta);
             }
-        };
-        // Extract timestamp and convert to EXIF format (original date only)
-        let exif_date = v["photoTakenTime"]["timestamp"].as_str().and_then(|ts| {
-            ts.parse::<i64>().ok().map(|timestamp| {
-                let dt = Utc.timestamp_opt(timestamp, 0).unwrap();
-                dt.format("%Y:%m:%d %H:%M:%S").to_string()
-            })
-        });
-        // Extract GPS
-        let gps_latitude = v["geoData"]["latitude"].as_f64()
-            .or_else(|| v["geoDataExif"]["latitude"].as_f64());
-        let gps_longitude = v["geoData"]["longitude"].as_f64()
-            .or_else(|| v["geoDataExif"]["longitude"].as_f64());
-        let gps_altitude = v["geoData"]["altitude"].as_f64()
-            .or_else(|| v["geoDataExif"]["altitude"].as_f64());
-        // Camera make/model
-        let camera_make = v["cameraMake"].as_str().map(|s| s.to_string());
-        let camera_model = v["cameraModel"].as_str().map(|s| s.to_string());
-
-        metadata_list.push(MediaMetadata {
-            media_path: media_path.clone(),
-            _json_path: json_path.clone(),
-            exif_date,
-            gps_latitude,
-            gps_longitude,
-            gps_altitude,
-            camera_make,
-            camera_model,
-        });
+            Err(e) => log_to_file(logs_dir, "metadata_extraction.log", &e),
+        }
         processed += 1;
         print_progress(processed, total);
-    }
+    });
+    log_offset_sources(&offset_sources, timezone, logs_dir);
     // Handle unpaired media
     if !unpaired_media.is_empty() {
+        let paired = media_json_pairs.len();
         println!(
-            "\n⚠️  No .json found for {} out of {} files ({}%).\nWhat should MetaSort do?\n1. Skip and move to 'Unknown Time'\n2. Try to guess timestamp from filename\nEnter 1 or 2:",
-            unpaired_media.len(), paired_media.len() + unpaired_media.len(), (unpaired_media.len() * 100) / (paired_media.len() + unpaired_media.len())
+            "\n⚠️  No .json found for {} out of {} files ({}%).",
+            unpaired_media.len(), paired + unpaired_media.len(), (unpaired_media.len() * 100) / (paired + unpaired_media.len())
         );
-        let mut input = String::new();
-        io::stdin().read_line(&mut input).unwrap();
-        let guess = input.trim() == "2";
+        let guess = unpaired_policy == UnpairedPolicy::Guess;
         for media_path in unpaired_media {
             let filename = media_path.file_name().and_then(|n| n.to_str()).unwrap_or("");
             let exif_date = if guess {
                 if let Some(date) = extract_date_from_filename(filename) {
-                    log_to_file(&logs_dir, "metadata_extraction.log", &format!("Guessed date from filename for {:?}: {}", filename, date));
+                    log_to_file(logs_dir, "metadata_extraction.log", &format!("Guessed date from filename for {:?}: {}", filename, date));
                     Some(date)
                 } else {
-                    log_to_file(&logs_dir, "metadata_extraction.log", &format!("Could not guess date from filename for {:?}", filename));
+                    log_to_file(logs_dir, "metadata_extraction.log", &format!("Could not guess date from filename for {:?}", filename));
                     failed_guess_paths.push(media_path.clone());
                     None
                 }
             } else {
-                log_to_file(&logs_dir, "metadata_extraction.log", &format!("No JSON for {:?}, moved to Unknown Time", filename));
+                log_to_file(logs_dir, "metadata_extraction.log", &format!("No JSON for {:?}, moved to Unknown Time", filename));
                 None
             };
-            metadata_list.push(MediaMetadata {
+            let meta = MediaMetadata {
                 media_path: media_path.clone(),
-                _json_path: PathBuf::new(),
+                json_path: PathBuf::new(),
                 exif_date,
+                exif_offset: None,
                 gps_latitude: None,
                 gps_longitude: None,
                 gps_altitude: None,
                 camera_make: None,
                 camera_model: None,
-            });
+                title: None,
+                description: None,
+                people: Vec::new(),
+                favorited: false,
+                archived: false,
+                trashed: false,
+                locked: false,
+                albums: Vec::new(),
+                preserved_file_name: None,
+            };
+            record_extracted(journal, &meta, failed_guess_paths.last() == Some(&media_path));
+            metadata_list.push(meta);
         }
     }
     (metadata_list, failed_guess_paths)
 }
 
+fn record_extracted(journal: &mut Journal, meta: &MediaMetadata, failed_guess: bool) {
+    let record = ExtractedRecord { metadata: meta.clone(), failed_guess };
+    if let Ok(data) = serde_json::to_value(&record) {
+        journal.record_with(Stage::Extraction, &meta.media_path, data);
+    }
+}
+
+/// Finds every media file below `base_path` and its JSON sidecar, returning the
+/// `(media, json)` pairs and the media without a JSON. The JSON path is where
+/// the file currently lives, even when a planned rename would move it.
+pub fn pair_media_with_json(base_path: &str, renames: &[(PathBuf, PathBuf)]) -> (Vec<(PathBuf, PathBuf)>, Vec<PathBuf>) {
+    let files: Vec<PathBuf> = WalkDir::new(base_path).sort_by_file_name()
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|e| e.path().is_file())
+        .map(|e| e.into_path())
+        .collect();
+    pair_files(&files, renames)
+}
+
+/// Like [`pair_media_with_json`], but over a list of file paths instead of a
+/// folder on disk, so it also works on a merged multi-part tree.
+pub fn pair_files(files: &[PathBuf], renames: &[(PathBuf, PathBuf)]) -> (Vec<(PathBuf, PathBuf)>, Vec<PathBuf>) {
+    let mut media_json_pairs: Vec<(PathBuf, PathBuf)> = Vec::new();
+    let mut unpaired_media: Vec<PathBuf> = Vec::new();
+    let media_extensions = vec![
+        // Images
+        "jpg", "jpeg", "png", "webp", "heic", "heif", "bmp", "tiff", "gif", "avif", "jxl", "jfif",
+        "raw", "cr2", "nef", "orf", "sr2", "arw", "dng", "pef", "raf", "rw2", "srw", "3fr", "erf",
+        "k25", "kdc", "mef", "mos", "mrw", "nrw", "srf", "x3f", "svg", "ico", "psd", "ai", "eps",
+        // Videos
+        "mp4", "mov", "mkv", "avi", "webm", "3gp", "m4v", "mpg", "mpeg", "mts", "m2ts", "ts", "flv",
+        "f4v", "wmv", "asf", "rm", "rmvb", "vob", "ogv", "mxf", "dv", "divx", "xvid"
+    ];
+    let existing: HashSet<&Path> = files.iter().map(PathBuf::as_path).collect();
+    // A JSON exists if it is in the list or is the target of a planned rename
+    let locate = |json_path: PathBuf| -> Option<PathBuf> {
+        if let Some((from, _)) = renames.iter().rev().find(|(_, to)| *to == json_path) {
+            Some(from.clone())
+        } else if existing.contains(json_path.as_path()) {
+            Some(json_path)
+        } else {
+            None
+        }
+    };
+
+    let is_media = |path: &Path| {
+        path.extension().and_then(|e| e.to_str()).is_some_and(|ext| media_extensions.contains(&ext.to_lowercase().as_str()))
+    };
+    // Try IMG_001.jpg.json, IMG_001.JPG.json and IMG_001.json
+    let json_of = |path: &Path| {
+        let ext_lc = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_lowercase();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        locate(path.with_extension(format!("{}.json", ext_lc)))
+            .or_else(|| locate(path.with_file_name(format!("{}.json", name))))
+            .or_else(|| locate(path.with_extension("json")))
+    };
+    // Edited copies share the JSON of their original, which may have another extension
+    let mut by_stem: HashMap<(&Path, &OsStr), Vec<&Path>> = HashMap::new();
+    for path in files.iter().filter(|path| is_media(path)) {
+        if let (Some(parent), Some(stem)) = (path.parent(), path.file_stem()) {
+            by_stem.entry((parent, stem)).or_default().push(path);
+        }
+    }
+    let original_json = |path: &Path| {
+        let stem = edited::original_stem(path)?;
+        // Built by hand, as with_extension would cut a stem like `IMG.2021` short
+        let original = path.with_file_name(format!("{}.{}", stem, path.extension()?.to_string_lossy()));
+        by_stem.get(&(path.parent()?, OsStr::new(&stem))).into_iter().flatten()
+            .find_map(|original| json_of(original))
+            .or_else(|| json_of(&original))
+    };
+
+    // Find all media files and their matching .json
+    for path in files.iter().filter(|path| is_media(path)) {
+        match json_of(path).or_else(|| original_json(path)) {
+            Some(json_path) => media_json_pairs.push((path.clone(), json_path)),
+            None => unpaired_media.push(path.clone()),
+        }
+    }
+    (media_json_pairs, unpaired_media)
+}
+
+/// Reads the Google Photos metadata of one media file from its JSON sidecar,
+/// with where the time zone of its date came from.
+pub fn read_sidecar(media_path: &Path, json_path: &Path, timezone: Option<&Zone>) -> Result<(MediaMetadata, Option<OffsetSource>), String> {
+    let json_str = fs::read_to_string(json_path)
+        .map_err(|e| format!("Failed to read JSON for {:?}: {}", json_path, e))?;
+    let v: Value = serde_json::from_str(&json_str)
+        .map_err(|e| format!("Failed to parse JSON for {:?}: {}", json_path, e))?;
+    // Extract GPS
+    let gps_latitude = v["geoData"]["latitude"].as_f64()
+        .or_else(|| v["geoDataExif"]["latitude"].as_f64());
+    let gps_longitude = v["geoData"]["longitude"].as_f64()
+        .or_else(|| v["geoDataExif"]["longitude"].as_f64());
+    let gps_altitude = v["geoData"]["altitude"].as_f64()
+        .or_else(|| v["geoDataExif"]["altitude"].as_f64());
+    // The timestamp is UTC, but EXIF dates are local time
+    let local = v["photoTakenTime"]["timestamp"].as_str()
+        .and_then(|ts| ts.parse::<i64>().ok())
+        .and_then(|timestamp| timezone::local_time(timestamp, read_time_offset(media_path), gps_latitude.zip(gps_longitude), timezone));
+    // Camera make/model
+    let camera_make = v["cameraMake"].as_str().map(|s| s.to_string());
+    let camera_model = v["cameraModel"].as_str().map(|s| s.to_string());
+    // Caption, title, tagged people and the favorite/archive/trash/locked flags
+    let text = |key: &str| v[key].as_str().map(str::trim).filter(|s| !s.is_empty()).map(str::to_string);
+    let people = v["people"].as_array().into_iter().flatten()
+        .filter_map(|person| person["name"].as_str().map(str::trim).filter(|name| !name.is_empty()).map(str::to_string))
+        .collect();
+
+    let source = local.as_ref().map(|local| local.source);
+    let meta = MediaMetadata {
+        media_path: media_path.to_path_buf(),
+        json_path: json_path.to_path_buf(),
+        exif_date: local.as_ref().map(|local| local.date.clone()),
+        exif_offset: local.map(|local| local.offset),
+        gps_latitude,
+        gps_longitude,
+        gps_altitude,
+        camera_make,
+        camera_model,
+        title: text("title"),
+        description: text("description"),
+        people,
+        favorited: v["favorited"].as_bool().unwrap_or(false),
+        archived: v["archived"].as_bool().unwrap_or(false),
+        trashed: v["trashed"].as_bool().unwrap_or(false),
+        locked: v["inLockedFolder"].as_bool().unwrap_or(false),
+        albums: Vec::new(),
+        preserved_file_name: None,
+    };
+    Ok((meta, source))
+}
+
+/// Logs where the time zones of the extracted dates came from, and warns when
+/// some had to stay in UTC.
+fn log_offset_sources(sources: &[OffsetSource], timezone: Option<&Zone>, logs_dir: &Path) {
+    let count = |source: OffsetSource| sources.iter().filter(|s| **s == source).count();
+    let in_utc = count(OffsetSource::Utc);
+    log_to_file(logs_dir, "metadata_extraction.log", &format!(
+        "Time zones of {} dates: {} from the files, {} from GPS, {} from --timezone{}, {} left in UTC",
+        sources.len(), count(OffsetSource::File), count(OffsetSource::Gps), count(OffsetSource::Default),
+        timezone.map(|zone| format!(" ({})", zone)).unwrap_or_default(), in_utc,
+    ));
+    if in_utc > 0 {
+        println!("\n⚠️  No time zone is known for {} files, so their dates stay in UTC. Set one with --timezone.", in_utc);
+    }
+}
+
 fn print_progress(done: usize, total: usize) {
-    let percent = if total > 0 { (done * 100) / total } else { 100 };
+    let percent = (done * 100).checked_div(total).unwrap_or(100);
     let bar = format!("{}{}", "🟩".repeat(percent / 4), "⬜".repeat(25 - percent / 4));
     print!("\r🔍 Extracting metadata: [{}] {}% ({} / {})", bar, percent, done, total);
     let _ = std::io::stdout().flush();
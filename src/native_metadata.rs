@@ -0,0 +1,486 @@
+// native_metadata.rs
+// Built-in metadata writer for MetaSort_v1.0.0 – Google Photos Takeout Organizer
+
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+use exif::experimental::Writer;
+use exif::{Field, In, Rational, Reader, Tag, Value};
+use img_parts::jpeg::{markers, Jpeg, JpegSegment};
+use img_parts::png::{Png, PngChunk};
+use img_parts::{Bytes, ImageEXIF};
+use crate::metadata_extraction::MediaMetadata;
+use crate::quicktime::{self, MovieTags};
+use crate::timezone::parse_offset;
+
+/// Which metadata writer `metadata_embed` uses.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MetadataWriter {
+    /// Built-in writer for JPEG, PNG, MP4 and MOV; exiftool for everything else
+    #[default]
+    Native,
+    /// exiftool for every file
+    Exiftool,
+}
+
+/// Formats the built-in writer handles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NativeFormat {
+    Jpeg,
+    Png,
+    QuickTime,
+}
+
+/// Format of a file by its extension, if the built-in writer supports it.
+pub fn native_format(path: &Path) -> Option<NativeFormat> {
+    let ext = path.extension()?.to_string_lossy().to_lowercase();
+    match ext.as_str() {
+        "jpg" | "jpeg" | "jfif" => Some(NativeFormat::Jpeg),
+        "png" => Some(NativeFormat::Png),
+        "mp4" | "mov" | "m4v" | "3gp" => Some(NativeFormat::QuickTime),
+        _ => None,
+    }
+}
+
+/// Writes the same tags as [`crate::metadata_embed::exiftool_tag_args`] without
+/// exiftool: EXIF, XMP and an IPTC caption in a JPEG, EXIF (`eXIf`) and XMP in
+/// a PNG, and QuickTime dates, GPS, make/model and captions in an MP4/MOV. As
+/// MP4/MOV files get no XMP, the original file name of a renamed video is only
+/// kept in the CSV reports. The file is replaced by a new one, never edited in
+/// place.
+///
+/// `date` is local time and `offset` its UTC offset, if known.
+///
+/// Returns an error for unsupported formats and for files whose existing
+/// metadata cannot be parsed, so the caller can fall back to exiftool.
+pub fn write_metadata(meta: &MediaMetadata, date: Option<&str>, offset: Option<&str>) -> Result<(), String> {
+    let path = &meta.media_path;
+    match native_format(path).ok_or("format not supported by the built-in writer")? {
+        NativeFormat::Jpeg => {
+            let bytes = fs::read(path).map_err(|e| e.to_string())?;
+            let mut jpeg = Jpeg::from_bytes(bytes.into()).map_err(|e| e.to_string())?;
+            let exif = merge_exif(jpeg.exif().as_deref(), meta, date, offset)?;
+            // EXIF goes right after SOI, or after a JFIF APP0, followed by XMP and IPTC
+            let segments = jpeg.segments_mut();
+            segments.retain(|s| !(s.marker() == markers::APP1 && s.contents().starts_with(EXIF_PREFIX)));
+            let mut at = usize::from(segments.first().map(|s| s.marker()) == Some(markers::APP0));
+            segments.insert(at, app_segment(markers::APP1, [EXIF_PREFIX, &exif[..]].concat())?);
+            at += 1;
+            let properties = xmp_properties(meta, None, None);
+            if !properties.is_empty() {
+                let existing = segments.iter().position(|s| s.marker() == markers::APP1 && s.contents().starts_with(XMP_HEADER));
+                let old = existing.map(|i| String::from_utf8_lossy(&segments[i].contents()[XMP_HEADER.len()..]).to_string());
+                let packet = xmp_packet(old.as_deref(), &properties)?;
+                let segment = app_segment(markers::APP1, [XMP_HEADER, packet.as_bytes()].concat())?;
+                match existing {
+                    Some(i) => segments[i] = segment,
+                    None => {
+                        segments.insert(at, segment);
+                        at += 1;
+                    }
+                }
+            }
+            if let Some(description) = &meta.description {
+                if segments.iter().any(|s| s.marker() == markers::APP13 && s.contents().starts_with(PHOTOSHOP_HEADER)) {
+                    return Err("file already has IPTC data".to_string());
+                }
+                segments.insert(at, app_segment(markers::APP13, iptc_caption(description))?);
+            }
+            replace_file(path, jpeg.encoder().bytes())
+        }
+        NativeFormat::Png => {
+            let bytes = fs::read(path).map_err(|e| e.to_string())?;
+            let mut png = Png::from_bytes(bytes.into()).map_err(|e| e.to_string())?;
+            let exif = merge_exif(png.exif().as_deref(), meta, date, offset)?;
+            let chunks = png.chunks_mut();
+            chunks.retain(|c| c.kind() != CHUNK_EXIF);
+            // Readers stop looking for metadata at the image data
+            let idat = chunks.iter().position(|c| c.kind() == CHUNK_IDAT).unwrap_or(chunks.len().saturating_sub(1));
+            chunks.insert(idat, PngChunk::new(CHUNK_EXIF, exif.into()));
+            // Few PNG readers look at EXIF, so the date goes into XMP as well
+            let properties = xmp_properties(meta, date, offset);
+            if !properties.is_empty() {
+                let existing = chunks.iter().position(|c| c.kind() == CHUNK_ITXT && c.contents().starts_with(XMP_KEYWORD));
+                let old = match existing {
+                    Some(i) => Some(itxt_text(&chunks[i].contents()[XMP_KEYWORD.len()..]).ok_or("compressed XMP")?),
+                    None => None,
+                };
+                let packet = xmp_packet(old.as_deref(), &properties)?;
+                // keyword, compression flag and method, empty language and translated keyword
+                let chunk = PngChunk::new(CHUNK_ITXT, [XMP_KEYWORD, &[0, 0, 0, 0], packet.as_bytes()].concat().into());
+                match existing {
+                    Some(i) => chunks[i] = chunk,
+                    None => chunks.insert(idat, chunk),
+                }
+            }
+            replace_file(path, png.encoder().bytes())
+        }
+        NativeFormat::QuickTime => {
+            let tags = MovieTags {
+                date: date.map(str::to_string),
+                offset: offset.and_then(parse_offset),
+                gps: meta.gps_latitude.zip(meta.gps_longitude).map(|(lat, lon)| (lat, lon, meta.gps_altitude)),
+                make: meta.camera_make.clone(),
+                model: meta.camera_model.clone(),
+                title: meta.caption_title().map(str::to_string),
+                description: meta.description.clone(),
+            };
+            quicktime::write_movie_tags(path, &tags)
+        }
+    }
+}
+
+const EXIF_PREFIX: &[u8] = b"Exif\0\0";
+const CHUNK_EXIF: [u8; 4] = *b"eXIf";
+const CHUNK_IDAT: [u8; 4] = *b"IDAT";
+const CHUNK_ITXT: [u8; 4] = *b"iTXt";
+const XMP_KEYWORD: &[u8] = b"XML:com.adobe.xmp\0";
+const XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const PHOTOSHOP_HEADER: &[u8] = b"Photoshop 3.0\0";
+const XMP_NAMESPACES: &str = "xmlns:dc='http://purl.org/dc/elements/1.1/' xmlns:exif='http://ns.adobe.com/exif/1.0/' \
+xmlns:xmp='http://ns.adobe.com/xap/1.0/' xmlns:xmpMM='http://ns.adobe.com/xap/1.0/mm/' xmlns:Iptc4xmpExt='http://iptc.org/std/Iptc4xmpExt/2008-02-29/'";
+
+/// Existing EXIF (TIFF data) with MetaSort's tags added or replaced. Maker
+/// notes and other fields are kept as they are; the thumbnail is carried over.
+fn merge_exif(existing: Option<&[u8]>, meta: &MediaMetadata, date: Option<&str>, offset: Option<&str>) -> Result<Vec<u8>, String> {
+    let new_fields = exif_fields(meta, date, offset);
+    let parsed = match existing {
+        Some(raw) => Some(Reader::new().read_raw(raw.to_vec()).map_err(|e| format!("unreadable EXIF: {}", e))?),
+        None => None,
+    };
+    let replaced = |f: &Field| new_fields.iter().any(|n| n.tag == f.tag && n.ifd_num == f.ifd_num);
+    let kept: Vec<&Field> = parsed.iter()
+        .flat_map(|exif| exif.fields())
+        .filter(|f| !matches!(f.value, Value::Unknown(..)) && !replaced(f))
+        .collect();
+    let thumbnail = parsed.as_ref().and_then(|exif| {
+        let offset = exif.get_field(Tag::JPEGInterchangeFormat, In::THUMBNAIL)?.value.get_uint(0)? as usize;
+        let len = exif.get_field(Tag::JPEGInterchangeFormatLength, In::THUMBNAIL)?.value.get_uint(0)? as usize;
+        exif.buf().get(offset..offset.checked_add(len)?)
+    });
+
+    let mut writer = Writer::new();
+    for field in kept.into_iter().chain(new_fields.iter()) {
+        writer.push_field(field);
+    }
+    if let Some(thumbnail) = thumbnail {
+        writer.set_jpeg(thumbnail, In::THUMBNAIL);
+    }
+    let mut out = Cursor::new(Vec::new());
+    let little_endian = parsed.as_ref().is_some_and(|exif| exif.little_endian());
+    writer.write(&mut out, little_endian).map_err(|e| format!("could not write EXIF: {}", e))?;
+    Ok(out.into_inner())
+}
+
+/// EXIF fields for the date and its offset, GPS position and camera of a file.
+fn exif_fields(meta: &MediaMetadata, date: Option<&str>, offset: Option<&str>) -> Vec<Field> {
+    let ascii = |s: &str| Value::Ascii(vec![s.as_bytes().to_vec()]);
+    let field = |tag: Tag, value: Value| Field { tag, ifd_num: In::PRIMARY, value };
+    let mut fields = Vec::new();
+    if let Some(date) = date {
+        fields.push(field(Tag::DateTimeOriginal, ascii(date)));
+        if let Some(offset) = offset {
+            fields.push(field(Tag::OffsetTimeOriginal, ascii(offset)));
+            fields.push(field(Tag::OffsetTime, ascii(offset)));
+        }
+    }
+    if let (Some(lat), Some(lon)) = (meta.gps_latitude, meta.gps_longitude) {
+        fields.push(field(Tag::GPSVersionID, Value::Byte(vec![2, 3, 0, 0])));
+        fields.push(field(Tag::GPSLatitudeRef, ascii(if lat < 0.0 { "S" } else { "N" })));
+        fields.push(field(Tag::GPSLatitude, Value::Rational(degrees_to_dms(lat))));
+        fields.push(field(Tag::GPSLongitudeRef, ascii(if lon < 0.0 { "W" } else { "E" })));
+        fields.push(field(Tag::GPSLongitude, Value::Rational(degrees_to_dms(lon))));
+    }
+    if let Some(alt) = meta.gps_altitude {
+        fields.push(field(Tag::GPSAltitudeRef, Value::Byte(vec![u8::from(alt < 0.0)])));
+        fields.push(field(Tag::GPSAltitude, Value::Rational(vec![Rational { num: (alt.abs() * 1000.0).round() as u32, denom: 1000 }])));
+    }
+    if let Some(make) = &meta.camera_make {
+        fields.push(field(Tag::Make, ascii(make)));
+    }
+    if let Some(model) = &meta.camera_model {
+        fields.push(field(Tag::Model, ascii(model)));
+    }
+    if let Some(description) = &meta.description {
+        fields.push(field(Tag::ImageDescription, ascii(description)));
+    }
+    fields
+}
+
+/// Degrees, minutes and seconds (to 1/10000 s) of an absolute coordinate.
+fn degrees_to_dms(value: f64) -> Vec<Rational> {
+    let value = value.abs();
+    let degrees = value.trunc();
+    let minutes = ((value - degrees) * 60.0).trunc();
+    let seconds = (value - degrees - minutes / 60.0) * 3600.0;
+    vec![
+        Rational { num: degrees as u32, denom: 1 },
+        Rational { num: minutes as u32, denom: 1 },
+        Rational { num: (seconds * 10000.0).round() as u32, denom: 10000 },
+    ]
+}
+
+/// A JPEG APPn segment, if `contents` fit into one.
+fn app_segment(marker: u8, contents: Vec<u8>) -> Result<JpegSegment, String> {
+    if contents.len() + 2 > u16::MAX as usize {
+        return Err("metadata too large for a JPEG segment".to_string());
+    }
+    Ok(JpegSegment::new_with_contents(marker, contents.into()))
+}
+
+/// XMP properties for a file as `(qualified name, RDF/XML element)`. `date`
+/// is only given for formats whose EXIF few readers look at.
+fn xmp_properties(meta: &MediaMetadata, date: Option<&str>, offset: Option<&str>) -> Vec<(&'static str, String)> {
+    let alt = |name: &str, value: &str| {
+        format!("<{0}><rdf:Alt><rdf:li xml:lang='x-default'>{1}</rdf:li></rdf:Alt></{0}>", name, xml_escape(value))
+    };
+    let mut properties = Vec::new();
+    if let Some(date) = date {
+        properties.push(("exif:DateTimeOriginal", format!("<exif:DateTimeOriginal>{}</exif:DateTimeOriginal>", xmp_date(date, offset))));
+    }
+    if let Some(title) = meta.caption_title() {
+        properties.push(("dc:title", alt("dc:title", title)));
+    }
+    if let Some(description) = &meta.description {
+        properties.push(("dc:description", alt("dc:description", description)));
+    }
+    if !meta.people.is_empty() {
+        let names: String = meta.people.iter().map(|name| format!("<rdf:li>{}</rdf:li>", xml_escape(name))).collect();
+        properties.push(("Iptc4xmpExt:PersonInImage", format!("<Iptc4xmpExt:PersonInImage><rdf:Bag>{}</rdf:Bag></Iptc4xmpExt:PersonInImage>", names)));
+    }
+    if meta.favorited {
+        properties.push(("xmp:Rating", "<xmp:Rating>5</xmp:Rating>".to_string()));
+    }
+    if let Some(name) = &meta.preserved_file_name {
+        properties.push(("xmpMM:PreservedFileName", format!("<xmpMM:PreservedFileName>{}</xmpMM:PreservedFileName>", xml_escape(name))));
+    }
+    properties
+}
+
+/// An XMP packet with `properties`. An existing packet gets them as one more
+/// `rdf:Description`; if it already has one of them, merging is left to
+/// exiftool and an error is returned.
+fn xmp_packet(existing: Option<&str>, properties: &[(&str, String)]) -> Result<String, String> {
+    let description = format!(
+        "<rdf:Description rdf:about='' {}>{}</rdf:Description>",
+        XMP_NAMESPACES,
+        properties.iter().map(|(_, xml)| xml.as_str()).collect::<String>()
+    );
+    let Some(xmp) = existing else {
+        return Ok(format!(
+            "<?xpacket begin='\u{feff}' id='W5M0MpCehiHzreSzNTczkc9d'?>\
+<x:xmpmeta xmlns:x='adobe:ns:meta/'><rdf:RDF xmlns:rdf='http://www.w3.org/1999/02/22-rdf-syntax-ns#'>\
+{}</rdf:RDF></x:xmpmeta><?xpacket end='w'?>",
+            description
+        ));
+    };
+    if let Some((name, _)) = properties.iter().find(|(name, _)| xmp.contains(name)) {
+        return Err(format!("file already has XMP {}", name));
+    }
+    let end = xmp.rfind("</rdf:RDF>").ok_or("unreadable XMP")?;
+    Ok(format!("{}{}{}", &xmp[..end], description, &xmp[end..]))
+}
+
+/// Text of an uncompressed iTXt chunk, after its keyword.
+fn itxt_text(data: &[u8]) -> Option<String> {
+    let (&compressed, rest) = data.split_first()?;
+    if compressed != 0 {
+        return None;
+    }
+    // compression method, then the language tag and translated keyword
+    let mut parts = rest.get(1..)?.splitn(3, |b| *b == 0);
+    let (_, _, text) = (parts.next()?, parts.next()?, parts.next()?);
+    Some(String::from_utf8_lossy(text).to_string())
+}
+
+/// A Photoshop APP13 segment with an IPTC caption (2:120) in UTF-8.
+fn iptc_caption(caption: &str) -> Vec<u8> {
+    // Caption-Abstract is limited to 2000 bytes
+    let mut end = caption.len().min(2000);
+    while !caption.is_char_boundary(end) {
+        end -= 1;
+    }
+    let caption = &caption.as_bytes()[..end];
+    let mut iptc = vec![0x1C, 1, 90, 0, 3, 0x1B, b'%', b'G', 0x1C, 2, 0, 0, 2, 0, 4, 0x1C, 2, 120];
+    iptc.extend_from_slice(&(caption.len() as u16).to_be_bytes());
+    iptc.extend_from_slice(caption);
+    // 8BIM resource 0x0404 (IPTC), empty name, even length
+    let mut segment = [PHOTOSHOP_HEADER, b"8BIM\x04\x04\0\0"].concat();
+    segment.extend_from_slice(&(iptc.len() as u32).to_be_bytes());
+    segment.extend_from_slice(&iptc);
+    if iptc.len() % 2 == 1 {
+        segment.push(0);
+    }
+    segment
+}
+
+fn xml_escape(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('\'', "&apos;").replace('"', "&quot;")
+}
+
+/// `YYYY:MM:DD HH:MM:SS` as an XMP date (`YYYY-MM-DDTHH:MM:SS`), followed by
+/// its UTC offset when known.
+fn xmp_date(date: &str, offset: Option<&str>) -> String {
+    let date = match date.split_once(' ') {
+        Some((day, time)) => format!("{}T{}", day.replace(':', "-"), time),
+        None => date.replace(':', "-"),
+    };
+    format!("{}{}", date, offset.unwrap_or_default())
+}
+
+/// Writes `bytes` to a new file next to `path` and renames it over `path`, so a
+/// hardlinked original is never changed.
+pub(crate) fn replace_file(path: &Path, bytes: Bytes) -> Result<(), String> {
+    let tmp_path = path.with_file_name(format!(
+        ".{}.metasort-tmp",
+        path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default()
+    ));
+    fs::write(&tmp_path, &bytes)
+        .and_then(|_| fs::rename(&tmp_path, path))
+        .map_err(|e| {
+            let _ = fs::remove_file(&tmp_path);
+            e.to_string()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use image::{ImageFormat, RgbImage};
+
+    fn image_file(dir: &Path, name: &str, format: ImageFormat) -> PathBuf {
+        let path = dir.join(name);
+        RgbImage::new(8, 8).save_with_format(&path, format).unwrap();
+        path
+    }
+
+    fn metadata(path: PathBuf) -> MediaMetadata {
+        MediaMetadata {
+            media_path: path,
+            gps_latitude: Some(-33.8568),
+            gps_longitude: Some(151.2153),
+            gps_altitude: Some(-2.5),
+            camera_make: Some("Google".to_string()),
+            camera_model: Some("Pixel 5".to_string()),
+            title: Some("Opera House".to_string()),
+            description: Some("Sydney <harbour> & friends".to_string()),
+            people: vec!["Alex".to_string()],
+            favorited: true,
+            preserved_file_name: Some("IMG_0001.jpg".to_string()),
+            ..MediaMetadata::default()
+        }
+    }
+
+    fn exif_text(exif: &exif::Exif, tag: Tag) -> Option<String> {
+        match &exif.get_field(tag, In::PRIMARY)?.value {
+            Value::Ascii(values) => Some(String::from_utf8_lossy(&values[0]).to_string()),
+            _ => None,
+        }
+    }
+
+    fn exif_degrees(exif: &exif::Exif, tag: Tag) -> f64 {
+        match &exif.get_field(tag, In::PRIMARY).unwrap().value {
+            Value::Rational(dms) => dms[0].to_f64() + dms[1].to_f64() / 60.0 + dms[2].to_f64() / 3600.0,
+            _ => panic!("{} is not rational", tag),
+        }
+    }
+
+    #[test]
+    fn jpeg_gets_exif_xmp_and_iptc() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = image_file(dir.path(), "photo.jpg", ImageFormat::Jpeg);
+        let meta = metadata(path.clone());
+        write_metadata(&meta, Some("2021:05:01 10:30:00"), Some("+10:00")).unwrap();
+
+        let exif = Reader::new().read_from_container(&mut std::io::BufReader::new(fs::File::open(&path).unwrap())).unwrap();
+        assert_eq!(exif_text(&exif, Tag::DateTimeOriginal).as_deref(), Some("2021:05:01 10:30:00"));
+        assert_eq!(exif_text(&exif, Tag::OffsetTimeOriginal).as_deref(), Some("+10:00"));
+        assert_eq!(exif_text(&exif, Tag::GPSLatitudeRef).as_deref(), Some("S"));
+        assert_eq!(exif_text(&exif, Tag::GPSLongitudeRef).as_deref(), Some("E"));
+        assert!((exif_degrees(&exif, Tag::GPSLatitude) - 33.8568).abs() < 1e-6);
+        assert!((exif_degrees(&exif, Tag::GPSLongitude) - 151.2153).abs() < 1e-6);
+        assert_eq!(exif.get_field(Tag::GPSAltitudeRef, In::PRIMARY).unwrap().value.get_uint(0), Some(1));
+        assert_eq!(exif_text(&exif, Tag::Model).as_deref(), Some("Pixel 5"));
+
+        let jpeg = Jpeg::from_bytes(fs::read(&path).unwrap().into()).unwrap();
+        let xmp = jpeg.segments().iter()
+            .find(|s| s.marker() == markers::APP1 && s.contents().starts_with(XMP_HEADER))
+            .map(|s| String::from_utf8_lossy(&s.contents()[XMP_HEADER.len()..]).to_string())
+            .unwrap();
+        assert!(xmp.contains("<rdf:li xml:lang='x-default'>Sydney &lt;harbour&gt; &amp; friends</rdf:li>"));
+        assert!(xmp.contains("<rdf:li>Alex</rdf:li>"));
+        assert!(xmp.contains("<xmp:Rating>5</xmp:Rating>"));
+        assert!(xmp.contains("<xmpMM:PreservedFileName>IMG_0001.jpg</xmpMM:PreservedFileName>"));
+        // EXIF has the date, so the JPEG's XMP does not repeat it
+        assert!(!xmp.contains("DateTimeOriginal"));
+
+        let iptc = jpeg.segments().iter().find(|s| s.marker() == markers::APP13).unwrap().contents().to_vec();
+        let caption = b"Sydney <harbour> & friends";
+        let at = iptc.windows(3).position(|w| w == [0x1C, 2, 120]).unwrap() + 3;
+        assert_eq!(u16::from_be_bytes([iptc[at], iptc[at + 1]]) as usize, caption.len());
+        assert_eq!(&iptc[at + 2..at + 2 + caption.len()], caption);
+        assert_eq!(iptc.len() % 2, 0);
+    }
+
+    #[test]
+    fn rewriting_jpeg_exif_replaces_the_date() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = image_file(dir.path(), "photo.jpg", ImageFormat::Jpeg);
+        let meta = MediaMetadata { media_path: path.clone(), ..MediaMetadata::default() };
+        write_metadata(&meta, Some("2021:05:01 10:30:00"), None).unwrap();
+        write_metadata(&meta, Some("2022:01:02 03:04:05"), None).unwrap();
+        let exif = Reader::new().read_from_container(&mut std::io::BufReader::new(fs::File::open(&path).unwrap())).unwrap();
+        assert_eq!(exif.fields().filter(|f| f.tag == Tag::DateTimeOriginal).count(), 1);
+        assert_eq!(exif_text(&exif, Tag::DateTimeOriginal).as_deref(), Some("2022:01:02 03:04:05"));
+    }
+
+    #[test]
+    fn png_gets_exif_and_an_xmp_date() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = image_file(dir.path(), "shot.png", ImageFormat::Png);
+        let meta = MediaMetadata { media_path: path.clone(), camera_make: Some("Google".to_string()), ..MediaMetadata::default() };
+        write_metadata(&meta, Some("2021:05:01 10:30:00"), Some("-04:00")).unwrap();
+
+        let png = Png::from_bytes(fs::read(&path).unwrap().into()).unwrap();
+        let exif = Reader::new().read_raw(png.exif().unwrap().to_vec()).unwrap();
+        assert_eq!(exif_text(&exif, Tag::DateTimeOriginal).as_deref(), Some("2021:05:01 10:30:00"));
+        assert_eq!(exif_text(&exif, Tag::Make).as_deref(), Some("Google"));
+        let chunks = png.chunks();
+        let idat = chunks.iter().position(|c| c.kind() == CHUNK_IDAT).unwrap();
+        let itxt = chunks.iter().position(|c| c.kind() == CHUNK_ITXT).unwrap();
+        assert!(itxt < idat, "metadata must come before the image data");
+        let xmp = itxt_text(&chunks[itxt].contents()[XMP_KEYWORD.len()..]).unwrap();
+        assert!(xmp.contains("<exif:DateTimeOriginal>2021-05-01T10:30:00-04:00</exif:DateTimeOriginal>"));
+    }
+
+    #[test]
+    fn existing_xmp_is_extended_unless_it_has_the_property() {
+        let properties = [("dc:title", "<dc:title>x</dc:title>".to_string())];
+        let packet = xmp_packet(None, &properties).unwrap();
+        let other = [("xmp:Rating", "<xmp:Rating>5</xmp:Rating>".to_string())];
+        let merged = xmp_packet(Some(&packet), &other).unwrap();
+        assert_eq!(merged.matches("<rdf:Description").count(), 2);
+        assert!(merged.find("<xmp:Rating>").unwrap() < merged.find("</rdf:RDF>").unwrap());
+        assert!(xmp_packet(Some(&packet), &properties).is_err());
+    }
+
+    #[test]
+    fn iptc_captions_are_cut_at_a_character_boundary() {
+        let segment = iptc_caption(&format!("a{}", "é".repeat(1500)));
+        let at = segment.windows(3).position(|w| w == [0x1C, 2, 120]).unwrap() + 3;
+        let len = u16::from_be_bytes([segment[at], segment[at + 1]]) as usize;
+        assert_eq!(len, 1999);
+        assert!(std::str::from_utf8(&segment[at + 2..at + 2 + len]).is_ok());
+    }
+
+    #[test]
+    fn coordinates_and_dates_are_converted() {
+        let dms = degrees_to_dms(-33.8568);
+        assert_eq!((dms[0].num, dms[1].num), (33, 51));
+        assert!((dms[2].to_f64() - 24.48).abs() < 1e-3);
+        assert_eq!(xmp_date("2021:05:01 10:30:00", Some("+02:00")), "2021-05-01T10:30:00+02:00");
+        assert_eq!(xmp_date("2021:05:01 10:30:00", None), "2021-05-01T10:30:00");
+    }
+}
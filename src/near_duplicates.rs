@@ -0,0 +1,245 @@
+// near_duplicates.rs
+// Perceptual near-duplicate detection for MetaSort_v1.0.0 – Google Photos Takeout Organizer
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use rayon::prelude::*;
+use crate::utils::log_to_file;
+
+/// Photos whose hashes differ in at most this many of their 64 bits are
+/// near-duplicates: recompressed, resized, or shot a moment apart.
+pub const MAX_DISTANCE: u32 = 10;
+/// Formats decoded for hashing.
+const EXTENSIONS: [&str; 4] = ["jpg", "jpeg", "png", "webp"];
+
+/// The perceptual hash of one photo, with what its quality is judged by.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PhotoHash {
+    pub path: PathBuf,
+    /// 64-bit difference hash (dHash) of the photo.
+    pub hash: u64,
+    pub width: u32,
+    pub height: u32,
+    pub size: u64,
+}
+
+impl PhotoHash {
+    pub fn distance(&self, other: &PhotoHash) -> u32 {
+        (self.hash ^ other.hash).count_ones()
+    }
+}
+
+/// Photos that look alike, best quality first.
+#[derive(Debug, Clone)]
+pub struct NearDuplicateGroup {
+    pub photos: Vec<PhotoHash>,
+}
+
+impl NearDuplicateGroup {
+    /// The photo with the most pixels, then the largest file.
+    pub fn best(&self) -> &PhotoHash {
+        &self.photos[0]
+    }
+}
+
+/// Computes the dHash of a photo: shrunk to 9x8 grey pixels, each bit tells
+/// whether a pixel is brighter than its right neighbour. Recompressing or
+/// resizing a photo barely changes it.
+pub fn dhash(path: &Path) -> Result<PhotoHash, String> {
+    let image = image::ImageReader::open(path)
+        .and_then(|reader| reader.with_guessed_format())
+        .map_err(|e| e.to_string())?
+        .decode()
+        .map_err(|e| e.to_string())?;
+    let pixels = image.thumbnail_exact(9, 8).to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash = (hash << 1) | u64::from(pixels.get_pixel(x, y)[0] > pixels.get_pixel(x + 1, y)[0]);
+        }
+    }
+    let size = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    Ok(PhotoHash { path: path.to_path_buf(), hash, width: image.width(), height: image.height(), size })
+}
+
+/// Groups the JPEG, PNG and WebP photos among `paths` that are within
+/// [`MAX_DISTANCE`] of each other. Photos that cannot be decoded are logged
+/// to `near_duplicates.log` and skipped. Nothing is moved or deleted.
+pub fn find_near_duplicates(paths: &[PathBuf], logs_dir: &Path) -> Vec<NearDuplicateGroup> {
+    let photos: Vec<&PathBuf> = paths.iter()
+        .filter(|path| path.extension().and_then(|e| e.to_str()).is_some_and(|ext| EXTENSIONS.contains(&ext.to_lowercase().as_str())))
+        .collect();
+    let results: Vec<Result<PhotoHash, String>> = photos.par_iter().map(|path| dhash(path)).collect();
+    let mut hashes = Vec::new();
+    for (path, result) in photos.iter().zip(results) {
+        match result {
+            Ok(hash) => hashes.push(hash),
+            Err(e) => log_to_file(logs_dir, "near_duplicates.log", &format!("Could not read {:?}: {}", path, e)),
+        }
+    }
+
+    // Union-find over every pair close enough, found through a BK-tree rather than by comparing all pairs
+    let mut parent: Vec<usize> = (0..hashes.len()).collect();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    let mut tree = BkTree::default();
+    for (i, photo) in hashes.iter().enumerate() {
+        for j in tree.within(photo.hash, MAX_DISTANCE) {
+            let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+            parent[a.max(b)] = a.min(b);
+        }
+        tree.insert(photo.hash, i);
+    }
+    let mut groups: Vec<Vec<PhotoHash>> = vec![Vec::new(); hashes.len()];
+    for (i, hash) in hashes.into_iter().enumerate() {
+        let group = root(&mut parent, i);
+        groups[group].push(hash);
+    }
+    groups.into_iter()
+        .filter(|photos| photos.len() > 1)
+        .map(|mut photos| {
+            photos.sort_by(|a, b| {
+                let quality = |p: &PhotoHash| (u64::from(p.width) * u64::from(p.height), p.size);
+                quality(b).cmp(&quality(a)).then_with(|| a.path.cmp(&b.path))
+            });
+            NearDuplicateGroup { photos }
+        })
+        .collect()
+}
+
+/// A BK-tree of hashes under the Hamming distance: the children of a node are
+/// keyed by their distance to it, so by the triangle inequality a search only
+/// descends into children whose key is within the search distance of the
+/// node's own distance to the hash looked for.
+#[derive(Default)]
+struct BkTree {
+    /// `(hash, index of the photo, children by distance)`; the root is the first.
+    nodes: Vec<(u64, usize, HashMap<u32, usize>)>,
+}
+
+impl BkTree {
+    fn insert(&mut self, hash: u64, index: usize) {
+        let new = self.nodes.len();
+        let mut node = 0;
+        while node < new {
+            let distance = (self.nodes[node].0 ^ hash).count_ones();
+            match self.nodes[node].2.get(&distance) {
+                Some(&child) => node = child,
+                None => {
+                    self.nodes[node].2.insert(distance, new);
+                    break;
+                }
+            }
+        }
+        self.nodes.push((hash, index, HashMap::new()));
+    }
+
+    /// Indices of the photos whose hash is at most `max` bits from `hash`.
+    fn within(&self, hash: u64, max: u32) -> Vec<usize> {
+        let mut found = Vec::new();
+        let mut pending = if self.nodes.is_empty() { Vec::new() } else { vec![0] };
+        while let Some(node) = pending.pop() {
+            let (node_hash, index, children) = &self.nodes[node];
+            let distance = (node_hash ^ hash).count_ones();
+            if distance <= max {
+                found.push(*index);
+            }
+            pending.extend(children.iter()
+                .filter(|(key, _)| key.abs_diff(distance) <= max)
+                .map(|(_, child)| *child));
+        }
+        found
+    }
+}
+
+/// Writes `near_duplicates.csv` for review: one row per photo, grouped, with
+/// the best photo of each group marked and the distance of the others to it.
+pub fn write_report(groups: &[NearDuplicateGroup], path: &Path) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut wtr = csv::Writer::from_path(path)?;
+    wtr.write_record(["Group", "Best", "FileName", "File Resolution", "Bytes", "Distance"])?;
+    for (i, group) in groups.iter().enumerate() {
+        let best = group.best();
+        for photo in &group.photos {
+            wtr.write_record([
+                (i + 1).to_string(),
+                if photo == best { "yes" } else { "" }.to_string(),
+                photo.path.to_string_lossy().to_string(),
+                format!("{}x{}", photo.width, photo.height),
+                photo.size.to_string(),
+                photo.distance(best).to_string(),
+            ])?;
+        }
+    }
+    wtr.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{imageops, ImageFormat, Luma, GrayImage};
+
+    fn landscape(width: u32, height: u32, phase: f64) -> GrayImage {
+        GrayImage::from_fn(width, height, |x, y| {
+            let (x, y) = (x as f64 / width as f64 * 9.0, y as f64 / height as f64 * 8.0);
+            Luma([(128.0 + 60.0 * (x * 1.3 + phase).sin() + 60.0 * (y * 0.9 - x * phase).cos()) as u8])
+        })
+    }
+
+    #[test]
+    fn resized_and_reencoded_copies_are_grouped() {
+        let dir = tempfile::tempdir().unwrap();
+        let photo = landscape(400, 300, 0.0);
+        let paths = ["photo.jpg", "small.png", "other.jpg"].map(|name| dir.path().join(name));
+        photo.save_with_format(&paths[0], ImageFormat::Jpeg).unwrap();
+        imageops::resize(&photo, 120, 90, imageops::FilterType::Triangle).save_with_format(&paths[1], ImageFormat::Png).unwrap();
+        landscape(400, 300, 2.0).save_with_format(&paths[2], ImageFormat::Jpeg).unwrap();
+        fs::write(dir.path().join("broken.jpg"), "not a photo").unwrap();
+        let mut inputs = paths.to_vec();
+        inputs.push(dir.path().join("broken.jpg"));
+        inputs.push(dir.path().join("notes.txt"));
+
+        let groups = find_near_duplicates(&inputs, dir.path());
+        assert_eq!(groups.len(), 1);
+        let grouped: Vec<&Path> = groups[0].photos.iter().map(|photo| photo.path.as_path()).collect();
+        // The larger photo is the best one
+        assert_eq!(grouped, [paths[0].as_path(), paths[1].as_path()]);
+        assert!(fs::read_to_string(dir.path().join("near_duplicates.log")).unwrap().contains("broken.jpg"));
+    }
+
+    #[test]
+    fn the_bk_tree_finds_what_comparing_all_pairs_finds() {
+        // A few clusters of hashes with some bits flipped, plus scattered ones
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let centres: Vec<u64> = (0..5).map(|_| next()).collect();
+        let hashes: Vec<u64> = (0..300).map(|i| {
+            let noise = next() & next() & next();
+            if i % 3 == 0 { next() } else { centres[i % 5] ^ noise }
+        }).collect();
+        let mut tree = BkTree::default();
+        for (i, hash) in hashes.iter().enumerate() {
+            tree.insert(*hash, i);
+        }
+        for hash in &hashes {
+            let mut found = tree.within(*hash, MAX_DISTANCE);
+            found.sort();
+            let expected: Vec<usize> = (0..hashes.len()).filter(|&j| (hashes[j] ^ hash).count_ones() <= MAX_DISTANCE).collect();
+            assert_eq!(found, expected);
+        }
+    }
+}
@@ -0,0 +1,875 @@
+// pipeline.rs
+// End-to-end organizer pipeline for MetaSort_v1.0.0 – Google Photos Takeout Organizer
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::config::Config;
+use crate::archive::read_archive;
+use crate::input::{resolve_inputs, InputSource};
+use crate::journal::{Journal, Stage};
+use crate::locale::Locale;
+use crate::merge::VirtualTree;
+use crate::error::{Error, Result};
+use crate::metadata_embed::{self, choose_date, DateSource};
+use crate::metadata_extraction::{self, MediaMetadata, UnpairedPolicy};
+use crate::native_metadata::MetadataWriter;
+use crate::platform::{is_exiftool_available, set_exiftool_path};
+use crate::ui::MetaSortUI;
+use crate::utils::log_to_file;
+use crate::plan::{JsonRename, Plan, PlanEntry};
+use crate::transfer::{transfer_file, TransferMode};
+use crate::albums::{self, AlbumMode};
+use crate::collision::{self, CollisionStrategy, Resolution, COLLISIONS_CSV};
+use crate::dedup;
+use crate::edited::{self, EditedPolicy};
+use crate::item_status::{self, StatusPolicy, STATUS_FOLDERS};
+use crate::live_photos::{self, MotionPhotoMode};
+use crate::near_duplicates;
+use crate::sidecar::{self, SidecarMatches};
+use crate::{exiftool, html_report, media_cleaning, sort_to_folders};
+
+/// Name of the working copy created inside the output folder.
+pub const TEMP_DIR_NAME: &str = "MetaSort_temp";
+/// Name of the organized library created inside the output folder.
+pub const OUTPUT_DIR_NAME: &str = "MetaSort_Output";
+/// Folder in the output for files left out of a `--transfer move` run.
+pub const LEFT_OUT_DIR_NAME: &str = "Left Out";
+/// Name of the folder a dry run writes its plan manifest into.
+pub const PLAN_DIR_NAME: &str = "MetaSort_Plan";
+
+/// Builder for a complete MetaSort run: copy (or extract archives), clean,
+/// extract metadata, embed, sort and report.
+///
+/// Settings left unset take their defaults (see [`Config::fill_defaults`]), so
+/// a pipeline never reads from stdin unless [`Pipeline::interactive`] asks it
+/// to.
+///
+/// ```no_run
+/// use metasort::{Pipeline, UnpairedPolicy};
+///
+/// let summary = Pipeline::new("Takeout", "Organized")
+///     .separate_whatsapp_screenshots(true)
+///     .unpaired_policy(UnpairedPolicy::Guess)
+///     .run()?;
+/// println!("{} files organized", summary.total());
+/// # Ok::<(), metasort::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Pipeline {
+    inputs: Vec<PathBuf>,
+    output: PathBuf,
+    config: Config,
+    interactive: bool,
+}
+
+/// File counts of a finished run, as shown in the terminal and HTML summary.
+#[derive(Debug, Clone, Default)]
+pub struct RunSummary {
+    pub output_dir: PathBuf,
+    pub photos: usize,
+    pub videos: usize,
+    pub whatsapp: usize,
+    pub screenshots: usize,
+    pub unknown: usize,
+    pub mkv: usize,
+    /// Files sorted into 'Trash', 'Locked Folder' or 'Archived'.
+    pub separated: usize,
+    pub errors: usize,
+    pub metadata: Vec<MediaMetadata>,
+}
+
+impl RunSummary {
+    pub fn total(&self) -> usize {
+        self.photos + self.videos + self.whatsapp + self.screenshots + self.unknown + self.mkv + self.separated
+    }
+}
+
+impl Pipeline {
+    /// `input` is a Takeout folder, a `takeout-*.zip`/`.tgz` archive, or a
+    /// folder of such archives.
+    pub fn new(input: impl Into<PathBuf>, output: impl Into<PathBuf>) -> Self {
+        let output = output.into();
+        let config = Config { output: Some(output.clone()), ..Config::default() };
+        Self { inputs: vec![input.into()], output, config, interactive: false }
+    }
+
+    /// Builds a pipeline from saved settings; the config must name an output folder.
+    pub fn from_config(input: impl Into<PathBuf>, config: Config) -> Result<Self> {
+        let output = config.output.clone().ok_or(Error::MissingOutput)?;
+        Ok(Self { inputs: vec![input.into()], output, config, interactive: false })
+    }
+
+    /// Adds another input, e.g. the next part of a split Takeout.
+    pub fn add_input(mut self, input: impl Into<PathBuf>) -> Self {
+        self.inputs.push(input.into());
+        self
+    }
+
+    pub fn separate_whatsapp_screenshots(mut self, separate: bool) -> Self {
+        self.config.separate_whatsapp_screenshots = Some(separate);
+        self
+    }
+
+    pub fn unpaired_policy(mut self, policy: UnpairedPolicy) -> Self {
+        self.config.unpaired = Some(policy);
+        self
+    }
+
+    pub fn date_source(mut self, source: DateSource) -> Self {
+        self.config.date_source = Some(source);
+        self
+    }
+
+    /// How input folders are brought into the working folder; defaults to copying.
+    pub fn transfer_mode(mut self, mode: TransferMode) -> Self {
+        self.config.transfer = Some(mode);
+        self
+    }
+
+    /// What to do when a file would land on a name that is taken; numbered by default.
+    pub fn collision_strategy(mut self, strategy: CollisionStrategy) -> Self {
+        self.config.on_collision = Some(strategy);
+        self
+    }
+
+    /// Folder layout template below `Media Files`; see [`crate::layout`].
+    pub fn layout(mut self, template: impl Into<String>) -> Self {
+        self.config.layout = Some(template.into());
+        self
+    }
+
+    /// File name template for dated files; they keep their names by default.
+    pub fn rename(mut self, template: impl Into<String>) -> Self {
+        self.config.rename = Some(template.into());
+        self
+    }
+
+    /// Language of the month and category folder names; English by default.
+    pub fn locale(mut self, locale: Locale) -> Self {
+        self.config.locale = Some(locale);
+        self
+    }
+
+    /// Time zone of dates whose zone is neither in the file nor known from GPS; UTC by default.
+    pub fn timezone(mut self, zone: impl Into<String>) -> Self {
+        self.config.timezone = Some(zone.into());
+        self
+    }
+
+    /// How albums are recreated in the output; by default they are only recorded.
+    pub fn album_mode(mut self, mode: AlbumMode) -> Self {
+        self.config.albums = Some(mode);
+        self
+    }
+
+    /// What to do with the video embedded in Motion Photos; kept by default.
+    pub fn motion_photo_mode(mut self, mode: MotionPhotoMode) -> Self {
+        self.config.motion_photos = Some(mode);
+        self
+    }
+
+    /// Report groups of near-duplicate photos in `near_duplicates.csv`.
+    pub fn near_duplicates(mut self, report: bool) -> Self {
+        self.config.near_duplicates = Some(report);
+        self
+    }
+
+    /// Which versions of photos edited in Google Photos are kept; both by default.
+    pub fn edited_policy(mut self, policy: EditedPolicy) -> Self {
+        self.config.edited = Some(policy);
+        self
+    }
+
+    /// What to do with files archived in Google Photos; sorted normally by default.
+    pub fn archived_policy(mut self, policy: StatusPolicy) -> Self {
+        self.config.archived = Some(policy);
+        self
+    }
+
+    /// What to do with files in the Google Photos trash; separated by default.
+    pub fn trashed_policy(mut self, policy: StatusPolicy) -> Self {
+        self.config.trashed = Some(policy);
+        self
+    }
+
+    /// What to do with Locked Folder files; separated by default.
+    pub fn locked_policy(mut self, policy: StatusPolicy) -> Self {
+        self.config.locked = Some(policy);
+        self
+    }
+
+    /// Number of files processed in parallel; defaults to one per CPU core.
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.config.jobs = Some(jobs);
+        self
+    }
+
+    /// How metadata is written into files; defaults to the built-in writer.
+    pub fn metadata_writer(mut self, writer: MetadataWriter) -> Self {
+        self.config.writer = Some(writer);
+        self
+    }
+
+    pub fn exiftool_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.exiftool_path = Some(path.into());
+        self
+    }
+
+    /// Asks on stdin for the settings a run needs but were left unset (WhatsApp
+    /// separation, unpaired media, date source) when it reaches them, instead
+    /// of using their defaults. Off by default.
+    pub fn interactive(mut self, interactive: bool) -> Self {
+        self.interactive = interactive;
+        self
+    }
+
+    /// Settings of this run, including any answers given interactively.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn inputs(&self) -> &[PathBuf] {
+        &self.inputs
+    }
+
+    pub fn output_dir(&self) -> &Path {
+        &self.output
+    }
+
+    /// Working copy of the input; deleted at the end of a successful run.
+    pub fn temp_dir(&self) -> PathBuf {
+        self.output.join(TEMP_DIR_NAME)
+    }
+
+    /// Folder a dry run writes its manifest and logs into.
+    pub fn plan_dir(&self) -> PathBuf {
+        self.output.join(PLAN_DIR_NAME)
+    }
+
+    /// Computes what [`Pipeline::run`] would do without copying, renaming or
+    /// writing metadata: the JSON renames, the pairing, the metadata to embed
+    /// and the destination of every file. exiftool is only used to read.
+    pub fn plan(&mut self) -> Result<Plan> {
+        if let Some(path) = &self.config.exiftool_path {
+            set_exiftool_path(path.clone());
+        }
+        if !self.interactive {
+            self.config.fill_defaults();
+        }
+        let jobs = self.config.jobs;
+        with_workers(jobs, || self.plan_on_workers())
+    }
+
+    fn plan_on_workers(&mut self) -> Result<Plan> {
+        let roots: Vec<PathBuf> = resolve_inputs(&self.inputs)?.into_iter().map(|source| match source {
+            InputSource::Directory(path) | InputSource::Archive(path) => path,
+        }).collect();
+        let layout = self.config.layout()?;
+        let timezone = self.config.timezone()?;
+        let logs_dir = self.plan_dir().join("logs");
+        let final_output_dir = self.output.join(OUTPUT_DIR_NAME);
+
+        MetaSortUI::print_section_header("Planning (dry run)");
+        let separate_wa_sc = self.resolve_separation()?;
+        // All inputs are planned as one library, like the merged working folder of a run.
+        // Archives are not extracted: only their JSON files are read out, to a scratch folder
+        let scratch_dir = self.plan_dir().join("archives");
+        let mut tree = VirtualTree::build(&roots)?;
+        tree.read_for_planning(&scratch_dir)?;
+        let sidecars = tree.resolve_sidecars();
+        sidecar::log_report(&sidecars, &logs_dir);
+        report_sidecars(&sidecars);
+        let renames = sidecars.renames;
+        let (mut pairs, mut unpaired) = tree.pair_media_with_json(&renames);
+        // Album copies of photos in a year folder are dropped, like in a run
+        let album_scan = albums::scan_albums(tree.files(), |a, b| tree.same_contents(a, b));
+        let album_copies: HashSet<&Path> = album_scan.copies.iter().map(|(copy, _)| copy.as_path()).collect();
+        let is_album_copy = |real: &Path| tree.logical_path_of(real).is_some_and(|logical| album_copies.contains(logical));
+        pairs.retain(|(media, _)| !is_album_copy(media));
+        unpaired.retain(|media| !is_album_copy(media));
+        let mut album_titles: HashMap<&Path, Vec<String>> = HashMap::new();
+        for album in &album_scan.albums {
+            for member in &album.members {
+                album_titles.entry(member.as_path()).or_default().push(album.title.clone());
+            }
+        }
+        let unpaired_policy = self.resolve_unpaired_policy(pairs.len(), unpaired.len())?;
+        let (mut metadata, mut failed_guess_paths) = metadata_extraction::extract_paired_metadata(
+            pairs, unpaired, unpaired_policy, timezone.as_ref(), &logs_dir, &mut Journal::disabled(),
+        );
+        for meta in &mut metadata {
+            let merged_path = tree.logical_path_of(&meta.media_path).unwrap_or(&meta.media_path);
+            meta.albums = album_titles.get(merged_path).cloned().unwrap_or_default();
+        }
+        let duplicates = dedup::find_duplicates_by(&metadata, |path| tree.size_of(path), |path| tree.hash_of(path));
+        dedup::merge_duplicates(&mut metadata, &mut failed_guess_paths, &duplicates);
+        for duplicate in &duplicates {
+            log_to_file(&logs_dir, "dedup.log", &format!("Would remove {:?}: same contents as {:?}", duplicate.path, duplicate.kept));
+        }
+        // Motion Photo videos are only extracted or stripped in a run
+        let live_pairs = live_photos::pair_live_photos(&mut metadata, &mut failed_guess_paths, MotionPhotoMode::Keep, &logs_dir);
+        let use_filename = self.resolve_date_source()? == DateSource::Filename;
+        let policies = self.config.status_policies();
+        let dropped_edits = edited::dropped_by_policy(&metadata, self.config.edited.unwrap_or_default());
+
+        let mut renamer = layout.renamer();
+        let mut entries = Vec::new();
+        for meta in &metadata {
+            let status = policies.status_of(meta);
+            // A file renamed while merging parts is sorted under its merged name
+            let merged_path = tree.logical_path_of(&meta.media_path).unwrap_or(&meta.media_path);
+            let filename = merged_path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+            let is_wa_sc = separate_wa_sc && media_cleaning::separation_folder(&filename).is_some();
+            let (date, used) = choose_date(meta, use_filename, is_wa_sc);
+            // The sort stage reads back the embedded date; without one it sees what the file already has
+            let mut media = sort_to_folders::read_sort_info(&meta.media_path, timezone.as_ref());
+            if date.is_some() {
+                media.date_taken = date.clone();
+            }
+            media.camera_make = meta.camera_make.clone().or(media.camera_make);
+            media.camera_model = meta.camera_model.clone().or(media.camera_model);
+            let is_failed_guess = failed_guess_paths.contains(&meta.media_path);
+            let album = meta.albums.first().map(String::as_str);
+            let (dest_folder, category) = sort_to_folders::destination_folder(
+                &final_output_dir, &layout, &filename, &media, album, is_failed_guess, status.map(|(folder, _)| folder), separate_wa_sc,
+            );
+            let destination = sort_to_folders::file_destination(renamer.as_mut(), &dest_folder, category, &media, album, &filename, |path| path.exists());
+            // Excluded files are listed without a destination
+            let excluded = status.is_some_and(|(_, policy)| policy == StatusPolicy::Exclude)
+                || dropped_edits.contains(&meta.media_path);
+            entries.push(PlanEntry {
+                source: meta.media_path.clone(),
+                json: (!meta.json_path.as_os_str().is_empty()).then(|| tree.source_of(&meta.json_path).to_path_buf()),
+                category: if excluded { "Excluded".to_string() } else { category.to_string() },
+                date,
+                date_source: used.to_string(),
+                gps_latitude: meta.gps_latitude,
+                gps_longitude: meta.gps_longitude,
+                gps_altitude: meta.gps_altitude,
+                camera_make: meta.camera_make.clone(),
+                camera_model: meta.camera_model.clone(),
+                albums: meta.albums.clone(),
+                destination: if excluded { PathBuf::new() } else { destination },
+            });
+        }
+        // Duplicates are listed without a destination (see dedup.log)
+        entries.extend(duplicates.iter().map(|duplicate| PlanEntry {
+            source: duplicate.path.clone(),
+            category: "Duplicate".to_string(),
+            ..PlanEntry::default()
+        }));
+        // Live Photo clips are sorted next to their photo
+        let photos: HashMap<PathBuf, (PathBuf, String)> = entries.iter()
+            .map(|entry| (entry.source.clone(), (entry.destination.clone(), entry.category.clone())))
+            .collect();
+        for entry in &mut entries {
+            let Some(photo) = live_pairs.get(&entry.source) else { continue };
+            if let Some((destination, category)) = photos.get(photo) {
+                if !destination.as_os_str().is_empty() && !entry.destination.as_os_str().is_empty() {
+                    entry.destination = sort_to_folders::clip_destination(&entry.source, photo, destination);
+                    entry.category = category.clone();
+                }
+            }
+        }
+        // Names taken twice, or already in the output, are resolved like the sort stage would
+        let on_collision = self.config.on_collision.unwrap_or_default();
+        let collisions_csv = self.plan_dir().join(COLLISIONS_CSV);
+        let _ = fs::remove_file(&collisions_csv);
+        let mut claimed: HashMap<PathBuf, PathBuf> = HashMap::new();
+        for entry in &mut entries {
+            if entry.destination.as_os_str().is_empty() {
+                continue;
+            }
+            let occupant = |path: &Path| claimed.get(path).cloned().or_else(|| path.exists().then(|| path.to_path_buf()));
+            if occupant(&entry.destination).is_some() {
+                match collision::resolve(&entry.source, &entry.destination, on_collision, occupant) {
+                    Ok(resolution) => {
+                        collision::record(&entry.source, &entry.destination, &resolution, &logs_dir, "sorting.log", &collisions_csv);
+                        match resolution {
+                            Resolution::Renamed(renamed) => entry.destination = renamed,
+                            Resolution::Identical(_) => {
+                                entry.category = "Duplicate".to_string();
+                                entry.destination = PathBuf::new();
+                                continue;
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        log_to_file(&logs_dir, "sorting.log", &format!("❌ {}", e));
+                        entry.category = "Collision".to_string();
+                        entry.destination = PathBuf::new();
+                        continue;
+                    }
+                }
+            }
+            claimed.insert(entry.destination.clone(), entry.source.clone());
+        }
+        let _ = fs::remove_dir_all(&scratch_dir);
+        let json_renames = renames.iter().map(|(from, to)| {
+            let from = tree.source_of(from).to_path_buf();
+            let to = to.file_name().map_or_else(|| to.clone(), |name| from.with_file_name(name));
+            JsonRename { from, to }
+        }).collect();
+        Ok(Plan { json_renames, entries })
+    }
+
+    /// Runs every stage. Settings asked for by an [interactive](Pipeline::interactive)
+    /// run, or filled with their defaults, are stored back into [`Pipeline::config`].
+    pub fn run(&mut self) -> Result<RunSummary> {
+        if let Some(path) = &self.config.exiftool_path {
+            set_exiftool_path(path.clone());
+        }
+        if self.config.requires_exiftool() && !is_exiftool_available() {
+            return Err(Error::ExifToolMissing);
+        }
+        if !self.interactive {
+            self.config.fill_defaults();
+        }
+        let jobs = self.config.jobs;
+        with_workers(jobs, || self.run_on_workers())
+    }
+
+    fn run_on_workers(&mut self) -> Result<RunSummary> {
+        let layout = self.config.layout()?;
+        let timezone = self.config.timezone()?;
+        let sources = resolve_inputs(&self.inputs)?;
+        let temp_dir = self.temp_dir();
+        let temp_str = temp_dir.to_string_lossy().to_string();
+
+        // Pick up an interrupted run from its journal
+        let mut journal = Journal::open(&self.output)?;
+        if journal.is_resumed() {
+            MetaSortUI::print_info("Resuming the previous run; completed files are skipped.");
+        }
+
+        // Copy input folder to MetaSort_temp in output directory
+        MetaSortUI::print_section_header("Copying Files");
+        let mode = self.config.transfer.unwrap_or_default();
+        MetaSortUI::print_info(match mode {
+            TransferMode::Copy => "Copying input folder to working directory...",
+            TransferMode::Move => "Moving input files to working directory (originals will be consumed)...",
+            TransferMode::Hardlink => "Hardlinking input files into working directory...",
+            TransferMode::Reflink => "Cloning input files into working directory...",
+        });
+
+        // Every part, folder or archive, is merged into one tree
+        let roots: Vec<PathBuf> = sources.iter().map(|source| match source {
+            InputSource::Directory(path) | InputSource::Archive(path) => path.clone(),
+        }).collect();
+
+        let mut ui = MetaSortUI::new();
+        if !journal.is_stage_done(Stage::Copy) {
+            // Files an interrupted run brought over keep the path they got then
+            let transferred: Vec<(PathBuf, PathBuf)> = journal.entries(Stage::Copy).into_iter()
+                .filter_map(|(real, logical)| Some((PathBuf::from(real), PathBuf::from(logical.as_str()?))))
+                .collect();
+            let tree = VirtualTree::build_resumed(&roots, &transferred, &temp_dir)?;
+            ui.start_main_progress(tree.len() as u64, "Copying files");
+            log_merge(&tree, &self.output.join(OUTPUT_DIR_NAME).join("Technical Files").join("logs"));
+            copy_to_working_dir(&tree, &temp_dir, mode, &mut journal, &ui)?;
+            journal.finish_stage(Stage::Copy);
+        }
+
+        ui.finish_progress("Copy complete!");
+        MetaSortUI::print_success(&format!("All processing will happen in: {}", temp_dir.display()));
+
+        // 1. Clean and pair media files with their JSONs (fix weird JSON names)
+        MetaSortUI::print_section_header("Cleaning and Pairing Files");
+        MetaSortUI::print_info("Cleaning and pairing media files with JSONs...");
+        let technical_logs_dir = self.output.join(OUTPUT_DIR_NAME).join("Technical Files").join("logs");
+        let sidecars = media_cleaning::clean_json_filenames(&temp_str, &technical_logs_dir, &mut journal);
+        report_sidecars(&sidecars);
+        MetaSortUI::print_success("JSON filename cleaning and pairing complete!");
+
+        // Moved input files exist only in the working folder, so files left out are kept here
+        let left_out_dir = (mode == TransferMode::Move).then(|| self.output.join(OUTPUT_DIR_NAME).join(LEFT_OUT_DIR_NAME));
+
+        // 1a. Read albums; album copies of photos that are also in a year folder are dropped
+        let albums = albums::collect_albums(&temp_dir, left_out_dir.as_deref(), &technical_logs_dir, &mut journal);
+        if !albums.is_empty() {
+            MetaSortUI::print_info(&format!("Found {} albums (see albums.log).", albums.len()));
+        }
+
+        // 1b. Ask if WhatsApp/Screenshots should be separated
+        let separate_wa_sc = self.resolve_separation()?;
+        let on_collision = self.config.on_collision.unwrap_or_default();
+        let collisions_csv = self.output.join(OUTPUT_DIR_NAME).join("Technical Files").join("CSV Report").join(COLLISIONS_CSV);
+        media_cleaning::ask_and_separate_whatsapp_screenshots(&temp_str, separate_wa_sc, on_collision, &collisions_csv, &mut journal)?;
+
+        // 2. Extract metadata from JSON and embed into media files
+        MetaSortUI::print_section_header("Metadata Extraction and Embedding");
+        MetaSortUI::print_info("Extracting metadata from JSON and embedding into media files...");
+        let (pairs, unpaired) = metadata_extraction::pair_media_with_json(&temp_str, &[]);
+        let unpaired_policy = self.resolve_unpaired_policy(pairs.len(), unpaired.len())?;
+        let (mut metadata, mut failed_guess_paths) = metadata_extraction::extract_paired_metadata(
+            pairs, unpaired, unpaired_policy, timezone.as_ref(), &temp_dir.join("logs"), &mut journal,
+        );
+        albums::assign_albums(&albums, &mut metadata, &journal);
+        let duplicates = dedup::remove_duplicates(&mut metadata, &mut failed_guess_paths, &temp_dir, left_out_dir.as_deref(), &technical_logs_dir, &mut journal);
+        if !duplicates.is_empty() {
+            MetaSortUI::print_info(&format!("Removed {} duplicate files; their metadata was merged into the copy kept (see duplicates.csv).", duplicates.len()));
+        }
+        let live_pairs = live_photos::pair_live_photos(&mut metadata, &mut failed_guess_paths, self.config.motion_photos.unwrap_or_default(), &technical_logs_dir);
+        if !live_pairs.is_empty() {
+            MetaSortUI::print_info(&format!("Found {} Live/Motion Photo pairs; each clip is sorted next to its photo.", live_pairs.len()));
+        }
+        let dropped = edited::apply_edited_policy(&mut metadata, self.config.edited.unwrap_or_default(), &temp_dir, left_out_dir.as_deref(), &technical_logs_dir);
+        if dropped > 0 {
+            MetaSortUI::print_info(&format!("Left out {} originals or edited copies of edited photos (see edited.log).", dropped));
+        }
+        let (status_folders, excluded) = item_status::apply_status_policies(&mut metadata, &self.config.status_policies(), &temp_dir, left_out_dir.as_deref(), &technical_logs_dir);
+        if excluded > 0 {
+            MetaSortUI::print_info(&format!("Left out {} archived, trashed or locked files (see item_status.log).", excluded));
+        }
+        if layout.names().is_some() {
+            // Renamed files keep their Takeout name in XMP
+            for meta in &mut metadata {
+                meta.preserved_file_name = meta.media_path.file_name().map(|name| name.to_string_lossy().to_string());
+            }
+        }
+        let date_source = self.resolve_date_source()?;
+        metadata_embed::embed_metadata_all(&metadata, &temp_dir, date_source, self.config.writer.unwrap_or_default(), &mut journal);
+        MetaSortUI::print_success("Metadata extraction and embedding complete!");
+
+        // 3. Sort files using the embedded metadata (DateTimeOriginal)
+        MetaSortUI::print_section_header("Sorting Files");
+        MetaSortUI::print_info("Sorting files using embedded metadata...");
+        let final_output_dir = self.output.join(OUTPUT_DIR_NAME);
+        let first_albums: HashMap<PathBuf, String> = metadata.iter()
+            .filter_map(|meta| Some((meta.media_path.clone(), meta.albums.first()?.clone())))
+            .collect();
+        let sorted = sort_to_folders::sort_files_to_folders(
+            &temp_dir, &final_output_dir, &layout, &first_albums, &failed_guess_paths, &status_folders, &live_pairs,
+            separate_wa_sc, on_collision, timezone.as_ref(), &mut journal,
+        )?;
+        let mut destinations = sorted.destinations.clone();
+        // Albums that had a removed duplicate point at the copy kept
+        for duplicate in &duplicates {
+            if let Some(destination) = destinations.get(&duplicate.kept).cloned() {
+                destinations.insert(duplicate.path.clone(), destination);
+            }
+        }
+        let report = final_output_dir.join("Technical Files").join("CSV Report").join("duplicates.csv");
+        if let Err(e) = dedup::write_report(&duplicates, &report) {
+            MetaSortUI::print_warning(&format!("Could not write {}: {}", report.display(), e));
+        }
+        let album_mode = self.config.albums.unwrap_or_default();
+        let written = albums::write_albums(&albums, &destinations, &final_output_dir, album_mode, &technical_logs_dir, &journal);
+        if written > 0 {
+            MetaSortUI::print_success(&format!("Recreated {} albums in {}", written, final_output_dir.join("Albums").display()));
+        }
+        let csv_report_dir = final_output_dir.join("Technical Files").join("CSV Report");
+        let mut csv_files = vec!["photos.csv", "videos.csv", "unknown_time.csv", "mkv_files.csv", "duplicates.csv", COLLISIONS_CSV];
+        if self.config.near_duplicates.unwrap_or(false) {
+            MetaSortUI::print_info("Looking for near-duplicate photos...");
+            let mut sorted: Vec<PathBuf> = destinations.values().cloned().collect();
+            sorted.sort();
+            sorted.dedup();
+            let groups = near_duplicates::find_near_duplicates(&sorted, &technical_logs_dir);
+            let report = csv_report_dir.join("near_duplicates.csv");
+            match near_duplicates::write_report(&groups, &report) {
+                Ok(()) => MetaSortUI::print_success(&format!("Found {} groups of near-duplicate photos; nothing was deleted (see near_duplicates.csv).", groups.len())),
+                Err(e) => MetaSortUI::print_warning(&format!("Could not write {}: {}", report.display(), e)),
+            }
+            csv_files.push("near_duplicates.csv");
+        }
+        MetaSortUI::print_success("All done! Check your output and logs for details.");
+
+        // 4. Move technical folders into Technical Files
+        let technical_dir = final_output_dir.join("Technical Files");
+        let _ = fs::create_dir_all(&technical_dir);
+        for folder in &["CSV Report", "Json Files", "logs"] {
+            let src = final_output_dir.join(folder);
+            let dst = technical_dir.join(folder);
+            if src.exists() {
+                let _ = fs_extra::dir::move_dir(&src, &dst, &fs_extra::dir::CopyOptions::new());
+            }
+        }
+
+        // 5. HTML summary report
+        let summary = RunSummary {
+            // Counted by category, as the layout decides the folders
+            photos: sorted.count(&["Photos"]),
+            videos: sorted.count(&["Videos"]),
+            whatsapp: sorted.count(&["Whatsapp"]),
+            screenshots: sorted.count(&["Screenshots"]),
+            unknown: sorted.count(&["Unknown Time", "Failed Filename Guess"]),
+            mkv: sorted.count(&["mkv_files"]),
+            separated: sorted.count(&STATUS_FOLDERS),
+            errors: count_log_errors(&technical_dir.join("logs")),
+            output_dir: final_output_dir,
+            metadata,
+        };
+        let log_files = vec!["merge.log", "sidecars.log", "albums.log", "dedup.log", "near_duplicates.log", "live_photos.log", "edited.log", "item_status.log", "media_cleaning.log", "metadata_extraction.log", "metadata_embedding.log", "sorting.log"];
+        let metadata_fields: Vec<&str> = if let Some(meta) = summary.metadata.first() {
+            let mut fields = vec!["media_path", "json_path"];
+            if meta.exif_date.is_some() { fields.push("exif_date"); }
+            if meta.exif_offset.is_some() { fields.push("exif_offset"); }
+            if meta.gps_latitude.is_some() { fields.push("gps_latitude"); }
+            if meta.gps_longitude.is_some() { fields.push("gps_longitude"); }
+            if meta.gps_altitude.is_some() { fields.push("gps_altitude"); }
+            if meta.camera_make.is_some() { fields.push("camera_make"); }
+            if meta.camera_model.is_some() { fields.push("camera_model"); }
+            if summary.metadata.iter().any(|m| !m.albums.is_empty()) { fields.push("albums"); }
+            if summary.metadata.iter().any(|m| m.title.is_some()) { fields.push("title"); }
+            if summary.metadata.iter().any(|m| m.description.is_some()) { fields.push("description"); }
+            if summary.metadata.iter().any(|m| !m.people.is_empty()) { fields.push("people"); }
+            if summary.metadata.iter().any(|m| m.favorited) { fields.push("favorited"); }
+            if summary.metadata.iter().any(|m| m.archived) { fields.push("archived"); }
+            if summary.metadata.iter().any(|m| m.trashed) { fields.push("trashed"); }
+            if summary.metadata.iter().any(|m| m.locked) { fields.push("locked"); }
+            fields
+        } else {
+            vec!["media_path", "json_path", "exif_date", "gps_latitude", "gps_longitude", "gps_altitude", "camera_make", "camera_model"]
+        };
+        html_report::generate_html_report(
+            &summary.output_dir,
+            summary.total(), summary.photos, summary.videos, summary.whatsapp, summary.screenshots, summary.unknown, summary.mkv, summary.errors,
+            &csv_files, &log_files, &metadata_fields,
+        );
+
+        // Delete MetaSort_temp folder after all processing
+        if temp_dir.exists() {
+            match fs_extra::dir::remove(&temp_dir) {
+                Ok(_) => MetaSortUI::print_info(&format!("Deleted temporary folder: {}", temp_dir.display())),
+                Err(e) => MetaSortUI::print_warning(&format!("Could not delete temporary folder: {} (Error: {})", temp_dir.display(), e)),
+            }
+        }
+        journal.remove();
+        Ok(summary)
+    }
+
+    fn resolve_separation(&mut self) -> Result<bool> {
+        let separate_wa_sc = match self.config.separate_whatsapp_screenshots {
+            Some(separate) => separate,
+            None => {
+                let answer = ask("\nDo you want to separate WhatsApp and Screenshot images? (y/n)")?;
+                matches!(answer.to_lowercase().as_str(), "y" | "yes")
+            }
+        };
+        self.config.separate_whatsapp_screenshots = Some(separate_wa_sc);
+        if separate_wa_sc {
+            MetaSortUI::print_success("WhatsApp and Screenshot images will be sorted into their own folders by year/month.");
+        } else {
+            MetaSortUI::print_info("WhatsApp and Screenshot images will be treated as regular photos.");
+        }
+        Ok(separate_wa_sc)
+    }
+
+    /// The unpaired policy; asked for only when some of the `paired + unpaired` media have no JSON.
+    fn resolve_unpaired_policy(&mut self, paired: usize, unpaired: usize) -> Result<UnpairedPolicy> {
+        if let Some(policy) = self.config.unpaired {
+            return Ok(policy);
+        }
+        if unpaired == 0 {
+            return Ok(UnpairedPolicy::UnknownTime);
+        }
+        let answer = ask(&format!(
+            "\n⚠️  No .json found for {} out of {} files.\nWhat should MetaSort do?\n1. Skip and move to 'Unknown Time'\n2. Try to guess timestamp from filename\nEnter 1 or 2:",
+            unpaired, paired + unpaired
+        ))?;
+        let policy = if answer == "2" { UnpairedPolicy::Guess } else { UnpairedPolicy::UnknownTime };
+        self.config.unpaired = Some(policy);
+        Ok(policy)
+    }
+
+    fn resolve_date_source(&mut self) -> Result<DateSource> {
+        if let Some(source) = self.config.date_source {
+            return Ok(source);
+        }
+        let answer = ask("\n🧐Do you want to embed date/time for WhatsApp & Screenshot images based on their  \n1. Metadata\n2. Filename\n")?;
+        let source = if answer == "2" { DateSource::Filename } else { DateSource::Metadata };
+        self.config.date_source = Some(source);
+        Ok(source)
+    }
+}
+
+/// Prints `question` and reads the answer from stdin, trimmed.
+fn ask(question: &str) -> Result<String> {
+    println!("{}", question);
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).map_err(|e| Error::io("stdin", e))?;
+    Ok(answer.trim().to_string())
+}
+
+/// Runs `op` on a pool of `jobs` worker threads (one per core when unset). Each
+/// worker keeps its own exiftool session, which is stopped when `op` returns.
+/// If no pool can be built, `op` runs on rayon's global pool, which may be
+/// shared with the caller; only this thread's session is stopped then, and
+/// the sessions of the global workers stay open for their next commands.
+fn with_workers<R: Send>(jobs: Option<usize>, op: impl FnOnce() -> R + Send) -> R {
+    match rayon::ThreadPoolBuilder::new().num_threads(jobs.unwrap_or(0)).build() {
+        Ok(pool) => {
+            let result = pool.install(op);
+            pool.broadcast(|_| exiftool::close());
+            result
+        }
+        Err(_) => {
+            let result = op();
+            exiftool::close();
+            result
+        }
+    }
+}
+
+/// Brings the merged input folders into the working folder file by file,
+/// skipping files a previous, interrupted run already transferred. Each file
+/// is journalled with its logical path, see [`VirtualTree::build_resumed`].
+/// Files inside archives are extracted straight to their place, reading each
+/// archive once; `mode` only applies to files on disk.
+fn copy_to_working_dir(tree: &VirtualTree, temp_dir: &Path, mode: TransferMode, journal: &mut Journal, ui: &MetaSortUI) -> Result<()> {
+    let mut archives: BTreeMap<&Path, HashMap<PathBuf, PathBuf>> = BTreeMap::new();
+    for (logical, path) in tree.files() {
+        if journal.is_done(Stage::Copy, path) {
+            ui.inc_progress(1);
+            continue;
+        }
+        if let Some((archive, entry)) = tree.archive_entry(path) {
+            archives.entry(archive).or_default().insert(entry.to_path_buf(), temp_dir.join(logical));
+            continue;
+        }
+        let dest = temp_dir.join(logical);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent).map_err(|e| Error::io(parent, e))?;
+        }
+        transfer_file(path, &dest, mode).map_err(|e| Error::Copy { path: path.to_path_buf(), message: e.to_string() })?;
+        journal.record_with(Stage::Copy, path, logical.to_string_lossy().into());
+        ui.inc_progress(1);
+    }
+    for (archive, destinations) in archives {
+        read_archive(archive, &destinations, &HashSet::new(), |entry| {
+            let real = archive.join(entry);
+            if let Some(logical) = tree.logical_path_of(&real) {
+                journal.record_with(Stage::Copy, &real, logical.to_string_lossy().into());
+            }
+            ui.inc_progress(1);
+        })?;
+    }
+    Ok(())
+}
+
+/// Points at `sidecars.log` when some JSON files could not be matched cleanly.
+fn report_sidecars(sidecars: &SidecarMatches) {
+    if !sidecars.ambiguous.is_empty() || !sidecars.orphans.is_empty() {
+        MetaSortUI::print_warning(&format!(
+            "{} ambiguous and {} orphaned JSON files (see sidecars.log).",
+            sidecars.ambiguous.len(), sidecars.orphans.len()
+        ));
+    }
+}
+
+/// Reports what merging several Takeout parts dropped or renamed.
+fn log_merge(tree: &VirtualTree, logs_dir: &Path) {
+    for path in tree.duplicates() {
+        log_to_file(logs_dir, "merge.log", &format!("Skipped {:?}: identical copy already in an earlier part", path));
+    }
+    for (path, logical) in tree.renamed() {
+        log_to_file(logs_dir, "merge.log", &format!("Renamed {:?} to {:?}: a different file has the same path in an earlier part", path, logical));
+    }
+    if !tree.duplicates().is_empty() || !tree.renamed().is_empty() {
+        MetaSortUI::print_info(&format!(
+            "Merged Takeout parts: {} duplicate files skipped, {} clashing files renamed (see merge.log).",
+            tree.duplicates().len(), tree.renamed().len()
+        ));
+    }
+}
+
+fn count_log_errors(logs_dir: &Path) -> usize {
+    let mut errors = 0;
+    if let Ok(entries) = fs::read_dir(logs_dir) {
+        for entry in entries.flatten() {
+            if let Ok(content) = fs::read_to_string(entry.path()) {
+                errors += content.matches("❌").count();
+            }
+        }
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resumed_moves_keep_clashing_parts_apart() {
+        let dir = tempfile::tempdir().unwrap();
+        let parts = [dir.path().join("part1"), dir.path().join("part2")];
+        for (part, contents) in parts.iter().zip(["first", "second"]) {
+            fs::create_dir_all(part.join("Takeout/Google Photos")).unwrap();
+            fs::write(part.join("Takeout/Google Photos/notes"), contents).unwrap();
+        }
+        let (output, temp_dir) = (dir.path().join("out"), dir.path().join("out/temp"));
+        let ui = MetaSortUI::new();
+
+        // The second part's file cannot be moved, so the run stops after the first
+        let blocked = temp_dir.join("Google Photos/notes (part 2)");
+        fs::create_dir_all(&blocked).unwrap();
+        let mut journal = Journal::open(&output).unwrap();
+        let tree = VirtualTree::build(&parts).unwrap();
+        assert!(copy_to_working_dir(&tree, &temp_dir, TransferMode::Move, &mut journal, &ui).is_err());
+        assert!(!parts[0].join("Takeout/Google Photos/notes").exists());
+        fs::remove_dir(&blocked).unwrap();
+
+        // Resumed, the part-1 file is no longer in the input but keeps its path
+        let mut journal = Journal::open(&output).unwrap();
+        let transferred: Vec<(PathBuf, PathBuf)> = journal.entries(Stage::Copy).into_iter()
+            .filter_map(|(real, logical)| Some((PathBuf::from(real), PathBuf::from(logical.as_str()?))))
+            .collect();
+        let tree = VirtualTree::build_resumed(&parts, &transferred, &temp_dir).unwrap();
+        copy_to_working_dir(&tree, &temp_dir, TransferMode::Move, &mut journal, &ui).unwrap();
+        assert_eq!(fs::read_to_string(temp_dir.join("Google Photos/notes")).unwrap(), "first");
+        assert_eq!(fs::read_to_string(temp_dir.join("Google Photos/notes (part 2)")).unwrap(), "second");
+    }
+
+    #[test]
+    fn resumed_runs_extract_only_the_missing_archive_entries() {
+        use std::io::Write;
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("takeout-001.zip");
+        let mut zip = zip::ZipWriter::new(fs::File::create(&archive).unwrap());
+        for (name, contents) in [("a.jpg", "first"), ("b.jpg", "second")] {
+            zip.start_file(format!("Takeout/Google Photos/{}", name), zip::write::SimpleFileOptions::default()).unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+        let (output, temp_dir) = (dir.path().join("out"), dir.path().join("out/temp"));
+        let ui = MetaSortUI::new();
+
+        // The second entry cannot be written, so the run stops after the first
+        let blocked = temp_dir.join("Google Photos/b.jpg");
+        fs::create_dir_all(&blocked).unwrap();
+        let mut journal = Journal::open(&output).unwrap();
+        let tree = VirtualTree::build(std::slice::from_ref(&archive)).unwrap();
+        assert!(copy_to_working_dir(&tree, &temp_dir, TransferMode::Copy, &mut journal, &ui).is_err());
+        fs::remove_dir(&blocked).unwrap();
+        fs::write(temp_dir.join("Google Photos/a.jpg"), "kept").unwrap();
+
+        // Resumed, the journalled entry is not extracted again
+        let mut journal = Journal::open(&output).unwrap();
+        let transferred: Vec<(PathBuf, PathBuf)> = journal.entries(Stage::Copy).into_iter()
+            .filter_map(|(real, logical)| Some((PathBuf::from(real), PathBuf::from(logical.as_str()?))))
+            .collect();
+        assert_eq!(transferred, [(archive.join("Takeout/Google Photos/a.jpg"), PathBuf::from("Google Photos/a.jpg"))]);
+        let tree = VirtualTree::build_resumed(std::slice::from_ref(&archive), &transferred, &temp_dir).unwrap();
+        copy_to_working_dir(&tree, &temp_dir, TransferMode::Copy, &mut journal, &ui).unwrap();
+        assert_eq!(fs::read_to_string(temp_dir.join("Google Photos/a.jpg")).unwrap(), "kept");
+        assert_eq!(fs::read_to_string(temp_dir.join("Google Photos/b.jpg")).unwrap(), "second");
+    }
+
+    #[test]
+    fn pipelines_use_defaults_instead_of_prompting() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("Takeout/Google Photos/Photos from 2021");
+        fs::create_dir_all(&input).unwrap();
+        fs::write(input.join("IMG-20210501-WA0001.jpg"), "no json").unwrap();
+        let mut pipeline = Pipeline::new(dir.path().join("Takeout"), dir.path().join("out"));
+        let plan = pipeline.plan().unwrap();
+        assert_eq!(plan.entries.len(), 1);
+        let config = pipeline.config();
+        assert_eq!(config.separate_whatsapp_screenshots, Some(false));
+        assert_eq!(config.unpaired, Some(UnpairedPolicy::UnknownTime));
+        assert_eq!(config.date_source, Some(DateSource::Metadata));
+    }
+}
@@ -0,0 +1,88 @@
+// plan.rs
+// Dry-run plan manifest for MetaSort_v1.0.0 – Google Photos Takeout Organizer
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+use crate::error::{Error, Result};
+
+/// Everything a run would do, computed without touching the input.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Plan {
+    pub json_renames: Vec<JsonRename>,
+    pub entries: Vec<PlanEntry>,
+}
+
+/// A JSON sidecar that `clean_json_filenames` would rename.
+#[derive(Debug, Clone, Serialize)]
+pub struct JsonRename {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+/// What would happen to one media file.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PlanEntry {
+    pub source: PathBuf,
+    /// JSON sidecar the metadata is read from, if the file is paired.
+    pub json: Option<PathBuf>,
+    pub category: String,
+    /// Date written as DateTimeOriginal, if any.
+    pub date: Option<String>,
+    /// Where the date comes from: "metadata", "filename" or "metadata (fallback)".
+    pub date_source: String,
+    pub gps_latitude: Option<f64>,
+    pub gps_longitude: Option<f64>,
+    pub gps_altitude: Option<f64>,
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    /// Titles of the albums the file is in.
+    pub albums: Vec<String>,
+    pub destination: PathBuf,
+}
+
+impl Plan {
+    /// Writes `plan.csv`, `plan.json` and `json_renames.csv` into `dir`.
+    pub fn write_to(&self, dir: &Path) -> Result<()> {
+        fs::create_dir_all(dir).map_err(|e| Error::io(dir, e))?;
+        self.write_csv(&dir.join("plan.csv"))?;
+        self.write_renames_csv(&dir.join("json_renames.csv"))?;
+        let json_path = dir.join("plan.json");
+        let text = serde_json::to_string_pretty(self).expect("plan is always serializable");
+        fs::write(&json_path, text).map_err(|e| Error::io(&json_path, e))
+    }
+
+    fn write_csv(&self, path: &Path) -> Result<()> {
+        let mut wtr = csv::Writer::from_path(path).map_err(|e| Error::io(path, e.into()))?;
+        let _ = wtr.write_record([
+            "Source", "JSON", "Category", "Date", "Date Source", "GPS Latitude", "GPS Longitude",
+            "GPS Altitude", "Camera Make", "Camera Model", "Albums", "Destination",
+        ]);
+        for entry in &self.entries {
+            let _ = wtr.write_record([
+                entry.source.to_string_lossy().to_string(),
+                entry.json.as_ref().map(|p| p.to_string_lossy().to_string()).unwrap_or_default(),
+                entry.category.clone(),
+                entry.date.clone().unwrap_or_default(),
+                entry.date_source.clone(),
+                entry.gps_latitude.map(|v| v.to_string()).unwrap_or_default(),
+                entry.gps_longitude.map(|v| v.to_string()).unwrap_or_default(),
+                entry.gps_altitude.map(|v| v.to_string()).unwrap_or_default(),
+                entry.camera_make.clone().unwrap_or_default(),
+                entry.camera_model.clone().unwrap_or_default(),
+                entry.albums.join("; "),
+                entry.destination.to_string_lossy().to_string(),
+            ]);
+        }
+        wtr.flush().map_err(|e| Error::io(path, e))
+    }
+
+    fn write_renames_csv(&self, path: &Path) -> Result<()> {
+        let mut wtr = csv::Writer::from_path(path).map_err(|e| Error::io(path, e.into()))?;
+        let _ = wtr.write_record(["From", "To"]);
+        for rename in &self.json_renames {
+            let _ = wtr.write_record([rename.from.to_string_lossy().as_ref(), rename.to.to_string_lossy().as_ref()]);
+        }
+        wtr.flush().map_err(|e| Error::io(path, e))
+    }
+}
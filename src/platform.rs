@@ -3,6 +3,8 @@
 
 use std::process::Command;
 use std::env;
+use std::path::PathBuf;
+use std::sync::OnceLock;
 
 #[cfg(target_os = "windows")]
 pub const EXIFTOOL_CMD: &str = "exiftool.exe";
@@ -10,21 +12,36 @@ pub const EXIFTOOL_CMD: &str = "exiftool.exe";
 #[cfg(not(target_os = "windows"))]
 pub const EXIFTOOL_CMD: &str = "exiftool";
 
-/// Check if exiftool is available on the system
+static EXIFTOOL_PATH: OnceLock<PathBuf> = OnceLock::new();
+static EXIFTOOL_AVAILABLE: OnceLock<bool> = OnceLock::new();
+
+/// Use a specific exiftool executable instead of looking it up in PATH.
+/// Only the first call has an effect.
+pub fn set_exiftool_path(path: PathBuf) {
+    let _ = EXIFTOOL_PATH.set(path);
+}
+
+/// Check if exiftool is available on the system. The answer is cached, so
+/// set the exiftool path first.
 pub fn is_exiftool_available() -> bool {
-    let output = Command::new(EXIFTOOL_CMD)
-        .arg("-ver")
-        .output();
-    
-    match output {
-        Ok(output) => output.status.success(),
-        Err(_) => false,
-    }
+    *EXIFTOOL_AVAILABLE.get_or_init(|| {
+        let output = get_exiftool_command()
+            .arg("-ver")
+            .output();
+
+        match output {
+            Ok(output) => output.status.success(),
+            Err(_) => false,
+        }
+    })
 }
 
 /// Get the exiftool command with proper path handling
 pub fn get_exiftool_command() -> Command {
-    Command::new(EXIFTOOL_CMD)
+    match EXIFTOOL_PATH.get() {
+        Some(path) => Command::new(path),
+        None => Command::new(EXIFTOOL_CMD),
+    }
 }
 
 /// Get platform-specific installation instructions
@@ -0,0 +1,642 @@
+// quicktime.rs
+// Native MP4/MOV metadata for MetaSort_v1.0.0 – Google Photos Takeout Organizer
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use chrono::{DateTime, FixedOffset, NaiveDateTime};
+
+/// Seconds from the QuickTime epoch (1904-01-01) to the Unix epoch.
+const QUICKTIME_EPOCH_OFFSET: i64 = 2_082_844_800;
+/// Atoms whose body is a list of child atoms and may need editing.
+const CONTAINERS: [&[u8; 4]; 7] = [b"moov", b"trak", b"mdia", b"minf", b"stbl", b"udta", b"meta"];
+/// Top-level atoms of a fragmented movie, which hold media data offsets too.
+const FRAGMENT_ATOMS: [&[u8; 4]; 3] = [b"moof", b"sidx", b"mfra"];
+
+pub const KEY_CREATION_DATE: &str = "com.apple.quicktime.creationdate";
+pub const KEY_LOCATION: &str = "com.apple.quicktime.location.ISO6709";
+pub const KEY_MAKE: &str = "com.apple.quicktime.make";
+pub const KEY_MODEL: &str = "com.apple.quicktime.model";
+pub const KEY_TITLE: &str = "com.apple.quicktime.title";
+pub const KEY_DESCRIPTION: &str = "com.apple.quicktime.description";
+
+/// Metadata to write into a QuickTime movie.
+#[derive(Debug, Clone, Default)]
+pub struct MovieTags {
+    /// Local date in EXIF format (`YYYY:MM:DD HH:MM:SS`).
+    pub date: Option<String>,
+    /// UTC offset of `date` (`+02:00`); the date is taken to be UTC without one.
+    pub offset: Option<FixedOffset>,
+    pub gps: Option<(f64, f64, Option<f64>)>,
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub title: Option<String>,
+    pub description: Option<String>,
+}
+
+struct Atom {
+    kind: [u8; 4],
+    body: AtomBody,
+}
+
+enum AtomBody {
+    Data(Vec<u8>),
+    /// `header` holds the version/flags of full boxes such as an ISO `meta`;
+    /// `trailer` keeps bytes after the last child (old QuickTime `udta` ends in zeros).
+    Children { header: Vec<u8>, children: Vec<Atom>, trailer: Vec<u8> },
+}
+
+impl Atom {
+    fn new(kind: &[u8; 4], body: Vec<u8>) -> Atom {
+        Atom { kind: *kind, body: AtomBody::Data(body) }
+    }
+
+    fn container(kind: &[u8; 4], children: Vec<Atom>) -> Atom {
+        Atom { kind: *kind, body: AtomBody::Children { header: Vec::new(), children, trailer: Vec::new() } }
+    }
+
+    fn parse(kind: [u8; 4], body: &[u8]) -> Atom {
+        if CONTAINERS.contains(&&kind) {
+            // An ISO `meta` is a full box: 4 bytes of version/flags before its children
+            let header_len = if &kind == b"meta" && body.len() >= 8 && body[..4] == [0; 4] && body[4..8] != [0; 4] { 4 } else { 0 };
+            if let Some((children, trailer)) = parse_children(&body[header_len..]) {
+                return Atom { kind, body: AtomBody::Children { header: body[..header_len].to_vec(), children, trailer } };
+            }
+        }
+        Atom { kind, body: AtomBody::Data(body.to_vec()) }
+    }
+
+    fn children_mut(&mut self) -> Option<&mut Vec<Atom>> {
+        match &mut self.body {
+            AtomBody::Children { children, .. } => Some(children),
+            AtomBody::Data(_) => None,
+        }
+    }
+
+    fn child_mut(&mut self, kind: &[u8; 4]) -> Option<&mut Atom> {
+        self.children_mut()?.iter_mut().find(|a| &a.kind == kind)
+    }
+
+    fn data_mut(&mut self) -> Option<&mut Vec<u8>> {
+        match &mut self.body {
+            AtomBody::Data(data) => Some(data),
+            AtomBody::Children { .. } => None,
+        }
+    }
+
+    /// True when an atom that should hold children was kept as raw data, e.g.
+    /// because a child has a 64-bit size, so tables inside it cannot be found.
+    fn has_unparsed_container(&self) -> bool {
+        match &self.body {
+            AtomBody::Data(_) => CONTAINERS.contains(&&self.kind),
+            AtomBody::Children { children, .. } => children.iter().any(Atom::has_unparsed_container),
+        }
+    }
+
+    /// Every atom of `kind` below this one, depth first.
+    fn find_all_mut<'a>(&'a mut self, kind: &[u8; 4], found: &mut Vec<&'a mut Atom>) {
+        if &self.kind == kind {
+            found.push(self);
+            return;
+        }
+        if let AtomBody::Children { children, .. } = &mut self.body {
+            for child in children {
+                child.find_all_mut(kind, found);
+            }
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        let start = out.len();
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&self.kind);
+        match &self.body {
+            AtomBody::Data(data) => out.extend_from_slice(data),
+            AtomBody::Children { header, children, trailer } => {
+                out.extend_from_slice(header);
+                for child in children {
+                    child.encode(out);
+                }
+                out.extend_from_slice(trailer);
+            }
+        }
+        let size = (out.len() - start) as u32;
+        out[start..start + 4].copy_from_slice(&size.to_be_bytes());
+    }
+}
+
+fn parse_children(mut data: &[u8]) -> Option<(Vec<Atom>, Vec<u8>)> {
+    let mut children = Vec::new();
+    while data.len() >= 8 {
+        let size = u32::from_be_bytes(data[..4].try_into().ok()?) as usize;
+        if size < 8 || size > data.len() {
+            if data.iter().all(|b| *b == 0) {
+                break;
+            }
+            return None;
+        }
+        let kind: [u8; 4] = data[4..8].try_into().ok()?;
+        children.push(Atom::parse(kind, &data[8..size]));
+        data = &data[size..];
+    }
+    Some((children, data.to_vec()))
+}
+
+/// Position and length of each top-level atom, without reading their bodies.
+fn top_level_atoms(file: &mut File) -> io::Result<Vec<([u8; 4], u64, u64)>> {
+    let file_len = file.metadata()?.len();
+    let mut atoms = Vec::new();
+    let mut pos = 0;
+    while pos + 8 <= file_len {
+        file.seek(SeekFrom::Start(pos))?;
+        let mut header = [0u8; 8];
+        file.read_exact(&mut header)?;
+        let kind: [u8; 4] = header[4..8].try_into().unwrap_or_default();
+        let len = match u32::from_be_bytes(header[..4].try_into().unwrap_or_default()) {
+            0 => file_len - pos,
+            1 => {
+                let mut large = [0u8; 8];
+                file.read_exact(&mut large)?;
+                u64::from_be_bytes(large)
+            }
+            size => size as u64,
+        };
+        if len < 8 || pos + len > file_len {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "truncated or corrupt atom"));
+        }
+        atoms.push((kind, pos, len));
+        pos += len;
+    }
+    Ok(atoms)
+}
+
+fn read_moov(file: &mut File) -> Result<(Atom, u64, u64), String> {
+    let atoms = top_level_atoms(file).map_err(|e| e.to_string())?;
+    let (_, start, len) = atoms.into_iter().find(|(kind, _, _)| kind == b"moov").ok_or("no moov atom")?;
+    if len > 256 * 1024 * 1024 {
+        return Err("moov atom too large".to_string());
+    }
+    let mut moov = vec![0u8; len as usize];
+    file.seek(SeekFrom::Start(start)).map_err(|e| e.to_string())?;
+    file.read_exact(&mut moov).map_err(|e| e.to_string())?;
+    let header_len = if u32::from_be_bytes(moov[..4].try_into().unwrap_or_default()) == 1 { 16 } else { 8 };
+    let moov = Atom::parse(*b"moov", &moov[header_len..]);
+    if let AtomBody::Data(_) = moov.body {
+        return Err("unreadable moov atom".to_string());
+    }
+    Ok((moov, start, len))
+}
+
+/// Writes `tags` into the movie at `path`: the `mvhd`/`tkhd`/`mdhd` creation
+/// times, `udta/©xyz` GPS, and Apple `keys` entries for date, location, make,
+/// model, title and description. The file is rewritten next to the original and renamed over it.
+pub fn write_movie_tags(path: &Path, tags: &MovieTags) -> Result<(), String> {
+    let mut file = File::open(path).map_err(|e| e.to_string())?;
+    let (mut moov, moov_start, old_len) = read_moov(&mut file)?;
+    let fragmented = top_level_atoms(&mut file).map_err(|e| e.to_string())?
+        .iter()
+        .any(|(kind, _, _)| FRAGMENT_ATOMS.contains(&kind));
+
+    if let Some(date) = &tags.date {
+        let offset = tags.offset.unwrap_or(FixedOffset::east_opt(0).expect("zero is a valid offset"));
+        let local = NaiveDateTime::parse_from_str(date, "%Y:%m:%d %H:%M:%S")
+            .map_err(|e| format!("invalid date {:?}: {}", date, e))?
+            .and_local_timezone(offset)
+            .single()
+            .ok_or_else(|| format!("invalid date {:?}", date))?;
+        // Header times are UTC; the Apple key keeps the local time with its offset
+        let seconds = local.timestamp() + QUICKTIME_EPOCH_OFFSET;
+        for kind in [b"mvhd", b"tkhd", b"mdhd"] {
+            let mut found = Vec::new();
+            moov.find_all_mut(kind, &mut found);
+            for atom in found {
+                if let Some(data) = atom.data_mut() {
+                    set_header_times(data, seconds);
+                }
+            }
+        }
+        set_key(&mut moov, KEY_CREATION_DATE, &local.format("%Y-%m-%dT%H:%M:%S%z").to_string());
+    }
+    if let Some((lat, lon, alt)) = tags.gps {
+        let iso6709 = match alt {
+            Some(alt) => format!("{:+08.4}{:+09.4}{:+08.3}/", lat, lon, alt),
+            None => format!("{:+08.4}{:+09.4}/", lat, lon),
+        };
+        set_user_data(&mut moov, b"\xa9xyz", &iso6709);
+        set_key(&mut moov, KEY_LOCATION, &iso6709);
+    }
+    if let Some(make) = &tags.make {
+        set_key(&mut moov, KEY_MAKE, make);
+    }
+    if let Some(model) = &tags.model {
+        set_key(&mut moov, KEY_MODEL, model);
+    }
+    if let Some(title) = &tags.title {
+        set_key(&mut moov, KEY_TITLE, title);
+    }
+    if let Some(description) = &tags.description {
+        set_key(&mut moov, KEY_DESCRIPTION, description);
+    }
+
+    // Media data after a grown or shrunk moov moves, so its chunk offsets move too
+    let mut encoded = Vec::new();
+    moov.encode(&mut encoded);
+    let delta = encoded.len() as i64 - old_len as i64;
+    if delta != 0 {
+        shift_chunk_offsets(&mut moov, moov_start + old_len, delta, fragmented)?;
+        encoded.clear();
+        moov.encode(&mut encoded);
+    }
+
+    let tmp_path = path.with_file_name(format!(
+        ".{}.metasort-tmp",
+        path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default()
+    ));
+    let result = (|| -> io::Result<()> {
+        let mut out = io::BufWriter::new(File::create(&tmp_path)?);
+        file.seek(SeekFrom::Start(0))?;
+        io::copy(&mut (&mut file).take(moov_start), &mut out)?;
+        out.write_all(&encoded)?;
+        file.seek(SeekFrom::Start(moov_start + old_len))?;
+        io::copy(&mut file, &mut out)?;
+        out.into_inner().map_err(|e| e.into_error())?.sync_all()
+    })();
+    drop(file);
+    let result = result.and_then(|_| std::fs::rename(&tmp_path, path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+    result.map_err(|e| e.to_string())
+}
+
+/// Creation and modification time of a `mvhd`, `tkhd` or `mdhd` body.
+fn set_header_times(data: &mut [u8], seconds: i64) {
+    match data.first() {
+        Some(0) if data.len() >= 12 => {
+            let value = (seconds.clamp(0, u32::MAX as i64) as u32).to_be_bytes();
+            data[4..8].copy_from_slice(&value);
+            data[8..12].copy_from_slice(&value);
+        }
+        Some(1) if data.len() >= 20 => {
+            let value = (seconds.max(0) as u64).to_be_bytes();
+            data[4..12].copy_from_slice(&value);
+            data[12..20].copy_from_slice(&value);
+        }
+        _ => {}
+    }
+}
+
+/// Moves the `stco`/`co64` chunk offsets at or after `moved_from` by `delta`.
+/// Fails when part of `moov` could not be parsed, as a chunk table in it would
+/// be left pointing at the wrong data, and for a `fragmented` movie, whose
+/// `moof`, `sidx` and `mfra` atoms hold offsets of their own; the caller falls
+/// back to exiftool.
+fn shift_chunk_offsets(moov: &mut Atom, moved_from: u64, delta: i64, fragmented: bool) -> Result<(), String> {
+    if moov.has_unparsed_container() {
+        return Err("cannot move the media data: part of the moov atom could not be read".to_string());
+    }
+    if fragmented || moov.child_mut(b"mvex").is_some() {
+        return Err("cannot move the media data of a fragmented movie".to_string());
+    }
+    let mut tables = Vec::new();
+    moov.find_all_mut(b"stco", &mut tables);
+    for atom in tables {
+        let Some(data) = atom.data_mut() else { continue };
+        for entry in data.get_mut(8..).unwrap_or_default().chunks_exact_mut(4) {
+            let offset = u32::from_be_bytes(entry.try_into().unwrap_or_default()) as u64;
+            if offset >= moved_from {
+                let shifted = u32::try_from(offset as i64 + delta).map_err(|_| "chunk offset out of 32-bit range")?;
+                entry.copy_from_slice(&shifted.to_be_bytes());
+            }
+        }
+    }
+    let mut tables = Vec::new();
+    moov.find_all_mut(b"co64", &mut tables);
+    for atom in tables {
+        let Some(data) = atom.data_mut() else { continue };
+        for entry in data.get_mut(8..).unwrap_or_default().chunks_exact_mut(8) {
+            let offset = u64::from_be_bytes(entry.try_into().unwrap_or_default());
+            if offset >= moved_from {
+                entry.copy_from_slice(&((offset as i64 + delta) as u64).to_be_bytes());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Replaces or adds a QuickTime user data text item such as `©xyz`.
+fn set_user_data(moov: &mut Atom, kind: &[u8; 4], text: &str) {
+    let mut body = Vec::new();
+    body.extend_from_slice(&(text.len() as u16).to_be_bytes());
+    // Language code for "undetermined"
+    body.extend_from_slice(&0x55c4u16.to_be_bytes());
+    body.extend_from_slice(text.as_bytes());
+    let Some(children) = moov.children_mut() else { return };
+    if !children.iter().any(|a| &a.kind == b"udta") {
+        children.push(Atom::container(b"udta", Vec::new()));
+    }
+    let Some(udta) = moov.child_mut(b"udta").and_then(Atom::children_mut) else { return };
+    udta.retain(|a| &a.kind != kind);
+    udta.push(Atom::new(kind, body));
+}
+
+/// Replaces or adds an Apple metadata item in `moov/meta` (`keys` + `ilst`).
+/// A `meta` with another handler (e.g. iTunes tags) is left alone.
+fn set_key(moov: &mut Atom, key: &str, value: &str) {
+    let Some(children) = moov.children_mut() else { return };
+    if !children.iter().any(|a| &a.kind == b"meta") {
+        let mut hdlr = vec![0u8; 8];
+        hdlr.extend_from_slice(b"mdta");
+        hdlr.extend_from_slice(&[0; 13]);
+        children.push(Atom::container(b"meta", vec![
+            Atom::new(b"hdlr", hdlr),
+            Atom::new(b"keys", vec![0; 8]),
+            Atom::container(b"ilst", Vec::new()),
+        ]));
+    }
+    let Some(meta) = moov.child_mut(b"meta") else { return };
+    let is_mdta = match meta.child_mut(b"hdlr").and_then(|h| h.data_mut()) {
+        Some(hdlr) => hdlr.get(8..12) == Some(&b"mdta"[..]),
+        None => false,
+    };
+    if !is_mdta {
+        return;
+    }
+    let Some(meta_children) = meta.children_mut() else { return };
+    if !meta_children.iter().any(|a| &a.kind == b"keys") {
+        meta_children.push(Atom::new(b"keys", vec![0; 8]));
+    }
+    if !meta_children.iter().any(|a| &a.kind == b"ilst") {
+        meta_children.push(Atom::container(b"ilst", Vec::new()));
+    }
+
+    let Some(keys) = meta.child_mut(b"keys").and_then(Atom::data_mut) else { return };
+    let names = key_names(keys);
+    let index = match names.iter().position(|name| name == key) {
+        Some(i) => i as u32 + 1,
+        None => {
+            keys.extend_from_slice(&(8 + key.len() as u32).to_be_bytes());
+            keys.extend_from_slice(b"mdta");
+            keys.extend_from_slice(key.as_bytes());
+            let count = names.len() as u32 + 1;
+            keys[4..8].copy_from_slice(&count.to_be_bytes());
+            count
+        }
+    };
+    let mut data = Vec::new();
+    // Well-known type 1 (UTF-8), default locale
+    data.extend_from_slice(&1u32.to_be_bytes());
+    data.extend_from_slice(&0u32.to_be_bytes());
+    data.extend_from_slice(value.as_bytes());
+    let item = Atom::container(&index.to_be_bytes(), vec![Atom::new(b"data", data)]);
+    let Some(ilst) = meta.child_mut(b"ilst") else { return };
+    // Items are keyed by index, not by a known atom type, so they were parsed as raw data
+    if let AtomBody::Data(raw) = &ilst.body {
+        match parse_children(raw) {
+            Some((items, _)) => ilst.body = AtomBody::Children { header: Vec::new(), children: items, trailer: Vec::new() },
+            None => return,
+        }
+    }
+    if let Some(items) = ilst.children_mut() {
+        items.retain(|a| a.kind != index.to_be_bytes());
+        items.push(item);
+    }
+}
+
+fn key_names(keys: &[u8]) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut data = keys.get(8..).unwrap_or_default();
+    while data.len() >= 8 {
+        let size = u32::from_be_bytes(data[..4].try_into().unwrap_or_default()) as usize;
+        if size < 8 || size > data.len() {
+            break;
+        }
+        names.push(String::from_utf8_lossy(&data[8..size]).to_string());
+        data = &data[size..];
+    }
+    names
+}
+
+/// Reads an Apple `keys` text item, e.g. [`KEY_CREATION_DATE`].
+pub fn read_key(path: &Path, key: &str) -> Option<String> {
+    let mut file = File::open(path).ok()?;
+    let (mut moov, _, _) = read_moov(&mut file).ok()?;
+    let meta = moov.child_mut(b"meta")?;
+    let index = key_names(meta.child_mut(b"keys")?.data_mut()?).iter().position(|name| name == key)? as u32 + 1;
+    // ilst items and their data atoms are not parsed up front
+    let (items, _) = parse_children(meta.child_mut(b"ilst")?.data_mut()?)?;
+    let mut item = items.into_iter().find(|a| a.kind == index.to_be_bytes())?;
+    let (parts, _) = parse_children(item.data_mut()?)?;
+    let mut data = parts.into_iter().find(|a| &a.kind == b"data")?;
+    Some(String::from_utf8_lossy(data.data_mut()?.get(8..)?).to_string())
+}
+
+/// Creation date written in the Apple `keys` (by a phone or by MetaSort),
+/// in EXIF format and in the local time it was written with.
+pub fn read_creation_date(path: &Path) -> Option<String> {
+    Some(read_creation_time(path)?.format("%Y:%m:%d %H:%M:%S").to_string())
+}
+
+/// UTC offset of the Apple creation date key.
+pub fn read_creation_offset(path: &Path) -> Option<FixedOffset> {
+    Some(*read_creation_time(path)?.offset())
+}
+
+fn read_creation_time(path: &Path) -> Option<DateTime<FixedOffset>> {
+    let value = read_key(path, KEY_CREATION_DATE)?;
+    DateTime::parse_from_str(&value, "%Y-%m-%dT%H:%M:%S%z").ok()
+}
+
+/// Width and height of the first track with a picture, from its `tkhd`.
+pub fn read_dimensions(path: &Path) -> Option<(u32, u32)> {
+    let mut file = File::open(path).ok()?;
+    let (mut moov, _, _) = read_moov(&mut file).ok()?;
+    let mut headers = Vec::new();
+    moov.find_all_mut(b"tkhd", &mut headers);
+    headers.into_iter().find_map(|tkhd| {
+        let data = tkhd.data_mut()?;
+        // 16.16 fixed-point width and height close the header
+        let at = if data.first() == Some(&1) { 88 } else { 76 };
+        let width = u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?) >> 16;
+        let height = u32::from_be_bytes(data.get(at + 4..at + 8)?.try_into().ok()?) >> 16;
+        (width > 0 && height > 0).then_some((width, height))
+    })
+}
+
+/// GPS position from the Apple location key, or else from `udta/©xyz`.
+pub fn read_location(path: &Path) -> Option<(f64, f64)> {
+    let value = read_key(path, KEY_LOCATION).or_else(|| {
+        let mut file = File::open(path).ok()?;
+        let (mut moov, _, _) = read_moov(&mut file).ok()?;
+        let data = moov.child_mut(b"udta")?.child_mut(b"\xa9xyz")?.data_mut()?;
+        // 2 bytes of length and 2 of language before the text
+        Some(String::from_utf8_lossy(data.get(4..)?).to_string())
+    })?;
+    parse_iso6709(&value)
+}
+
+/// Latitude and longitude of an ISO 6709 string such as `+48.8577+002.2950+030.000/`.
+fn parse_iso6709(value: &str) -> Option<(f64, f64)> {
+    let lon_start = value.get(1..)?.find(['+', '-'])? + 1;
+    let latitude = value[..lon_start].parse().ok()?;
+    let rest = &value[lon_start..];
+    let lon_end = rest.get(1..)?.find(['+', '-', '/']).map_or(rest.len(), |i| i + 1);
+    Some((latitude, rest[..lon_end].parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAYLOAD: &[u8] = b"CHUNK-DATA";
+
+    fn atom(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        [&(8 + body.len() as u32).to_be_bytes()[..], kind, body].concat()
+    }
+
+    /// A movie with one track whose single chunk is `PAYLOAD`, with its chunk
+    /// table as `stco` or `co64` and the `moov` before or after the `mdat`.
+    fn movie(co64: bool, moov_first: bool, extra_in_trak: &[u8]) -> Vec<u8> {
+        let moov = |chunk: u64| {
+            let table = if co64 {
+                atom(b"co64", &[&[0; 4][..], &1u32.to_be_bytes(), &chunk.to_be_bytes()].concat())
+            } else {
+                atom(b"stco", &[&[0; 4][..], &1u32.to_be_bytes(), &(chunk as u32).to_be_bytes()].concat())
+            };
+            let stbl = atom(b"stbl", &table);
+            let mdia = atom(b"mdia", &[atom(b"mdhd", &[0; 24]), atom(b"minf", &stbl)].concat());
+            let trak = atom(b"trak", &[atom(b"tkhd", &[0; 84]), mdia, extra_in_trak.to_vec()].concat());
+            atom(b"moov", &[atom(b"mvhd", &[0; 100]), trak].concat())
+        };
+        let ftyp = atom(b"ftyp", b"qt  \0\0\0\0");
+        let mdat = atom(b"mdat", PAYLOAD);
+        let moov_len = moov(0).len() as u64;
+        if moov_first {
+            let chunk = (ftyp.len() as u64) + moov_len + 8;
+            [ftyp, moov(chunk), mdat].concat()
+        } else {
+            let chunk = ftyp.len() as u64 + 8;
+            [ftyp.clone(), mdat, moov(chunk)].concat()
+        }
+    }
+
+    fn find<'a>(atom: &'a Atom, kind: &[u8; 4]) -> Option<&'a Atom> {
+        if &atom.kind == kind {
+            return Some(atom);
+        }
+        match &atom.body {
+            AtomBody::Children { children, .. } => children.iter().find_map(|child| find(child, kind)),
+            AtomBody::Data(_) => None,
+        }
+    }
+
+    /// The offset in the chunk table of the movie at `path`.
+    fn chunk_offset(path: &Path) -> u64 {
+        let (moov, _, _) = read_moov(&mut File::open(path).unwrap()).unwrap();
+        match (find(&moov, b"stco"), find(&moov, b"co64")) {
+            (Some(Atom { body: AtomBody::Data(data), .. }), _) => u32::from_be_bytes(data[8..12].try_into().unwrap()) as u64,
+            (_, Some(Atom { body: AtomBody::Data(data), .. })) => u64::from_be_bytes(data[8..16].try_into().unwrap()),
+            _ => panic!("no chunk table"),
+        }
+    }
+
+    fn tags() -> MovieTags {
+        MovieTags {
+            date: Some("2020:05:31 17:00:00".to_string()),
+            offset: FixedOffset::west_opt(7 * 3600),
+            gps: Some((34.0522, -118.2437, Some(71.0))),
+            make: Some("Apple".to_string()),
+            model: Some("iPhone 11".to_string()),
+            title: Some("Beach".to_string()),
+            description: Some("A day at the beach".to_string()),
+        }
+    }
+
+    fn write_and_check(co64: bool, moov_first: bool) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clip.mov");
+        std::fs::write(&path, movie(co64, moov_first, &[])).unwrap();
+        write_movie_tags(&path, &tags()).unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        let offset = chunk_offset(&path) as usize;
+        assert_eq!(&bytes[offset..offset + PAYLOAD.len()], PAYLOAD);
+        assert_eq!(read_creation_date(&path).as_deref(), Some("2020:05:31 17:00:00"));
+        assert_eq!(read_creation_offset(&path), FixedOffset::west_opt(7 * 3600));
+        let (lat, lon) = read_location(&path).unwrap();
+        assert!((lat - 34.0522).abs() < 1e-4 && (lon + 118.2437).abs() < 1e-4);
+        assert_eq!(read_key(&path, KEY_MODEL).as_deref(), Some("iPhone 11"));
+        assert_eq!(read_key(&path, KEY_DESCRIPTION).as_deref(), Some("A day at the beach"));
+
+        // The header times are UTC: 2020-06-01 00:00:00
+        let mut file = File::open(&path).unwrap();
+        let (mut moov, _, _) = read_moov(&mut file).unwrap();
+        let mvhd = moov.child_mut(b"mvhd").and_then(Atom::data_mut).unwrap();
+        let seconds = u32::from_be_bytes(mvhd[4..8].try_into().unwrap()) as i64;
+        assert_eq!(seconds - QUICKTIME_EPOCH_OFFSET, 1_590_969_600);
+    }
+
+    #[test]
+    fn chunk_offsets_follow_media_data_after_the_moov() {
+        write_and_check(false, true);
+    }
+
+    #[test]
+    fn co64_chunk_offsets_follow_media_data_after_the_moov() {
+        write_and_check(true, true);
+    }
+
+    #[test]
+    fn chunk_offsets_before_the_moov_stay() {
+        write_and_check(false, false);
+        write_and_check(true, false);
+    }
+
+    #[test]
+    fn rewriting_tags_keeps_one_key_each() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clip.mp4");
+        std::fs::write(&path, movie(false, true, &[])).unwrap();
+        write_movie_tags(&path, &tags()).unwrap();
+        write_movie_tags(&path, &MovieTags { model: Some("Pixel 5".to_string()), ..tags() }).unwrap();
+        assert_eq!(read_key(&path, KEY_MODEL).as_deref(), Some("Pixel 5"));
+        let mut file = File::open(&path).unwrap();
+        let (mut moov, _, _) = read_moov(&mut file).unwrap();
+        let keys = moov.child_mut(b"meta").and_then(|meta| meta.child_mut(b"keys")).and_then(Atom::data_mut).unwrap();
+        assert_eq!(key_names(keys).len(), 6);
+        let offset = chunk_offset(&path) as usize;
+        assert_eq!(&std::fs::read(&path).unwrap()[offset..offset + PAYLOAD.len()], PAYLOAD);
+    }
+
+    #[test]
+    fn unparsed_containers_are_refused_when_media_data_moves() {
+        // A child with a 64-bit size keeps the trak from being parsed
+        let large = [&1u32.to_be_bytes()[..], b"free", &16u64.to_be_bytes()].concat();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clip.mov");
+        let original = movie(false, true, &large);
+        std::fs::write(&path, &original).unwrap();
+        assert!(write_movie_tags(&path, &tags()).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), original);
+    }
+
+    #[test]
+    fn fragmented_movies_are_refused_when_media_data_moves() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clip.mp4");
+        for fragment in [atom(b"moof", &atom(b"mfhd", &[0; 8])), atom(b"sidx", &[0; 24])] {
+            let original = [movie(false, true, &[]), fragment].concat();
+            std::fs::write(&path, &original).unwrap();
+            let error = write_movie_tags(&path, &tags()).unwrap_err();
+            assert!(error.contains("fragmented"), "{}", error);
+            assert_eq!(std::fs::read(&path).unwrap(), original);
+        }
+    }
+
+    #[test]
+    fn iso6709_positions_are_parsed() {
+        assert_eq!(parse_iso6709("+48.8577+002.2950+030.000/"), Some((48.8577, 2.295)));
+        assert_eq!(parse_iso6709("-33.8688+151.2093/"), Some((-33.8688, 151.2093)));
+        assert_eq!(parse_iso6709("nonsense"), None);
+    }
+}
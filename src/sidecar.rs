@@ -0,0 +1,295 @@
+// sidecar.rs
+// JSON sidecar resolution for MetaSort_v1.0.0 – Google Photos Takeout Organizer
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use regex::Regex;
+use crate::albums::ALBUM_METADATA_NAME;
+use crate::edited;
+use crate::utils::log_to_file;
+
+/// Google cuts sidecar names to this many bytes before `.json`.
+const TRUNCATED_LEN: usize = 46;
+/// Inserted before `.json` by newer exports, and cut short like the rest of the name.
+const SUPPLEMENTAL: &str = ".supplemental-metadata";
+/// JSON files in a Takeout that are not sidecars of a media file.
+const NOT_SIDECARS: [&str; 4] = [ALBUM_METADATA_NAME, "print-subscriptions.json", "shared_album_comments.json", "user-generated-memory-titles.json"];
+
+/// `name(N)`: the duplicate numbering Google puts at the very end of a name,
+/// so `IMG(1).jpg` has the sidecar `IMG.jpg(1).json`.
+static NUMBERED: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^(.+)\((\d+)\)$").unwrap());
+
+/// How each JSON file in a Takeout relates to the media files next to it.
+#[derive(Debug, Clone, Default)]
+pub struct SidecarMatches {
+    /// `(json, media)` for every JSON matched to a media file. An edited copy
+    /// whose original is missing is matched through the original's name.
+    pub matched: Vec<(PathBuf, PathBuf)>,
+    /// `(current, canonical)` renames giving matched JSON the `<media name>.json`
+    /// name pairing looks for.
+    pub renames: Vec<(PathBuf, PathBuf)>,
+    /// JSON that fit several media files equally well, or a media file that
+    /// already has a better JSON, with those media files. They are not renamed.
+    pub ambiguous: Vec<(PathBuf, Vec<PathBuf>)>,
+    /// JSON that fit no media file.
+    pub orphans: Vec<PathBuf>,
+}
+
+/// A name a media file can be looked up by.
+struct MediaKey<'a> {
+    name: String,
+    number: Option<u32>,
+    media: &'a Path,
+    /// Sidecar name the JSON gets when it is matched through this key.
+    canonical: String,
+    /// The key is the name of an edited copy's original.
+    edited: bool,
+}
+
+/// Matches every JSON in `files` to a media file in the same folder, covering
+/// Google's naming schemes: `IMG.jpg.json`, `IMG.json`, names cut at 46
+/// bytes, `.supplemental-metadata.json` (also cut short), `IMG.jpg(1).json`
+/// for `IMG(1).jpg`, and edited copies sharing their original's JSON.
+pub fn resolve_sidecars(files: &[PathBuf]) -> SidecarMatches {
+    let mut folders: BTreeMap<&Path, (Vec<&Path>, Vec<&Path>)> = BTreeMap::new();
+    for path in files {
+        let (media, jsons) = folders.entry(path.parent().unwrap_or(Path::new(""))).or_default();
+        let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+        if is_json(path) {
+            if !NOT_SIDECARS.contains(&name) {
+                jsons.push(path);
+            }
+        } else {
+            media.push(path);
+        }
+    }
+    let mut matches = SidecarMatches::default();
+    for (folder, (media, jsons)) in folders {
+        resolve_folder(folder, &media, &jsons, &mut matches);
+    }
+    matches
+}
+
+fn resolve_folder(folder: &Path, media: &[&Path], jsons: &[&Path], matches: &mut SidecarMatches) {
+    let keys: Vec<MediaKey> = media.iter().flat_map(|path| media_keys(path)).collect();
+    let mut exact: HashMap<(&str, Option<u32>), Vec<usize>> = HashMap::new();
+    let mut stems: HashMap<(&str, Option<u32>), Vec<usize>> = HashMap::new();
+    for (i, key) in keys.iter().enumerate() {
+        exact.entry((key.name.as_str(), key.number)).or_default().push(i);
+        if let Some(stem) = Path::new(&key.name).file_stem().and_then(|s| s.to_str()) {
+            stems.entry((stem, key.number)).or_default().push(i);
+        }
+    }
+
+    // The best key of every JSON, claimed under the sidecar name it would get
+    let mut claims: BTreeMap<&str, Vec<(u8, &Path, &MediaKey)>> = BTreeMap::new();
+    for json in jsons {
+        let base = json.file_stem().and_then(|s| s.to_str()).unwrap_or_default();
+        let mut candidates: Vec<(u8, usize)> = Vec::new();
+        for (name, number) in json_keys(base) {
+            let lookup = |map: &HashMap<(&str, Option<u32>), Vec<usize>>, name: &str| map.get(&(name, number)).cloned().unwrap_or_default();
+            // IMG.jpg.json
+            candidates.extend(lookup(&exact, name).into_iter().map(|i| (0, i)));
+            // IMG.jpg.supplemental-metadata.json and other additions, but not
+            // `.supplemental-metadata(2)`: that `(2)` is the duplicate number
+            if number.is_some() || !NUMBERED.is_match(name) {
+                for (at, _) in name.match_indices('.') {
+                    candidates.extend(lookup(&exact, &name[..at]).into_iter().map(|i| (1, i)));
+                }
+            }
+            // IMG.json
+            candidates.extend(lookup(&stems, name).into_iter().map(|i| (2, i)));
+            // Cut short: a prefix of the media name plus `.supplemental-metadata`
+            if name.len() >= TRUNCATED_LEN {
+                candidates.extend(keys.iter().enumerate()
+                    .filter(|(_, key)| key.number == number && format!("{}{}", key.name, SUPPLEMENTAL).starts_with(name))
+                    .map(|(i, _)| (3, i)));
+            }
+        }
+        // The original itself beats its edited copies, then the closest name wins
+        let Some(&(best_rank, best)) = candidates.iter().min_by_key(|(rank, i)| (keys[*i].edited, *rank)) else {
+            matches.orphans.push(json.to_path_buf());
+            continue;
+        };
+        let best_key = &keys[best];
+        let mut tied: Vec<&MediaKey> = candidates.iter()
+            .filter(|(rank, i)| *rank == best_rank && keys[*i].edited == best_key.edited)
+            .map(|(_, i)| &keys[*i])
+            .collect();
+        tied.sort_by_key(|key| (key.canonical.as_str(), key.media));
+        tied.dedup_by_key(|key| key.canonical.as_str());
+        if tied.len() > 1 {
+            matches.ambiguous.push((json.to_path_buf(), tied.iter().map(|key| key.media.to_path_buf()).collect()));
+            continue;
+        }
+        claims.entry(best_key.canonical.as_str()).or_default().push((best_rank, json, best_key));
+    }
+
+    // A media file with several JSON keeps the closest one
+    for (canonical, mut claimants) in claims {
+        claimants.sort_by_key(|(rank, json, _)| (*rank, *json));
+        let (_, json, key) = claimants[0];
+        matches.matched.push((json.to_path_buf(), key.media.to_path_buf()));
+        if json.file_name().and_then(|n| n.to_str()) != Some(canonical) {
+            matches.renames.push((json.to_path_buf(), folder.join(canonical)));
+        }
+        for (_, json, key) in &claimants[1..] {
+            matches.ambiguous.push((json.to_path_buf(), vec![key.media.to_path_buf()]));
+        }
+    }
+}
+
+/// The names a media file's sidecar can be based on: its own name, its name
+/// without the `(N)` duplicate number, and for an edited copy the original's name.
+fn media_keys(path: &Path) -> Vec<MediaKey<'_>> {
+    let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+        return Vec::new();
+    };
+    let canonical = format!("{}.json", name);
+    let mut keys = vec![MediaKey { name: name.to_string(), number: None, media: path, canonical: canonical.clone(), edited: false }];
+    let (stem, ext) = match (path.file_stem().and_then(|s| s.to_str()), path.extension().and_then(|e| e.to_str())) {
+        (Some(stem), Some(ext)) => (stem, ext),
+        _ => return keys,
+    };
+    if let Some(caps) = NUMBERED.captures(stem) {
+        if let Ok(number) = caps[2].parse() {
+            keys.push(MediaKey { name: format!("{}.{}", &caps[1], ext), number: Some(number), media: path, canonical, edited: false });
+        }
+    }
+    if let Some(original) = edited::original_stem(path) {
+        let name = format!("{}.{}", original, ext);
+        keys.push(MediaKey { canonical: format!("{}.json", name), name, number: None, media: path, edited: true });
+    }
+    keys
+}
+
+/// The names a JSON file can stand for: its name without `.json`, and that
+/// name without a trailing `(N)`.
+fn json_keys(base: &str) -> Vec<(&str, Option<u32>)> {
+    let mut keys = vec![(base, None)];
+    if let Some(caps) = NUMBERED.captures(base) {
+        if let (Some(name), Ok(number)) = (caps.get(1), caps[2].parse()) {
+            keys.push((name.as_str(), Some(number)));
+        }
+    }
+    keys
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("json"))
+}
+
+/// Writes the ambiguous and orphaned JSON files to `sidecars.log`.
+pub fn log_report(matches: &SidecarMatches, logs_dir: &Path) {
+    for (json, media) in &matches.ambiguous {
+        log_to_file(logs_dir, "sidecars.log", &format!("Ambiguous JSON {:?}: fits {:?}; not renamed", json, media));
+    }
+    for json in &matches.orphans {
+        log_to_file(logs_dir, "sidecars.log", &format!("Orphaned JSON {:?}: no media file found", json));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(names: &[&str]) -> SidecarMatches {
+        let files: Vec<PathBuf> = names.iter().map(|name| Path::new("Trip").join(name)).collect();
+        resolve_sidecars(&files)
+    }
+
+    fn path(name: &str) -> PathBuf {
+        Path::new("Trip").join(name)
+    }
+
+    fn pair(json: &str, media: &str) -> (PathBuf, PathBuf) {
+        (path(json), path(media))
+    }
+
+    #[test]
+    fn plain_sidecars_are_matched_without_renaming() {
+        let matches = resolve(&["IMG_1.jpg", "IMG_1.jpg.json", "VID_2.mp4", "VID_2.json", "metadata.json"]);
+        assert_eq!(matches.matched, vec![pair("IMG_1.jpg.json", "IMG_1.jpg"), pair("VID_2.json", "VID_2.mp4")]);
+        assert_eq!(matches.renames, vec![pair("VID_2.json", "VID_2.mp4.json")]);
+        assert!(matches.ambiguous.is_empty() && matches.orphans.is_empty());
+    }
+
+    #[test]
+    fn names_cut_at_46_bytes_are_matched() {
+        let media = "Screenshot_20210102-153045_Google Photos app.jpg";
+        let json = format!("{}.json", &media[..TRUNCATED_LEN]);
+        let matches = resolve(&[media, &json]);
+        assert_eq!(matches.matched, vec![pair(&json, media)]);
+        assert_eq!(matches.renames, vec![pair(&json, &format!("{}.json", media))]);
+
+        // `.supplemental-metadata` is cut along with the name
+        let media = "PXL_20210102_153045123.PORTRAIT.jpg";
+        let json = format!("{}.json", &format!("{}{}", media, SUPPLEMENTAL)[..TRUNCATED_LEN]);
+        let matches = resolve(&[media, &json]);
+        assert_eq!(matches.matched, vec![pair(&json, media)]);
+        assert_eq!(matches.renames, vec![pair(&json, &format!("{}.json", media))]);
+    }
+
+    #[test]
+    fn supplemental_metadata_sidecars_are_matched() {
+        let matches = resolve(&["IMG_1.jpg", "IMG_1.jpg.supplemental-metadata.json", "IMG_2.jpg", "IMG_2.jpg.suppl.json"]);
+        assert_eq!(matches.matched, vec![
+            pair("IMG_1.jpg.supplemental-metadata.json", "IMG_1.jpg"),
+            pair("IMG_2.jpg.suppl.json", "IMG_2.jpg"),
+        ]);
+        assert_eq!(matches.renames.len(), 2);
+    }
+
+    #[test]
+    fn duplicate_numbers_move_to_the_end_of_the_sidecar_name() {
+        let matches = resolve(&[
+            "IMG.jpg", "IMG.jpg.json", "IMG(1).jpg", "IMG.jpg(1).json", "IMG(2).jpg", "IMG.jpg.supplemental-metadata(2).json",
+        ]);
+        assert_eq!(matches.matched, vec![
+            pair("IMG.jpg(1).json", "IMG(1).jpg"),
+            pair("IMG.jpg.supplemental-metadata(2).json", "IMG(2).jpg"),
+            pair("IMG.jpg.json", "IMG.jpg"),
+        ]);
+        assert_eq!(matches.renames, vec![
+            pair("IMG.jpg(1).json", "IMG(1).jpg.json"),
+            pair("IMG.jpg.supplemental-metadata(2).json", "IMG(2).jpg.json"),
+        ]);
+        // A number no media file has is not matched to the unnumbered one
+        let matches = resolve(&["IMG.jpg", "IMG.jpg(3).json"]);
+        assert_eq!(matches.orphans, vec![path("IMG.jpg(3).json")]);
+    }
+
+    #[test]
+    fn edited_copies_share_their_original_json() {
+        // The original takes the JSON...
+        let matches = resolve(&["IMG_1.jpg", "IMG_1-edited.jpg", "IMG_1.jpg.json"]);
+        assert_eq!(matches.matched, vec![pair("IMG_1.jpg.json", "IMG_1.jpg")]);
+        assert!(matches.ambiguous.is_empty());
+        // ...and without it, the edited copy is matched through the original's name
+        let matches = resolve(&["IMG_2-bearbeitet.jpg", "IMG_2.jpg.supplemental-metadata.json"]);
+        assert_eq!(matches.matched, vec![pair("IMG_2.jpg.supplemental-metadata.json", "IMG_2-bearbeitet.jpg")]);
+        assert_eq!(matches.renames, vec![pair("IMG_2.jpg.supplemental-metadata.json", "IMG_2.jpg.json")]);
+    }
+
+    #[test]
+    fn unclear_and_unmatched_json_are_reported() {
+        // IMG.json fits both files equally well
+        let matches = resolve(&["IMG.jpg", "IMG.png", "IMG.json", "lost.jpg.json"]);
+        assert_eq!(matches.ambiguous, vec![(path("IMG.json"), vec![path("IMG.jpg"), path("IMG.png")])]);
+        assert_eq!(matches.orphans, vec![path("lost.jpg.json")]);
+        assert!(matches.matched.is_empty() && matches.renames.is_empty());
+        // A media file with two JSON keeps the closer name
+        let matches = resolve(&["IMG.jpg", "IMG.jpg.json", "IMG.json"]);
+        assert_eq!(matches.matched, vec![pair("IMG.jpg.json", "IMG.jpg")]);
+        assert_eq!(matches.ambiguous, vec![(path("IMG.json"), vec![path("IMG.jpg")])]);
+    }
+
+    #[test]
+    fn sidecars_only_match_media_in_their_folder() {
+        let files = vec![PathBuf::from("A/IMG.jpg"), PathBuf::from("B/IMG.jpg.json")];
+        let matches = resolve_sidecars(&files);
+        assert!(matches.matched.is_empty());
+        assert_eq!(matches.orphans, vec![PathBuf::from("B/IMG.jpg.json")]);
+    }
+}
@@ -1,15 +1,87 @@
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::io;
 use std::fs;
-use chrono::Datelike;
+use crate::collision::{self, CollisionStrategy, Resolution, COLLISIONS_CSV};
 use crate::csv_report;
-use crate::utils::log_to_file;
+use crate::error;
+use crate::utils::{for_each_ordered, human_readable_size, log_to_file};
 use std::io::Write;
-use serde_json;
-use crate::platform::get_exiftool_command;
+use crate::media_info::{read_media_info, read_time_offset, MediaInfo};
+use crate::journal::{Journal, Stage};
+use crate::layout::{Layout, LayoutValues, Renamer};
+use crate::locale::Locale;
+use crate::timezone::{self, Zone};
+use crate::transfer::{transfer_file, TransferMode};
+use serde::{Deserialize, Serialize};
 
-/// Main function to organize files into folders by type and date.
-pub fn sort_files_to_folders(input_dir: &Path, output_dir: &Path, failed_guess_paths: &Vec<PathBuf>, separate_wa_sc: bool) {
+/// One CSV report row: (FileName, Original FileName, Filetype, Original Time,
+/// Resolution, Human Size, Size). The original name is the one in the Takeout,
+/// before renaming.
+pub type FileInfo = (String, String, String, String, String, String, u64);
+
+/// Sort result of one file as stored in the journal.
+#[derive(Serialize, Deserialize)]
+struct SortedRecord {
+    category: String,
+    info: FileInfo,
+    #[serde(default)]
+    destination: PathBuf,
+    /// Identical to the file already at `destination`, so not moved or reported.
+    #[serde(default)]
+    skipped: bool,
+}
+
+/// Where the sort stage put each media file, by its path in the working folder.
+#[derive(Debug, Clone, Default)]
+pub struct Sorted {
+    pub destinations: HashMap<PathBuf, PathBuf>,
+    /// Category of each file, as returned by [`destination_folder`].
+    pub categories: HashMap<PathBuf, String>,
+}
+
+impl Sorted {
+    /// Number of files in the output sorted under one of `categories`.
+    pub fn count(&self, categories: &[&str]) -> usize {
+        let files: HashSet<&PathBuf> = self.destinations.iter()
+            .filter(|(file, _)| self.categories.get(*file).is_some_and(|category| categories.contains(&category.as_str())))
+            .map(|(_, destination)| destination)
+            .collect();
+        files.len()
+    }
+}
+
+/// Main function to organize files into folders by type and date. Files are
+/// moved out of `input_dir`, the working folder, so nothing is stored twice.
+/// Files sorted by an interrupted run are skipped but still reported.
+///
+/// Dated files are sorted into the folders of `layout`, and renamed if it has
+/// a file name template; `albums` gives the album of each file for its
+/// `{album}` field.
+///
+/// Files in `status_folders` (archived, trashed or locked in Google Photos)
+/// are sorted into that folder instead. The clips in `live_pairs` follow
+/// their photo into its folder.
+///
+/// A file whose name is taken in its folder is handled with `on_collision`;
+/// with [`CollisionStrategy::Fail`] sorting stops at that file. `timezone`
+/// places the JSON dates of files without a date of their own.
+///
+/// Returns where each media file was moved, by its path in `input_dir`.
+#[allow(clippy::too_many_arguments)]
+pub fn sort_files_to_folders(
+    input_dir: &Path,
+    output_dir: &Path,
+    layout: &Layout,
+    albums: &HashMap<PathBuf, String>,
+    failed_guess_paths: &[PathBuf],
+    status_folders: &HashMap<PathBuf, &'static str>,
+    live_pairs: &HashMap<PathBuf, PathBuf>,
+    separate_wa_sc: bool,
+    on_collision: CollisionStrategy,
+    timezone: Option<&Zone>,
+    journal: &mut Journal,
+) -> error::Result<Sorted> {
     let media_extensions = vec![
         // Images
         "jpg", "jpeg", "png", "webp", "heic", "heif", "bmp", "tiff", "gif", "avif", "jxl", "jfif",
@@ -24,142 +96,135 @@ pub fn sort_files_to_folders(input_dir: &Path, output_dir: &Path, failed_guess_p
     let mut unknown_info = Vec::new();
     let mut mkv_info = Vec::new();
     let mut failed_guess_info = Vec::new();
+    let mut push_info = |category: &str, info: FileInfo| match category {
+        "Videos" => videos_info.push(info),
+        "mkv_files" => mkv_info.push(info),
+        "Unknown Time" => unknown_info.push(info),
+        "Failed Filename Guess" => failed_guess_info.push(info),
+        _ => photos_info.push(info),
+    };
 
     let logs_dir = output_dir.join("Technical Files").join("logs");
+    let csv_report_folder = output_dir.join("Technical Files").join("CSV Report");
+    let collisions_csv = csv_report_folder.join(COLLISIONS_CSV);
 
-    let all_files: Vec<_> = walkdir::WalkDir::new(input_dir).into_iter().filter_map(Result::ok).filter(|e| e.path().is_file()).collect();
+    // Files an interrupted run already moved are only reported
+    let mut resumed = 0;
+    let mut destinations = HashMap::new();
+    let mut categories = HashMap::new();
+    for (file, data) in journal.entries(Stage::Sorting) {
+        if let Ok(record) = serde_json::from_value::<SortedRecord>(data.clone()) {
+            destinations.insert(PathBuf::from(file), record.destination);
+            categories.insert(PathBuf::from(file), record.category.clone());
+            if !record.skipped {
+                push_info(&record.category, record.info);
+            }
+            resumed += 1;
+        }
+    }
+
+    let all_files: Vec<_> = walkdir::WalkDir::new(input_dir).sort_by_file_name().into_iter().filter_map(Result::ok).filter(|e| e.path().is_file()).collect();
     // Only count media files for progress
-    let all_media_files: Vec<_> = all_files.iter().filter(|entry| {
-        let path = entry.path();
-        if path.is_file() {
-            let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
-            media_extensions.contains(&ext.as_str())
-        } else {
-            false
+    let all_media_files: Vec<PathBuf> = all_files.iter().map(|entry| entry.path()).filter(|path| {
+        let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+        media_extensions.contains(&ext.as_str())
+    }).map(Path::to_path_buf).collect();
+    let total = resumed + all_media_files.len();
+    // Live Photo clips go last, so their photo has been sorted when they are
+    let (clips, mut pending): (Vec<PathBuf>, Vec<PathBuf>) = all_media_files.into_iter()
+        .filter(|path| !journal.is_done(Stage::Sorting, path))
+        .partition(|path| live_pairs.contains_key(path));
+    pending.extend(clips);
+    let mut processed = total - pending.len();
+    // Reading each file's metadata runs in parallel. The move itself is a rename
+    // inside the output folder and happens in order, so two files with the same
+    // name always end up the same way.
+    let classify = |path: &PathBuf| {
+        let media = read_sort_info(path, timezone);
+        let file_size = path.metadata().map(|m| m.len()).unwrap_or(0);
+        let filename = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let is_failed_guess = failed_guess_paths.contains(path);
+        let status = status_folders.get(path).copied();
+        let album = albums.get(path).map(String::as_str);
+        let (dest_folder, category) = destination_folder(output_dir, layout, &filename, &media, album, is_failed_guess, status, separate_wa_sc);
+        let info = (
+            filename.clone(),
+            filename.clone(),
+            media.file_type.clone().unwrap_or_default(),
+            media.date_taken.clone().unwrap_or_default(),
+            media.image_size().unwrap_or_default(),
+            human_readable_size(file_size),
+            file_size,
+        );
+        (dest_folder, category, info, media, album)
+    };
+    let mut renamer = layout.renamer();
+    let mut failure = None;
+    for_each_ordered(&pending, classify, |path, (dest_folder, category, mut info, media, album)| {
+        if failure.is_some() {
+            return;
         }
-    }).collect();
-    let total = all_media_files.len();
-    let mut processed = 0;
-    for entry in all_media_files {
-        let path = entry.path();
-        if path.is_file() {
-            let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
-            // Use exiftool to get DateTimeOriginal, MIMEType, ImageSize
-            let output = get_exiftool_command()
-                .arg("-DateTimeOriginal")
-                .arg("-MIMEType")
-                .arg("-ImageSize")
-                .arg("-FileType")
-                .arg(path)
-                .output();
-            let mut date_str = String::new();
-            let mut mime_type = String::new();
-            let mut image_size = String::new();
-            let mut file_type = String::new();
-            if let Ok(out) = output {
-                let stdout = String::from_utf8_lossy(&out.stdout);
-                for line in stdout.lines() {
-                    if line.contains("Date/Time Original") {
-                        date_str = line.split(':').skip(1).collect::<Vec<_>>().join(":").trim().to_string();
-                    } else if line.contains("MIME Type") {
-                        mime_type = line.split(':').skip(1).collect::<Vec<_>>().join(":").trim().to_string();
-                    } else if line.contains("Image Size") {
-                        image_size = line.split(':').skip(1).collect::<Vec<_>>().join(":").trim().to_string();
-                    } else if line.contains("File Type") {
-                        file_type = line.split(':').skip(1).collect::<Vec<_>>().join(":").trim().to_string();
-                    }
-                }
+        let photo = live_pairs.get(path).and_then(|photo| Some((photo, destinations.get(photo)?, categories.get(photo)?)));
+        let (mut dest_path, category) = match photo {
+            Some((photo, photo_dest, photo_category)) => (clip_destination(path, photo, photo_dest), photo_category.clone()),
+            None => {
+                let dest_path = file_destination(renamer.as_mut(), &dest_folder, category, &media, album, &info.1, |path| path.exists());
+                (dest_path, category.to_string())
             }
-            // If date_str is still empty, try to extract from JSON
-            if date_str.is_empty() {
-                let json_path = path.with_extension(format!("{}json", ext));
-                if json_path.exists() {
-                    if let Ok(json_str) = std::fs::read_to_string(&json_path) {
-                        if let Ok(json_val) = serde_json::from_str::<serde_json::Value>(&json_str) {
-                            if let Some(ts) = json_val["photoTakenTime"]["timestamp"].as_str() {
-                                if let Ok(timestamp) = ts.parse::<i64>() {
-                                    use chrono::{TimeZone, Utc};
-                                    let dt = Utc.timestamp_opt(timestamp, 0).unwrap();
-                                    date_str = dt.format("%Y:%m:%d %H:%M:%S").to_string();
-                                }
-                            }
+        };
+        match collision::land(path, &dest_path, on_collision) {
+            Ok(None) => {}
+            Ok(Some(resolution)) => {
+                collision::record(path, &dest_path, &resolution, &logs_dir, "sorting.log", &collisions_csv);
+                match resolution {
+                    Resolution::Renamed(renamed) => dest_path = renamed,
+                    Resolution::Identical(identical) => {
+                        // Already in the library; albums and Live Photo clips point at that copy
+                        let _ = fs::remove_file(path);
+                        categories.insert(path.clone(), category.clone());
+                        if let Ok(data) = serde_json::to_value(SortedRecord { category, info, destination: identical.clone(), skipped: true }) {
+                            journal.record_with(Stage::Sorting, path, data);
                         }
+                        destinations.insert(path.clone(), identical);
+                        processed += 1;
+                        print_progress(processed, total);
+                        return;
                     }
                 }
             }
-            let file_size = path.metadata().map(|m| m.len()).unwrap_or(0);
-            let filename = path.file_name().unwrap().to_string_lossy().to_string();
-            let mut dest_folder = output_dir.join("Media Files");
-            // WhatsApp/Screenshot detection
-            let fname_lc = filename.to_lowercase();
-            let is_wa = fname_lc.contains("wa") || fname_lc.contains("whatsapp");
-            let is_sc = fname_lc.contains("screenshot");
-            if separate_wa_sc && is_wa {
-                dest_folder.push("Whatsapp");
-                if let Some(dt) = parse_exif_date(&date_str) {
-                    dest_folder.push(format!("{}", dt.year()));
-                    dest_folder.push(format!("{}", month_name(dt.month())));
-                }
-                photos_info.push((filename.clone(), file_type.clone(), date_str.clone(), image_size.clone(), human_readable_size(file_size), file_size));
-            } else if separate_wa_sc && is_sc {
-                dest_folder.push("Screenshots");
-                if let Some(dt) = parse_exif_date(&date_str) {
-                    dest_folder.push(format!("{}", dt.year()));
-                    dest_folder.push(format!("{}", month_name(dt.month())));
-                }
-                photos_info.push((filename.clone(), file_type.clone(), date_str.clone(), image_size.clone(), human_readable_size(file_size), file_size));
-            } else if ext == "mkv" {
-                let _file_category = "mkv_files".to_string();
-                dest_folder.push("mkv_files");
-                mkv_info.push((filename.clone(), file_type.clone(), date_str.clone(), image_size.clone(), human_readable_size(file_size), file_size));
-            } else if date_str.is_empty() {
-                if failed_guess_paths.contains(&path.to_path_buf()) {
-                    let _file_category = "Failed Filename Guess".to_string();
-                    dest_folder.push("Unknown Time");
-                    dest_folder.push("Failed Filename Guess");
-                    failed_guess_info.push((filename.clone(), file_type.clone(), date_str.clone(), image_size.clone(), human_readable_size(file_size), file_size));
-                } else {
-                    let _file_category = "Unknown Time".to_string();
-                    dest_folder.push("Unknown Time");
-                    unknown_info.push((filename.clone(), file_type.clone(), date_str.clone(), image_size.clone(), human_readable_size(file_size), file_size));
-                }
-            } else if mime_type.starts_with("video") || ["mp4","mov","avi","webm","3gp","m4v","mpg","mpeg","mts","m2ts","ts","flv","f4v","wmv","asf","rm","rmvb","vob","ogv","mxf","dv","divx","xvid"].contains(&ext.as_str()) {
-                // Video
-                let _file_category = "Videos".to_string();
-                dest_folder.push("Videos");
-                if let Some(dt) = parse_exif_date(&date_str) {
-                    dest_folder.push(format!("{}", dt.year()));
-                    dest_folder.push(format!("{}", month_name(dt.month())));
-                }
-                videos_info.push((filename.clone(), file_type.clone(), date_str.clone(), image_size.clone(), human_readable_size(file_size), file_size));
-            } else {
-                // Photo
-                let _file_category = "Photos".to_string();
-                dest_folder.push("Photos");
-                if let Some(dt) = parse_exif_date(&date_str) {
-                    dest_folder.push(format!("{}", dt.year()));
-                    dest_folder.push(format!("{}", month_name(dt.month())));
-                }
-                photos_info.push((filename.clone(), file_type.clone(), date_str.clone(), image_size.clone(), human_readable_size(file_size), file_size));
+            Err(e) => {
+                log_to_file(&logs_dir, "sorting.log", &format!("❌ {}", e));
+                failure = Some(e);
+                return;
             }
-            // Create destination folder if needed
-            let _ = fs::create_dir_all(&dest_folder);
-            let dest_path = dest_folder.join(&filename);
-            // Copy file
-            match fs::copy(path, &dest_path) {
-                Ok(_) => {
-                    log_to_file(&logs_dir, "sorting.log", &format!("Copied {:?} to {:?}", path.file_name().unwrap_or_default(), dest_path));
-                }
-                Err(e) => {
-                    log_to_file(&logs_dir, "sorting.log", &format!("Failed to copy {:?} to {:?}: {}", path.file_name().unwrap_or_default(), dest_path, e));
+        }
+        info.0 = dest_path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        categories.insert(path.clone(), category.clone());
+        push_info(&category, info.clone());
+        // Create destination folder if needed
+        if let Some(dest_folder) = dest_path.parent() {
+            let _ = fs::create_dir_all(dest_folder);
+        }
+        // Move file out of the working folder
+        match transfer_file(path, &dest_path, TransferMode::Move) {
+            Ok(_) => {
+                log_to_file(&logs_dir, "sorting.log", &format!("Moved {:?} to {:?}", path.file_name().unwrap_or_default(), dest_path));
+                if let Ok(data) = serde_json::to_value(SortedRecord { category, info, destination: dest_path.clone(), skipped: false }) {
+                    journal.record_with(Stage::Sorting, path, data);
                 }
+                destinations.insert(path.clone(), dest_path);
+            }
+            Err(e) => {
+                log_to_file(&logs_dir, "sorting.log", &format!("Failed to move {:?} to {:?}: {}", path.file_name().unwrap_or_default(), dest_path, e));
             }
-            processed += 1;
-            print_progress(processed, total);
         }
+        processed += 1;
+        print_progress(processed, total);
+    });
+    if let Some(e) = failure {
+        return Err(e);
     }
     // Write CSVs for each category in CSV Report folder
-    let csv_report_folder = output_dir.join("Technical Files").join("CSV Report");
     let _ = fs::create_dir_all(&csv_report_folder);
     csv_report::write_csv_report(&csv_report_folder, &photos_info, "photos.csv");
     csv_report::write_csv_report(&csv_report_folder, &videos_info, "videos.csv");
@@ -170,41 +235,158 @@ pub fn sort_files_to_folders(input_dir: &Path, output_dir: &Path, failed_guess_p
     println!("\n📦 Sorting complete! Sorted {} files.", processed);
     println!("\n📄 CSV files are added in: {}\nPlease keep this folder safe for future use!", csv_report_folder.display());
 
-    let failed_guess_folder = output_dir.join("Media Files").join("Unknown Time").join("Failed Filename Guess");
+    let locale = layout.locale();
+    let failed_guess_folder = output_dir.join(locale.folder("Media Files")).join(locale.folder("Unknown Time")).join(locale.folder("Failed Filename Guess"));
     let _ = fs::create_dir_all(&failed_guess_folder);
-    for path in failed_guess_paths {
+    for path in failed_guess_paths.iter().filter(|p| p.exists()) {
         if let Some(filename) = path.file_name() {
-            let dest = failed_guess_folder.join(filename);
+            let mut dest = failed_guess_folder.join(filename);
+            if let Some(resolution) = collision::land(path, &dest, on_collision)? {
+                collision::record(path, &dest, &resolution, &logs_dir, "sorting.log", &collisions_csv);
+                match resolution {
+                    Resolution::Renamed(renamed) => dest = renamed,
+                    Resolution::Identical(identical) => {
+                        let _ = fs::remove_file(path);
+                        categories.insert(path.clone(), "Failed Filename Guess".to_string());
+                        destinations.insert(path.clone(), identical);
+                        continue;
+                    }
+                }
+            }
             let _ = fs::rename(path, &dest);
             log_to_file(&logs_dir, "sorting.log", &format!("Moved failed guess file {:?} to {:?}", path, dest));
+            categories.insert(path.clone(), "Failed Filename Guess".to_string());
+            destinations.insert(path.clone(), dest);
         }
     }
+    Ok(Sorted { destinations, categories })
 }
 
-fn parse_exif_date(date_str: &str) -> Option<chrono::NaiveDateTime> {
-    chrono::NaiveDateTime::parse_from_str(date_str, "%Y:%m:%d %H:%M:%S").ok()
+/// Where a Live Photo clip goes: next to its photo, renamed along with it if
+/// the photo's name collided.
+pub fn clip_destination(clip: &Path, photo: &Path, photo_dest: &Path) -> PathBuf {
+    let name = clip.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let renamed = match (photo.file_stem(), photo_dest.file_stem(), clip.extension()) {
+        (Some(old), Some(new), Some(ext)) if old != new => Some(format!("{}.{}", new.to_string_lossy(), ext.to_string_lossy())),
+        _ => None,
+    };
+    photo_dest.with_file_name(renamed.unwrap_or(name))
 }
 
-fn month_name(month: u32) -> &'static str {
-    match month {
-        1 => "January",
-        2 => "February",
-        3 => "March",
-        4 => "April",
-        5 => "May",
-        6 => "June",
-        7 => "July",
-        8 => "August",
-        9 => "September",
-        10 => "October",
-        11 => "November",
-        12 => "December",
-        _ => "Unknown",
+/// Where a file in `folder` goes: renamed by `renamer` if it has a date and
+/// its category is placed by the layout, else under its own `filename`.
+/// `taken` tells which names are in use, for numbering with `{seq}`.
+pub fn file_destination(
+    renamer: Option<&mut Renamer>,
+    folder: &Path,
+    category: &str,
+    media: &MediaInfo,
+    album: Option<&str>,
+    filename: &str,
+    taken: impl Fn(&Path) -> bool,
+) -> PathBuf {
+    let values = layout_values(renamer.as_ref().map(|renamer| renamer.locale()).unwrap_or_default(), category, media, album);
+    match renamer {
+        Some(renamer) if values.date.is_some() && !FIXED_CATEGORIES.contains(&category) => renamer.path(folder, &values, filename, taken),
+        _ => folder.join(filename),
+    }
+}
+
+/// Categories with a folder of their own, whatever the layout.
+const FIXED_CATEGORIES: [&str; 3] = ["mkv_files", "Unknown Time", "Failed Filename Guess"];
+
+fn layout_values<'a>(locale: Locale, category: &'a str, media: &'a MediaInfo, album: Option<&'a str>) -> LayoutValues<'a> {
+    LayoutValues {
+        locale,
+        category,
+        date: media.date_taken.as_deref().and_then(parse_exif_date),
+        camera_make: media.camera_make.as_deref(),
+        camera_model: media.camera_model.as_deref(),
+        album,
+        country: media.country.as_deref(),
     }
 }
 
+/// Reads a file's metadata for sorting. Without a date in the file, the date
+/// from its Takeout JSON is used, in the time zone picked as in metadata
+/// extraction: the file's own offset, then its GPS position, then `timezone`
+/// (UTC without any).
+pub fn read_sort_info(path: &Path, timezone: Option<&Zone>) -> MediaInfo {
+    let mut info = read_media_info(path);
+    if info.date_taken.is_none() {
+        let json_path = path.with_file_name(format!("{}.json", path.file_name().unwrap_or_default().to_string_lossy()));
+        if json_path.exists() {
+            if let Ok(json_str) = std::fs::read_to_string(&json_path) {
+                if let Ok(json_val) = serde_json::from_str::<serde_json::Value>(&json_str) {
+                    if let Some(ts) = json_val["photoTakenTime"]["timestamp"].as_str() {
+                        if let Ok(timestamp) = ts.parse::<i64>() {
+                            let gps = json_val["geoData"]["latitude"].as_f64().zip(json_val["geoData"]["longitude"].as_f64());
+                            info.date_taken = timezone::local_time(timestamp, read_time_offset(path), gps, timezone).map(|local| local.date);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    info
+}
+
+/// Picks the folder a file is sorted into, returning it with its category:
+/// its `status` folder ("Trash", "Locked Folder" or "Archived") if it has one,
+/// else "Whatsapp", "Screenshots", "mkv_files", "Failed Filename Guess",
+/// "Unknown Time", "Videos" or "Photos". Files without a date and mkv files
+/// have fixed folders; the others are placed by `layout`.
+#[allow(clippy::too_many_arguments)]
+pub fn destination_folder(
+    output_dir: &Path,
+    layout: &Layout,
+    filename: &str,
+    media: &MediaInfo,
+    album: Option<&str>,
+    is_failed_guess: bool,
+    status: Option<&'static str>,
+    separate_wa_sc: bool,
+) -> (PathBuf, &'static str) {
+    let ext = Path::new(filename).extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    let locale = layout.locale();
+    let mut dest_folder = output_dir.join(locale.folder("Media Files"));
+    // WhatsApp/Screenshot detection
+    let fname_lc = filename.to_lowercase();
+    let is_wa = fname_lc.contains("wa") || fname_lc.contains("whatsapp");
+    let is_sc = fname_lc.contains("screenshot");
+    let category = if let Some(status) = status {
+        status
+    } else if separate_wa_sc && is_wa {
+        "Whatsapp"
+    } else if separate_wa_sc && is_sc {
+        "Screenshots"
+    } else if ext == "mkv" {
+        "mkv_files"
+    } else if media.date_taken.is_none() {
+        if is_failed_guess { "Failed Filename Guess" } else { "Unknown Time" }
+    } else if media.is_video() || ["mp4","mov","avi","webm","3gp","m4v","mpg","mpeg","mts","m2ts","ts","flv","f4v","wmv","asf","rm","rmvb","vob","ogv","mxf","dv","divx","xvid"].contains(&ext.as_str()) {
+        "Videos"
+    } else {
+        "Photos"
+    };
+    match category {
+        "mkv_files" => dest_folder.push("mkv_files"),
+        "Unknown Time" => dest_folder.push(locale.folder("Unknown Time")),
+        "Failed Filename Guess" => {
+            dest_folder.push(locale.folder("Unknown Time"));
+            dest_folder.push(locale.folder("Failed Filename Guess"));
+        }
+        _ => dest_folder = layout.folder(&dest_folder, &layout_values(locale, category, media, album)),
+    }
+    (dest_folder, category)
+}
+
+fn parse_exif_date(date_str: &str) -> Option<chrono::NaiveDateTime> {
+    chrono::NaiveDateTime::parse_from_str(date_str, "%Y:%m:%d %H:%M:%S").ok()
+}
+
 fn print_progress(done: usize, total: usize) {
-    let percent = if total > 0 { (done * 100) / total } else { 100 };
+    let percent = (done * 100).checked_div(total).unwrap_or(100);
     let bar = format!("{}{}", "🟨".repeat(percent / 4), "⬜".repeat(25 - percent / 4));
     print!("\r📦 Sorting: [{}] {}% ({} / {})", bar, percent, done, total);
     let _ = io::stdout().flush();
@@ -212,16 +394,3 @@ fn print_progress(done: usize, total: usize) {
         println!();
     }
 }
-
-fn human_readable_size(size: u64) -> String {
-    const KB: u64 = 1024;
-    const MB: u64 = KB * 1024;
-    const GB: u64 = MB * 1024;
-    match size {
-        s if s >= GB => format!("{:.2} GB", s as f64 / GB as f64),
-        s if s >= MB => format!("{:.2} MB", s as f64 / MB as f64),
-        s if s >= KB => format!("{:.2} KB", s as f64 / KB as f64),
-        _ => format!("{} B", size),
-    }
-}
-
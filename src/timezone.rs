@@ -0,0 +1,216 @@
+// timezone.rs
+// Time zone resolution for MetaSort_v1.0.0 – Google Photos Takeout Organizer
+
+use std::fmt;
+use std::sync::OnceLock;
+use chrono::{DateTime, FixedOffset, Offset, TimeZone, Utc};
+use chrono_tz::Tz;
+use tzf_rs::DefaultFinder;
+use crate::error::{Error, Result};
+
+/// A time zone to read Takeout timestamps in: an IANA zone such as
+/// `Europe/Berlin`, or a fixed offset such as `+02:00`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Zone {
+    Named(Tz),
+    Fixed(FixedOffset),
+}
+
+impl Zone {
+    /// Parses an IANA zone name, `UTC`, or an offset like `+02:00`, `-0530` or `+9`.
+    pub fn parse(zone: &str) -> Result<Zone> {
+        let zone = zone.trim();
+        if let Some(offset) = parse_offset(zone) {
+            return Ok(Zone::Fixed(offset));
+        }
+        zone.parse::<Tz>().map(Zone::Named).map_err(|_| Error::TimeZone(zone.to_string()))
+    }
+
+    /// UTC offset of the zone at the instant `utc`, daylight saving time included.
+    pub fn offset_at(&self, utc: &DateTime<Utc>) -> FixedOffset {
+        match self {
+            Zone::Named(tz) => tz.offset_from_utc_datetime(&utc.naive_utc()).fix(),
+            Zone::Fixed(offset) => *offset,
+        }
+    }
+}
+
+impl fmt::Display for Zone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Zone::Named(tz) => write!(f, "{}", tz.name()),
+            Zone::Fixed(offset) => write!(f, "{}", format_offset(*offset)),
+        }
+    }
+}
+
+/// Where the UTC offset of a local date came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OffsetSource {
+    /// `OffsetTimeOriginal` or the QuickTime creation date already in the file.
+    File,
+    /// The time zone at the GPS position of the file.
+    Gps,
+    /// The zone given with `--timezone`.
+    Default,
+    /// Nothing else was known, so the date stays in UTC.
+    Utc,
+}
+
+/// A Takeout timestamp as the local time it was taken at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalTime {
+    /// Local date in EXIF format (`YYYY:MM:DD HH:MM:SS`).
+    pub date: String,
+    /// Offset from UTC in EXIF format (`+02:00`).
+    pub offset: String,
+    pub source: OffsetSource,
+}
+
+/// Converts a Takeout timestamp (seconds since 1970, UTC) to local time. The
+/// offset comes from the file itself, else from the zone at `gps`, else from
+/// `default`; without any of them the date is kept in UTC.
+pub fn local_time(timestamp: i64, file_offset: Option<FixedOffset>, gps: Option<(f64, f64)>, default: Option<&Zone>) -> Option<LocalTime> {
+    let utc = Utc.timestamp_opt(timestamp, 0).single()?;
+    let (offset, source) = if let Some(offset) = file_offset {
+        (offset, OffsetSource::File)
+    } else if let Some(zone) = gps.and_then(|(lat, lon)| zone_at(lat, lon)) {
+        (zone.offset_at(&utc), OffsetSource::Gps)
+    } else if let Some(zone) = default {
+        (zone.offset_at(&utc), OffsetSource::Default)
+    } else {
+        (FixedOffset::east_opt(0)?, OffsetSource::Utc)
+    };
+    Some(LocalTime {
+        date: utc.with_timezone(&offset).format("%Y:%m:%d %H:%M:%S").to_string(),
+        offset: format_offset(offset),
+        source,
+    })
+}
+
+/// Time zone boundary polygons, loaded on first use.
+static BOUNDARIES: OnceLock<DefaultFinder> = OnceLock::new();
+
+/// Time zone at a GPS position, from the timezone-boundary-builder polygons
+/// bundled with `tzf-rs`. Out at sea that is the nautical zone of the
+/// longitude (`Etc/GMT-9` and so on). `(0, 0)`, which Takeout writes for
+/// photos without a location, has no zone.
+pub fn zone_at(lat: f64, lon: f64) -> Option<Zone> {
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) || (lat == 0.0 && lon == 0.0) {
+        return None;
+    }
+    let name = BOUNDARIES.get_or_init(DefaultFinder::new).get_tz_name(lon, lat);
+    match name.parse::<Tz>() {
+        Ok(tz) => Some(Zone::Named(tz)),
+        // A zone newer than chrono-tz's copy of the tz database, or no polygon at all
+        Err(_) => FixedOffset::east_opt((lon / 15.0).round() as i32 * 3600).map(Zone::Fixed),
+    }
+}
+
+/// An offset such as `+02:00`, `-0530`, `+9` or `Z`.
+pub fn parse_offset(value: &str) -> Option<FixedOffset> {
+    let value = value.trim();
+    if value == "Z" {
+        return FixedOffset::east_opt(0);
+    }
+    let sign = match value.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    let digits = value[1..].replace(':', "");
+    if digits.is_empty() || digits.len() > 4 || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let (hours, minutes) = if digits.len() > 2 { digits.split_at(digits.len() - 2) } else { (digits.as_str(), "0") };
+    let (hours, minutes): (i32, i32) = (hours.parse().ok()?, minutes.parse().ok()?);
+    if hours > 14 || minutes >= 60 {
+        return None;
+    }
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+/// An offset in EXIF format, e.g. `+02:00`.
+pub fn format_offset(offset: FixedOffset) -> String {
+    let seconds = offset.local_minus_utc();
+    let sign = if seconds < 0 { '-' } else { '+' };
+    let minutes = seconds.abs() / 60;
+    format!("{}{:02}:{:02}", sign, minutes / 60, minutes % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zone_name(lat: f64, lon: f64) -> String {
+        zone_at(lat, lon).map(|zone| zone.to_string()).unwrap_or_default()
+    }
+
+    #[test]
+    fn border_towns_get_their_own_zone() {
+        // Pairs a few kilometres apart on both sides of a border
+        assert_eq!(zone_name(48.5734, 7.7521), "Europe/Paris"); // Strasbourg
+        assert_eq!(zone_name(48.5723, 7.8156), "Europe/Berlin"); // Kehl
+        assert_eq!(zone_name(-4.2520, -69.9380), "America/Manaus"); // Tabatinga
+        assert_eq!(zone_name(-4.2153, -69.9406), "America/Bogota"); // Leticia
+        assert_eq!(zone_name(32.5560, -117.0440), "America/Los_Angeles"); // San Ysidro
+        assert_eq!(zone_name(32.5149, -117.0382), "America/Tijuana"); // Tijuana
+        assert_eq!(zone_name(31.7619, -106.4850), "America/Denver"); // El Paso
+        assert_eq!(zone_name(31.6904, -106.4245), "America/Ciudad_Juarez"); // Ciudad Juárez
+    }
+
+    #[test]
+    fn positions_at_sea_get_the_nautical_zone() {
+        let zone = zone_at(-30.0, -140.0).expect("zone at sea");
+        let offset = zone.offset_at(&Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap());
+        assert_eq!(format_offset(offset), "-09:00");
+    }
+
+    #[test]
+    fn positions_without_a_location_have_no_zone() {
+        assert_eq!(zone_at(0.0, 0.0), None);
+        assert_eq!(zone_at(91.0, 10.0), None);
+        assert_eq!(zone_at(10.0, 181.0), None);
+    }
+
+    #[test]
+    fn gps_offsets_follow_daylight_saving_time() {
+        // Berlin switched to summer time at 2021-03-28 01:00 UTC
+        let berlin = Some((52.52, 13.405));
+        let before = Utc.with_ymd_and_hms(2021, 3, 28, 0, 59, 59).unwrap().timestamp();
+        let time = local_time(before, None, berlin, None).unwrap();
+        assert_eq!((time.date.as_str(), time.offset.as_str()), ("2021:03:28 01:59:59", "+01:00"));
+        let time = local_time(before + 1, None, berlin, None).unwrap();
+        assert_eq!((time.date.as_str(), time.offset.as_str()), ("2021:03:28 03:00:00", "+02:00"));
+        assert_eq!(time.source, OffsetSource::Gps);
+    }
+
+    #[test]
+    fn file_offset_then_gps_then_default_zone() {
+        let timestamp = Utc.with_ymd_and_hms(2021, 7, 1, 12, 0, 0).unwrap().timestamp();
+        let file = parse_offset("-03:00");
+        let tokyo = Some((35.6762, 139.6503));
+        let default = Zone::parse("America/New_York").unwrap();
+
+        let time = local_time(timestamp, file, tokyo, Some(&default)).unwrap();
+        assert_eq!((time.date.as_str(), time.offset.as_str(), time.source), ("2021:07:01 09:00:00", "-03:00", OffsetSource::File));
+        let time = local_time(timestamp, None, tokyo, Some(&default)).unwrap();
+        assert_eq!((time.date.as_str(), time.offset.as_str(), time.source), ("2021:07:01 21:00:00", "+09:00", OffsetSource::Gps));
+        let time = local_time(timestamp, None, Some((0.0, 0.0)), Some(&default)).unwrap();
+        assert_eq!((time.date.as_str(), time.offset.as_str(), time.source), ("2021:07:01 08:00:00", "-04:00", OffsetSource::Default));
+        let time = local_time(timestamp, None, None, None).unwrap();
+        assert_eq!((time.date.as_str(), time.offset.as_str(), time.source), ("2021:07:01 12:00:00", "+00:00", OffsetSource::Utc));
+    }
+
+    #[test]
+    fn zones_and_offsets_parse() {
+        assert_eq!(Zone::parse("Europe/Berlin").unwrap().to_string(), "Europe/Berlin");
+        assert_eq!(Zone::parse(" +02:00 ").unwrap().to_string(), "+02:00");
+        assert!(Zone::parse("Mars/Olympus").is_err());
+        assert_eq!(parse_offset("-0530").map(format_offset).as_deref(), Some("-05:30"));
+        assert_eq!(parse_offset("+9").map(format_offset).as_deref(), Some("+09:00"));
+        assert_eq!(parse_offset("Z").map(format_offset).as_deref(), Some("+00:00"));
+        assert_eq!(parse_offset("+15:00"), None);
+        assert_eq!(parse_offset("02:00"), None);
+    }
+}
@@ -0,0 +1,81 @@
+// transfer.rs
+// File transfer strategies for MetaSort_v1.0.0 – Google Photos Takeout Organizer
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+
+/// How input files are brought into the working folder.
+///
+/// Both the built-in writer and exiftool's `-overwrite_original` write a new
+/// file and rename it over the old one, so a hardlinked or reflinked working
+/// file is detached from the original before it is changed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TransferMode {
+    /// Copy every file (originals untouched, needs the most space)
+    #[default]
+    Copy,
+    /// Move files out of the input (originals are consumed, needs no extra space)
+    Move,
+    /// Hardlink files, falling back to a copy across filesystems
+    Hardlink,
+    /// Copy-on-write clone (Btrfs, XFS, APFS, ReFS), falling back to a copy
+    Reflink,
+}
+
+impl TransferMode {
+    /// How many times the input size a run needs in free space, roughly.
+    /// Linked and cloned files still take space once exiftool rewrites them.
+    pub fn space_factor(self) -> u64 {
+        match self {
+            TransferMode::Copy => 2,
+            TransferMode::Hardlink | TransferMode::Reflink => 1,
+            TransferMode::Move => 0,
+        }
+    }
+}
+
+/// Transfers `src` to `dest` with `mode`, replacing `dest` if it exists.
+pub fn transfer_file(src: &Path, dest: &Path, mode: TransferMode) -> io::Result<()> {
+    match mode {
+        TransferMode::Copy => fs::copy(src, dest).map(|_| ()),
+        TransferMode::Move => {
+            // rename only works within one filesystem
+            if fs::rename(src, dest).is_err() {
+                fs::copy(src, dest)?;
+                fs::remove_file(src)?;
+            }
+            Ok(())
+        }
+        TransferMode::Hardlink => {
+            let _ = fs::remove_file(dest);
+            if fs::hard_link(src, dest).is_err() {
+                fs::copy(src, dest)?;
+            }
+            Ok(())
+        }
+        TransferMode::Reflink => {
+            let _ = fs::remove_file(dest);
+            reflink_copy::reflink_or_copy(src, dest).map(|_| ())
+        }
+    }
+}
+
+/// Takes a working file out of the run. Under `--transfer move` the working
+/// file is the only copy left, so `keep_in` is set and the file is moved there,
+/// below its path relative to `working_dir`; otherwise it is deleted, as the
+/// input still has it. Returns where the file was moved.
+pub fn leave_out(path: &Path, working_dir: &Path, keep_in: Option<&Path>) -> io::Result<Option<PathBuf>> {
+    let Some(keep_in) = keep_in else {
+        return fs::remove_file(path).map(|_| None);
+    };
+    let relative = path.strip_prefix(working_dir).ok().or_else(|| path.file_name().map(Path::new)).unwrap_or(path);
+    let dest = keep_in.join(relative);
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    transfer_file(path, &dest, TransferMode::Move)?;
+    Ok(Some(dest))
+}
@@ -8,6 +8,12 @@ pub struct MetaSortUI {
     main_progress: Option<ProgressBar>,
 }
 
+impl Default for MetaSortUI {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl MetaSortUI {
     pub fn new() -> Self {
         Self {
@@ -70,6 +76,19 @@ impl MetaSortUI {
         self.main_progress = Some(pb);
     }
 
+    pub fn inc_progress(&self, delta: u64) {
+        if let Some(pb) = &self.main_progress {
+            pb.inc(delta);
+        }
+    }
+
+    /// Grows the progress bar for work whose size is only known while it runs.
+    pub fn inc_length(&self, delta: u64) {
+        if let Some(pb) = &self.main_progress {
+            pb.inc_length(delta);
+        }
+    }
+
     pub fn finish_progress(&mut self, message: &str) {
         if let Some(pb) = &self.main_progress {
             pb.finish_with_message(message.to_string());
@@ -77,6 +96,7 @@ impl MetaSortUI {
         self.main_progress = None;
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn print_summary(
         photos: usize,
         videos: usize,
@@ -84,10 +104,11 @@ impl MetaSortUI {
         screenshots: usize,
         unknown: usize,
         mkv: usize,
+        separated: usize,
         errors: usize,
         output_path: &str,
     ) {
-        let total = photos + videos + whatsapp + screenshots + unknown + mkv;
+        let total = photos + videos + whatsapp + screenshots + unknown + mkv + separated;
         
         println!("\n━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
         println!("                           MetaSort Summary");
@@ -98,6 +119,9 @@ impl MetaSortUI {
         println!("  📱 Screenshots:          {}", screenshots);
         println!("  ❓ Unknown time:         {}", unknown);
         println!("  🎬 MKV files:            {}", mkv);
+        if separated > 0 {
+            println!("  🗑️  Trash/Locked/Archive: {}", separated);
+        }
         println!("  📊 Total files:          {}", total);
         println!("  ⚠️  Errors encountered:   {}", errors);
         println!("  📁 Output location:      {}", output_path);
@@ -5,6 +5,8 @@ use std::fs::{OpenOptions, create_dir_all};
 use std::io::Write;
 use std::path::Path;
 use chrono::Local;
+use rayon::prelude::*;
+use walkdir::WalkDir;
 
 /// Appends a timestamped log entry to a log file in the logs folder inside the given directory.
 pub fn log_to_file(log_dir: &Path, log_name: &str, message: &str) {
@@ -17,4 +19,54 @@ pub fn log_to_file(log_dir: &Path, log_name: &str, message: &str) {
         .expect("Unable to open log file");
     let now = Local::now().format("[%Y-%m-%d %H:%M:%S]");
     let _ = writeln!(file, "{} {}", now, message);
-} 
\ No newline at end of file
+}
+
+/// Counts the files below a folder, recursively.
+pub fn count_files(dir: &Path) -> usize {
+    WalkDir::new(dir)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|e| e.path().is_file())
+        .count()
+}
+
+/// Total size in bytes of all files below a folder, recursively.
+pub fn folder_size(dir: &Path) -> u64 {
+    WalkDir::new(dir)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|e| e.path().is_file())
+        .map(|e| e.metadata().map(|m| m.len()).unwrap_or(0))
+        .sum()
+}
+
+/// Formats a byte count as B/KB/MB/GB.
+pub fn human_readable_size(size: u64) -> String {
+    const KB: u64 = 1024;
+    const MB: u64 = KB * 1024;
+    const GB: u64 = MB * 1024;
+    match size {
+        s if s >= GB => format!("{:.2} GB", s as f64 / GB as f64),
+        s if s >= MB => format!("{:.2} MB", s as f64 / MB as f64),
+        s if s >= KB => format!("{:.2} KB", s as f64 / KB as f64),
+        _ => format!("{} B", size),
+    }
+}
+
+/// Runs `work` on every item in parallel on the current worker pool and hands
+/// each result to `finish` on the calling thread, in input order. Items go
+/// through in small batches, so logs, journal records and progress come out
+/// in the same order as a sequential run and a crash loses at most one batch.
+pub fn for_each_ordered<T, R>(items: &[T], work: impl Fn(&T) -> R + Sync, mut finish: impl FnMut(&T, R))
+where
+    T: Sync,
+    R: Send,
+{
+    let batch = rayon::current_num_threads() * 4;
+    for chunk in items.chunks(batch.max(1)) {
+        let results: Vec<R> = chunk.par_iter().map(&work).collect();
+        for (item, result) in chunk.iter().zip(results) {
+            finish(item, result);
+        }
+    }
+}